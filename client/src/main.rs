@@ -1,20 +1,74 @@
 use std::error::Error;
+use minase::db_core::csv::CsvOptions;
+use minase::db_core::query::Query;
 
+// client import FILE [TABLE] [options] reads a csv file on this machine into a table, a new one without TABLE.
+// client export TABLE FILE [options] writes a table to a csv file on this machine.
+// options: --delimiter C, --quote C, --null TEXT, --no-header, --max-errors N
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>>{
     let mut minase =
         minase_driver::Minase::connect("127.0.0.1:8080").await?;
 
-    minase.query(
-        minase::db_core::query::Query::SelectTable {
-            table: 0,
+    let (args, options) = csv_options(std::env::args().skip(1))?;
+
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["import", file, table @ ..] if table.len() <= 1 => {
+            let table = table.first().map(|table| table.parse()).transpose()?;
+            let import = minase.import_csv(file, table, options).await?;
+
+            println!("imported {} rows into table {}", import.rows, import.table);
+            for error in import.errors {
+                println!("skipped line {}: {}", error.line, error.message);
+            }
         }
-    ).await?;
-    let _ = minase.receive_table().await;
+        ["export", table, file] => {
+            let rows = minase.export_csv(Query::SelectTable { table: table.parse()? }, file, options).await?;
+            println!("exported {} rows to {}", rows, file);
+        }
+        [] => {
+            minase.query(
+                Query::SelectTable {
+                    table: 0,
+                }
+            ).await?;
+            let _ = minase.receive_table().await;
+        }
+        _ => return Err("usage: client [import FILE [TABLE] | export TABLE FILE] [options]".into()),
+    }
 
     minase.query(
-        minase::db_core::query::Query::Exit
+        Query::Exit
     ).await?;
 
     Ok(())
 }
+
+// the options of a csv command, and the arguments left once they are taken out
+fn csv_options(mut args: impl Iterator<Item = String>) -> Result<(Vec<String>, CsvOptions), Box<dyn Error>> {
+    let mut options = CsvOptions::default();
+    let mut rest = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+
+        match arg.as_str() {
+            "--delimiter" => options.delimiter = single_char(&value()?)?,
+            "--quote" => options.quote = single_char(&value()?)?,
+            "--null" => options.null = Some(value()?),
+            "--max-errors" => options.max_errors = value()?.parse()?,
+            "--no-header" => options.header = false,
+            _ => rest.push(arg),
+        }
+    }
+
+    Ok((rest, options))
+}
+
+fn single_char(value: &str) -> Result<char, Box<dyn Error>> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(format!("expected a single character, found {:?}", value).into()),
+    }
+}
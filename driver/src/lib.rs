@@ -1,20 +1,152 @@
 use rmp_serde::{Deserializer, Serializer};
 use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
-use minase::db_core::database::Table;
-use minase::db_core::query::Query;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use minase::db_core::arrow::{ArrowExport, ArrowFormat, ArrowImport, ArrowSource};
+#[cfg(feature = "arrow")]
+use minase::db_core::arrow::RecordBatch;
+use minase::db_core::auth::Password;
+use minase::db_core::csv::{CsvExport, CsvImport, CsvOptions, CsvSource};
+use minase::db_core::database::{MutationResult, Table};
+use minase::db_core::query::{CancelKey, Query};
 use minase::db_core::query_error::QueryError;
+use minase::db_core::values::Types;
+
+// addresses starting with it are paths of a unix socket rather than `host:port`
+const UNIX_PREFIX: &str = "unix:";
+
+// a connection to the server, plain or encrypted
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
 
 pub struct Minase {
-    socket: TcpStream
+    socket: Box<dyn Stream>,
+    addr: String,
+    transport: Transport,
+}
+
+// files are pem encoded. without a ca the server's certificate has to be signed by one of the
+// webpki roots, and without a server name it has to be issued for the host of the address
+#[derive(Clone, Debug, Default)]
+pub struct TlsConfig {
+    pub ca_cert: Option<PathBuf>,
+    // sent to servers that require client certificates, both or neither have to be given
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    pub server_name: Option<String>,
+}
+
+// how connections to the server are made, kept so a cancel handle connects the same way
+#[derive(Clone)]
+enum Transport {
+    Tcp,
+    Tls(TlsConnector, ServerName<'static>),
+}
+
+impl std::fmt::Debug for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transport::Tcp => write!(f, "Tcp"),
+            Transport::Tls(_, server_name) => write!(f, "Tls({:?})", server_name),
+        }
+    }
+}
+
+impl Transport {
+    fn tls(addr: &str, config: &TlsConfig) -> Result<Self, std::io::Error> {
+        let invalid = |err: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, err);
+
+        if addr.starts_with(UNIX_PREFIX) {
+            return Err(invalid("unix sockets are served without tls".to_string()));
+        }
+
+        let mut roots = RootCertStore::empty();
+        match &config.ca_cert {
+            Some(path) => {
+                for cert in CertificateDer::pem_file_iter(path)
+                    .map_err(|err| invalid(format!("failed to read ca {}: {}", path.display(), err)))? {
+                    let cert = cert.map_err(|err| invalid(format!("invalid ca {}: {}", path.display(), err)))?;
+                    roots.add(cert).map_err(|err| invalid(format!("invalid ca {}: {}", path.display(), err)))?;
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+
+        let builder = ClientConfig::builder().with_root_certificates(roots);
+        let client_config = match (&config.client_cert, &config.client_key) {
+            (Some(cert_path), Some(key_path)) => {
+                let certs = CertificateDer::pem_file_iter(cert_path)
+                    .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                    .map_err(|err| invalid(format!("invalid client certificate {}: {}", cert_path.display(), err)))?;
+                let key = PrivateKeyDer::from_pem_file(key_path)
+                    .map_err(|err| invalid(format!("invalid client key {}: {}", key_path.display(), err)))?;
+
+                builder.with_client_auth_cert(certs, key)
+                    .map_err(|err| invalid(format!("invalid client certificate: {}", err)))?
+            }
+            (None, None) => builder.with_no_client_auth(),
+            _ => return Err(invalid("a client certificate needs both a certificate and a key".to_string())),
+        };
+
+        // the host of `host:port`, without the brackets of an ipv6 address
+        let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+        let server_name = config.server_name.clone()
+            .unwrap_or_else(|| host.trim_start_matches('[').trim_end_matches(']').to_string());
+        let server_name = ServerName::try_from(server_name)
+            .map_err(|err| invalid(format!("invalid server name: {}", err)))?;
+
+        Ok(Transport::Tls(TlsConnector::from(Arc::new(client_config)), server_name))
+    }
+
+    async fn connect(&self, addr: &str) -> Result<Box<dyn Stream>, std::io::Error> {
+        #[cfg(unix)]
+        if let Some(path) = addr.strip_prefix(UNIX_PREFIX) {
+            return Ok(Box::new(tokio::net::UnixStream::connect(path).await?));
+        }
+
+        let socket = TcpStream::connect(addr).await?;
+
+        Ok(match self {
+            Transport::Tcp => Box::new(socket),
+            Transport::Tls(connector, server_name) => Box::new(connector.connect(server_name.clone(), socket).await?),
+        })
+    }
+}
+
+// cancels the query running on the connection it was taken from. it opens a connection of its own,
+// so it can be used from another task while the first one waits for the result
+#[derive(Clone, Debug)]
+pub struct CancelHandle {
+    addr: String,
+    transport: Transport,
+    key: CancelKey,
 }
 
 impl Minase {
+    // `host:port`, or `unix:/path` for the unix socket of a server on the same host
     pub async fn connect(addr: &str) -> Result<Self, std::io::Error> {
-        let socket = TcpStream::connect(addr).await?;
+        Self::connect_with(addr, Transport::Tcp).await
+    }
+
+    // for servers with tls turned on
+    pub async fn connect_tls(addr: &str, config: &TlsConfig) -> Result<Self, std::io::Error> {
+        Self::connect_with(addr, Transport::tls(addr, config)?).await
+    }
+
+    async fn connect_with(addr: &str, transport: Transport) -> Result<Self, std::io::Error> {
+        let socket = transport.connect(addr).await?;
         Ok(Self {
-            socket
+            socket,
+            addr: addr.to_string(),
+            transport,
         })
     }
 
@@ -26,22 +158,158 @@ impl Minase {
             )
         ).unwrap();
 
-
-        println!("{}", buffer.len());
-
         let size = (buffer.len() as u32).to_be_bytes();
         self.socket.write_all(&size[..]).await?;
         self.socket.write_all(&buffer[..]).await?;
-        println!("connection made");
         Ok(())
     }
 
-    pub async fn receive_table(&mut self) -> Result<Table, QueryError> {
+    // a connection that breaks, for instance because the server dropped a query over its frame limit,
+    // fails the query with a file error instead of a panic
+    async fn receive<T: DeserializeOwned>(&mut self) -> Result<T, QueryError> {
         let mut size_buffer = [0; 4];
-        self.socket.read_exact(&mut size_buffer).await.unwrap();
+        self.socket.read_exact(&mut size_buffer).await.map_err(connection_error)?;
         let size = u32::from_be_bytes(size_buffer);
         let mut buffer = vec![0; size as usize];
-        self.socket.read_exact(&mut buffer).await.unwrap();
-        Result::deserialize(&mut Deserializer::new(&buffer[..])).unwrap()
+        self.socket.read_exact(&mut buffer).await.map_err(connection_error)?;
+        Result::deserialize(&mut Deserializer::new(&buffer[..])).map_err(connection_error)?
+    }
+
+    // sends `query` and reads its result
+    async fn request<T: DeserializeOwned>(&mut self, query: Query) -> Result<T, QueryError> {
+        self.query(query).await.map_err(connection_error)?;
+        self.receive().await
+    }
+
+    pub async fn receive_table(&mut self) -> Result<Table, QueryError> {
+        self.receive().await
+    }
+
+    pub async fn receive_mutation(&mut self) -> Result<MutationResult, QueryError> {
+        self.receive().await
     }
-}
\ No newline at end of file
+
+    // for the results of queries that only succeed or fail, such as insert or commit
+    pub async fn receive_status(&mut self) -> Result<(), QueryError> {
+        self.receive().await
+    }
+
+    // for the results of add table queries, the position of the new table
+    pub async fn receive_created(&mut self) -> Result<usize, QueryError> {
+        self.receive().await
+    }
+
+    // adds a table with columns of `columns`, returning its position
+    pub async fn add_table(&mut self, columns: Vec<Types>) -> Result<usize, QueryError> {
+        self.request(Query::AddTable { columns }).await
+    }
+
+    // has to come first on a server that requires authentication
+    pub async fn login(&mut self, user: &str, password: &str) -> Result<(), QueryError> {
+        self.request(Query::Login {
+            user: user.to_string(),
+            password: Password(password.to_string()),
+        }).await
+    }
+
+    // for the results of import csv queries
+    pub async fn receive_import(&mut self) -> Result<CsvImport, QueryError> {
+        self.receive().await
+    }
+
+    // for the results of export csv queries
+    pub async fn receive_export(&mut self) -> Result<CsvExport, QueryError> {
+        self.receive().await
+    }
+
+    // reads a csv file on this machine into the table at `table`, or into a new table when there is none
+    pub async fn import_csv(&mut self, path: impl AsRef<Path>, table: Option<usize>, options: CsvOptions) -> Result<CsvImport, QueryError> {
+        let data = tokio::fs::read_to_string(path).await
+            .map_err(|err| QueryError::FileError(err.to_string()))?;
+
+        self.request(Query::ImportCsv {
+            source: CsvSource::Data(data),
+            table,
+            options,
+        }).await
+    }
+
+    // writes the result of `query` to a csv file on this machine, returning how many rows it has
+    pub async fn export_csv(&mut self, query: Query, path: impl AsRef<Path>, options: CsvOptions) -> Result<usize, QueryError> {
+        let export: CsvExport = self.request(Query::ExportCsv {
+            query: Box::new(query),
+            path: None,
+            options,
+        }).await?;
+        tokio::fs::write(path, export.data.unwrap_or_default()).await
+            .map_err(|err| QueryError::FileError(err.to_string()))?;
+
+        Ok(export.rows)
+    }
+
+    pub async fn receive_arrow_import(&mut self) -> Result<ArrowImport, QueryError> {
+        self.receive().await
+    }
+
+    pub async fn receive_arrow_export(&mut self) -> Result<ArrowExport, QueryError> {
+        self.receive().await
+    }
+
+    // a table sent back by a query as an arrow record batch, ready to hand to analytics libraries
+    #[cfg(feature = "arrow")]
+    pub async fn receive_record_batch(&mut self) -> Result<RecordBatch, QueryError> {
+        let table = self.receive_table().await?;
+        minase::db_core::arrow::to_record_batch(&table)
+    }
+
+    // reads an arrow ipc or parquet file on this machine into the table at `table`, or into a new table
+    // when there is none
+    pub async fn import_arrow(&mut self, path: impl AsRef<Path>, format: ArrowFormat, table: Option<usize>) -> Result<ArrowImport, QueryError> {
+        let data = tokio::fs::read(path).await
+            .map_err(|err| QueryError::FileError(err.to_string()))?;
+
+        self.request(Query::ImportArrow {
+            source: ArrowSource::Data(data),
+            format,
+            table,
+        }).await
+    }
+
+    // writes the result of `query` to an arrow ipc or parquet file on this machine, returning how many rows it has
+    pub async fn export_arrow(&mut self, query: Query, path: impl AsRef<Path>, format: ArrowFormat) -> Result<usize, QueryError> {
+        let export: ArrowExport = self.request(Query::ExportArrow {
+            query: Box::new(query),
+            path: None,
+            format,
+        }).await?;
+        tokio::fs::write(path, export.data.unwrap_or_default()).await
+            .map_err(|err| QueryError::FileError(err.to_string()))?;
+
+        Ok(export.rows)
+    }
+
+    pub async fn cancel_handle(&mut self) -> Result<CancelHandle, std::io::Error> {
+        self.query(Query::CancelKey).await?;
+        let key = self.receive().await.map_err(std::io::Error::other)?;
+
+        Ok(CancelHandle {
+            addr: self.addr.clone(),
+            transport: self.transport.clone(),
+            key,
+        })
+    }
+}
+
+impl CancelHandle {
+    pub async fn cancel(&self) -> Result<(), std::io::Error> {
+        let mut connection = Minase::connect_with(&self.addr, self.transport.clone()).await?;
+
+        connection.query(Query::Cancel { key: self.key }).await?;
+        let _ = connection.receive_status().await;
+        connection.query(Query::Exit).await
+    }
+}
+
+fn connection_error(err: impl std::fmt::Display) -> QueryError {
+    QueryError::FileError(format!("connection to the server failed: {}", err))
+}
@@ -1,46 +1,369 @@
-use tokio::fs::File;
-use tokio::io::{AsyncWriteExt, BufWriter};
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::{Mutex, oneshot};
+use tokio::task::JoinHandle;
 
-#[derive(Debug)]
+mod rotation;
+mod sink;
+mod writer;
+
+pub use rotation::{LogFile, RotationConfig};
+pub use sink::{FileSink, LogSink, MemorySink, NoopSink, StderrSink};
+pub use writer::OverflowPolicy;
+use writer::{Message, Queue};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    fn from_u8(level: u8) -> Level {
+        match level {
+            0 => Level::Debug,
+            1 => Level::Info,
+            2 => Level::Warn,
+            _ => Level::Error,
+        }
+    }
+}
+
+impl std::str::FromStr for Level {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level.to_ascii_lowercase().as_str() {
+            "debug" => Ok(Level::Debug),
+            "info" => Ok(Level::Info),
+            "warn" => Ok(Level::Warn),
+            "error" => Ok(Level::Error),
+            _ => Err(format!("unknown log level `{}`, expected debug, info, warn or error", level)),
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Level::Debug => write!(f, "DEBUG"),
+            Level::Info => write!(f, "INFO"),
+            Level::Warn => write!(f, "WARN"),
+            Level::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Format {
+    // `<timestamp> <level> <target> [session=<id>] [query=<id>] <title>: <description> [<key>=<value> ...]`
+    Text,
+    // one json object per line with the same fields as the text format
+    Json,
+}
+
+// `path`, `format` and `rotation` only apply to the file sink opened by `Logger::open`
+#[derive(Clone, Debug)]
+pub struct LoggerConfig {
+    pub path: PathBuf,
+    pub format: Format,
+    pub level: Level,
+    pub target: String,
+    pub rotation: RotationConfig,
+    // records waiting for the writer task before the overflow policy applies
+    pub queue_capacity: usize,
+    pub overflow: OverflowPolicy,
+    // how often the writer task flushes the file, so a crash loses at most this much
+    pub flush_interval: Duration,
+}
+
+impl Default for LoggerConfig {
+    fn default() -> Self {
+        LoggerConfig {
+            path: PathBuf::from("minase.log"),
+            format: Format::Text,
+            level: Level::Debug,
+            target: "minase".to_string(),
+            rotation: RotationConfig::default(),
+            queue_capacity: 4096,
+            overflow: OverflowPolicy::Block,
+            flush_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Record {
+    pub timestamp: String,
+    pub level: Level,
+    pub target: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<u64>,
+    pub title: String,
+    pub description: String,
+    #[serde(skip_serializing_if = "Vec::is_empty", serialize_with = "fields_as_map")]
+    pub fields: Vec<(String, String)>,
+}
+
+fn fields_as_map<S: serde::Serializer>(fields: &[(String, String)], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(fields.iter().map(|(key, value)| (key, value)))
+}
+
+impl Record {
+    pub fn now(level: Level, target: String, title: String, description: String) -> Self {
+        Record {
+            timestamp: timestamp(SystemTime::now()),
+            level,
+            target,
+            session: None,
+            query: None,
+            title,
+            description,
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn to_line(&self, format: Format) -> String {
+        match format {
+            Format::Text => {
+                let mut line = format!("{} {} {}", self.timestamp, self.level, self.target);
+
+                if let Some(session) = self.session {
+                    line.push_str(&format!(" session={}", session));
+                }
+                if let Some(query) = self.query {
+                    line.push_str(&format!(" query={}", query));
+                }
+
+                line.push_str(&format!(" {}: {}", self.title, self.description));
+
+                for (key, value) in &self.fields {
+                    line.push_str(&format!(" {}={:?}", key, value));
+                }
+
+                line.push('\n');
+                line
+            }
+            Format::Json => {
+                let mut line = serde_json::to_string(self).unwrap();
+                line.push('\n');
+                line
+            }
+        }
+    }
+}
+
+// formats a point in time as an RFC 3339 UTC timestamp with millisecond precision
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, day_seconds) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // civil date from days since 1970-01-01, after Howard Hinnant's algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        day_seconds / 3600,
+        day_seconds % 3600 / 60,
+        day_seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+// a handle on a shared log sink. records are handed to a writer task over a bounded queue,
+// so logging never waits on file I/O unless the queue is full and the policy is to block.
+// clones share the queue and the minimum level, so changing the level through one of them
+// changes it for all of them
+#[derive(Clone, Debug)]
 pub struct Logger {
-    writer: BufWriter<File>,
+    queue: Arc<Queue>,
+    writer: Arc<Mutex<Option<JoinHandle<()>>>>,
+    level: Arc<AtomicU8>,
+    target: String,
+    session: Option<u64>,
+    query: Option<u64>,
 }
 
 impl Logger {
     pub async fn new() -> Self {
-        let file = File::options()
-            .create(true)
-            .append(true)
-            .open("minase.log")
-            .await.
-            unwrap();
+        Logger::open(LoggerConfig::default()).await.unwrap()
+    }
+
+    // a logger writing to the file at `config.path`
+    pub async fn open(config: LoggerConfig) -> Result<Self, std::io::Error> {
+        let file = LogFile::open(config.path.clone(), config.rotation.clone()).await?;
+
+        Ok(Logger::with_sink(FileSink::new(file, config.format), &config))
+    }
+
+    // a logger writing to any sink, the file settings of `config` are ignored.
+    // has to be called from within a tokio runtime, which runs the writer task
+    pub fn with_sink<S: LogSink + 'static>(sink: S, config: &LoggerConfig) -> Self {
+        let queue = Arc::new(Queue::new(config.queue_capacity, config.overflow));
+        let writer = tokio::spawn(writer::run(queue.clone(), Box::new(sink), config.flush_interval));
+
+        Logger {
+            queue,
+            writer: Arc::new(Mutex::new(Some(writer))),
+            level: Arc::new(AtomicU8::new(config.level as u8)),
+            target: config.target.clone(),
+            session: None,
+            query: None,
+        }
+    }
+
+    pub fn stderr(format: Format) -> Self {
+        Logger::with_sink(StderrSink::new(format), &LoggerConfig::default())
+    }
 
-        let writer = BufWriter::new(file);
-        Logger { writer }
+    // a logger that keeps its records in memory, along with the sink to read them back from
+    pub fn memory() -> (Self, MemorySink) {
+        let sink = MemorySink::new();
+        (Logger::with_sink(sink.clone(), &LoggerConfig::default()), sink)
     }
 
-    async fn write(&mut self, msg_type: String, title: String, description: String) {
-        let message = format!("{} {}: {}\n", msg_type, title, description);
-        self.writer.write_all(message.as_bytes()).await.unwrap();
+    pub fn noop() -> Self {
+        Logger::with_sink(NoopSink, &LoggerConfig::default())
+    }
+
+    // a logger on the same file whose records carry the given session id
+    pub fn for_session(&self, session: u64) -> Logger {
+        let mut logger = self.clone();
+        logger.session = Some(session);
+        logger.query = None;
+        logger
+    }
+
+    // a logger on the same file whose records carry the given target
+    pub fn for_target(&self, target: &str) -> Logger {
+        let mut logger = self.clone();
+        logger.target = target.to_string();
+        logger
+    }
+
+    // the query id attached to the following records, until it is changed again
+    pub fn set_query(&mut self, query: Option<u64>) {
+        self.query = query;
+    }
+
+    pub fn level(&self) -> Level {
+        Level::from_u8(self.level.load(Ordering::Relaxed))
+    }
+
+    pub fn set_level(&self, level: Level) {
+        self.level.store(level as u8, Ordering::Relaxed);
+    }
+
+    pub fn enabled(&self, level: Level) -> bool {
+        level >= self.level()
+    }
+
+    pub async fn log(&mut self, level: Level, title: String, description: String, fields: Vec<(String, String)>) {
+        if !self.enabled(level) {
+            return;
+        }
+
+        let mut record = Record::now(level, self.target.clone(), title, description);
+        record.session = self.session;
+        record.query = self.query;
+        record.fields = fields;
+
+        self.queue.send_record(record).await;
     }
 
     pub async fn error(&mut self, title: String, description: String){
-        self.write("ERROR!".to_string(), title, description).await;
+        self.log(Level::Error, title, description, Vec::new()).await;
     }
 
     pub async fn warn(&mut self, title: String, description: String){
-        self.write("WARNING!".to_string(), title, description).await;
+        self.log(Level::Warn, title, description, Vec::new()).await;
     }
 
     pub async fn info(&mut self, title: String, description: String){
-        self.write("INFO".to_string(), title, description).await;
+        self.log(Level::Info, title, description, Vec::new()).await;
     }
 
     pub async fn debug(&mut self, title: String, description: String){
-        self.write("DEBUG".to_string(), title, description).await;
+        self.log(Level::Debug, title, description, Vec::new()).await;
+    }
+
+    // records thrown away by the overflow policy since the logger was opened
+    pub fn dropped_records(&self) -> u64 {
+        self.queue.dropped()
+    }
+
+    async fn control(&self, message: fn(oneshot::Sender<Result<(), std::io::Error>>) -> Message) -> Result<(), std::io::Error> {
+        let (done, result) = oneshot::channel();
+        self.queue.send_control(message(done));
+
+        result.await.unwrap_or_else(|_| Err(std::io::Error::other("log writer has shut down")))
     }
 
+    // waits until every record logged so far is written and flushed
     pub async fn flush_buffer(&mut self) {
-        self.writer.flush().await.unwrap();
+        if let Err(err) = self.control(Message::Flush).await {
+            eprintln!("failed to flush log file: {}", err);
+        }
+    }
+
+    pub async fn reopen(&self) -> Result<(), std::io::Error> {
+        self.control(Message::Reopen).await
+    }
+
+    pub async fn rotate(&self) -> Result<(), std::io::Error> {
+        self.control(Message::Rotate).await
     }
-}
\ No newline at end of file
+
+    // writes and flushes every queued record and stops the writer task.
+    // records logged afterwards through any clone are lost, and flushes, reopens and rotations fail
+    pub async fn shutdown(&self) -> Result<(), std::io::Error> {
+        let res = self.control(Message::Shutdown).await;
+
+        if let Some(writer) = self.writer.lock().await.take() {
+            let _ = writer.await;
+        }
+
+        res
+    }
+
+    // reopens the log file every time the process receives SIGHUP, as external logrotate setups expect
+    #[cfg(unix)]
+    pub fn reopen_on_sighup(&self) -> Result<(), std::io::Error> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        let mut logger = self.clone();
+
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                match logger.reopen().await {
+                    Ok(()) => logger.info("Log Reopened".to_string(), "log file reopened on SIGHUP".to_string()).await,
+                    Err(err) => eprintln!("failed to reopen log file: {}", err),
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
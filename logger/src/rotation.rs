@@ -0,0 +1,169 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+#[derive(Clone, Debug)]
+pub struct RotationConfig {
+    // rotate once the file would grow past this many bytes
+    pub max_bytes: Option<u64>,
+    // rotate once the file has been written to for this long
+    pub max_age: Option<Duration>,
+    // how many rotated files are kept next to the live one, as `<path>.1` (the newest) to `<path>.<keep>`
+    pub keep: usize,
+    // gzip rotated files, they are then named `<path>.<n>.gz`
+    pub compress: bool,
+}
+
+impl Default for RotationConfig {
+    fn default() -> Self {
+        RotationConfig {
+            max_bytes: None,
+            max_age: None,
+            keep: 5,
+            compress: false,
+        }
+    }
+}
+
+impl RotationConfig {
+    fn enabled(&self) -> bool {
+        self.max_bytes.is_some() || self.max_age.is_some()
+    }
+}
+
+// the live log file, rotated and reopened in place
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    written: u64,
+    opened_at: SystemTime,
+    rotation: RotationConfig,
+}
+
+async fn open_append(path: &Path) -> Result<File, std::io::Error> {
+    File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
+
+fn rotated_path(path: &Path, index: usize, compress: bool) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    if compress {
+        name.push(".gz");
+    }
+
+    PathBuf::from(name)
+}
+
+fn compress_file(path: &Path) -> Result<(), std::io::Error> {
+    let input = std::fs::read(path)?;
+
+    let mut target = path.as_os_str().to_owned();
+    target.push(".gz");
+
+    let mut encoder = GzEncoder::new(std::fs::File::create(PathBuf::from(target))?, Compression::default());
+    encoder.write_all(&input)?;
+    encoder.finish()?;
+
+    std::fs::remove_file(path)
+}
+
+impl LogFile {
+    pub async fn open(path: PathBuf, rotation: RotationConfig) -> Result<Self, std::io::Error> {
+        let file = open_append(&path).await?;
+        let written = file.metadata().await?.len();
+
+        Ok(LogFile {
+            path,
+            writer: BufWriter::new(file),
+            written,
+            opened_at: SystemTime::now(),
+            rotation,
+        })
+    }
+
+    pub async fn write_line(&mut self, line: &str) -> Result<(), std::io::Error> {
+        if self.should_rotate(line.len() as u64) {
+            self.rotate().await?;
+        }
+
+        self.writer.write_all(line.as_bytes()).await?;
+        self.written += line.len() as u64;
+
+        Ok(())
+    }
+
+    pub async fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.writer.flush().await
+    }
+
+    // closes the file and opens `path` again, for when an external tool such as logrotate moved it away
+    pub async fn reopen(&mut self) -> Result<(), std::io::Error> {
+        self.writer.flush().await?;
+
+        let file = open_append(&self.path).await?;
+        self.written = file.metadata().await?.len();
+        self.writer = BufWriter::new(file);
+        self.opened_at = SystemTime::now();
+
+        Ok(())
+    }
+
+    fn should_rotate(&self, incoming: u64) -> bool {
+        if !self.rotation.enabled() || self.written == 0 {
+            return false;
+        }
+
+        let too_big = self.rotation.max_bytes
+            .map(|max_bytes| self.written + incoming > max_bytes)
+            .unwrap_or(false);
+        let too_old = self.rotation.max_age
+            .map(|max_age| self.opened_at.elapsed().unwrap_or_default() >= max_age)
+            .unwrap_or(false);
+
+        too_big || too_old
+    }
+
+    // moves `<path>.<n>` to `<path>.<n + 1>`, dropping the oldest, then the live file to `<path>.1`
+    pub async fn rotate(&mut self) -> Result<(), std::io::Error> {
+        self.writer.flush().await?;
+
+        let keep = self.rotation.keep;
+        let compress = self.rotation.compress;
+
+        if keep == 0 {
+            tokio::fs::remove_file(&self.path).await?;
+        } else {
+            let _ = tokio::fs::remove_file(rotated_path(&self.path, keep, compress)).await;
+
+            for index in (1..keep).rev() {
+                let from = rotated_path(&self.path, index, compress);
+                if tokio::fs::metadata(&from).await.is_ok() {
+                    tokio::fs::rename(&from, rotated_path(&self.path, index + 1, compress)).await?;
+                }
+            }
+
+            let rotated = rotated_path(&self.path, 1, false);
+            tokio::fs::rename(&self.path, &rotated).await?;
+
+            if compress {
+                tokio::task::spawn_blocking(move || compress_file(&rotated)).await??;
+            }
+        }
+
+        let file = open_append(&self.path).await?;
+        self.writer = BufWriter::new(file);
+        self.written = 0;
+        self.opened_at = SystemTime::now();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use crate::{Format, Record};
+use crate::rotation::LogFile;
+
+// where the writer task puts records. every method is only ever called from the writer task,
+// one at a time, so a sink does not have to synchronize on its own
+#[async_trait]
+pub trait LogSink: Send {
+    async fn write(&mut self, record: &Record) -> Result<(), std::io::Error>;
+
+    async fn flush(&mut self) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+
+    // reopen any file the sink writes to, after it was moved away by an external tool
+    async fn reopen(&mut self) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+}
+
+// appends records to a file, rotating it as configured
+#[derive(Debug)]
+pub struct FileSink {
+    file: LogFile,
+    format: Format,
+}
+
+impl FileSink {
+    pub fn new(file: LogFile, format: Format) -> Self {
+        FileSink {
+            file,
+            format,
+        }
+    }
+}
+
+#[async_trait]
+impl LogSink for FileSink {
+    async fn write(&mut self, record: &Record) -> Result<(), std::io::Error> {
+        self.file.write_line(&record.to_line(self.format)).await
+    }
+
+    async fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.file.flush().await
+    }
+
+    async fn reopen(&mut self) -> Result<(), std::io::Error> {
+        self.file.reopen().await
+    }
+
+    async fn rotate(&mut self) -> Result<(), std::io::Error> {
+        self.file.rotate().await
+    }
+}
+
+#[derive(Debug)]
+pub struct StderrSink {
+    format: Format,
+}
+
+impl StderrSink {
+    pub fn new(format: Format) -> Self {
+        StderrSink {
+            format
+        }
+    }
+}
+
+#[async_trait]
+impl LogSink for StderrSink {
+    async fn write(&mut self, record: &Record) -> Result<(), std::io::Error> {
+        std::io::stderr().write_all(record.to_line(self.format).as_bytes())
+    }
+
+    async fn flush(&mut self) -> Result<(), std::io::Error> {
+        std::io::stderr().flush()
+    }
+}
+
+// keeps every record in memory. clones share the records, so a test can keep one clone and
+// hand the other to a logger
+#[derive(Clone, Debug, Default)]
+pub struct MemorySink {
+    records: Arc<Mutex<Vec<Record>>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        MemorySink::default()
+    }
+
+    pub fn records(&self) -> Vec<Record> {
+        self.records.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.records.lock().unwrap().clear();
+    }
+}
+
+#[async_trait]
+impl LogSink for MemorySink {
+    async fn write(&mut self, record: &Record) -> Result<(), std::io::Error> {
+        self.records.lock().unwrap().push(record.clone());
+        Ok(())
+    }
+}
+
+// throws every record away
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopSink;
+
+#[async_trait]
+impl LogSink for NoopSink {
+    async fn write(&mut self, _record: &Record) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+}
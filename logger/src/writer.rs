@@ -0,0 +1,210 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::{Notify, oneshot};
+use crate::{Level, Record};
+use crate::sink::LogSink;
+
+// what a logger does with a record when the queue to the writer task is full
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum OverflowPolicy {
+    // wait until the writer task made room
+    Block,
+    // throw away the oldest queued record to make room
+    DropOldest,
+    // throw away the new record
+    DropNewest,
+}
+
+pub(crate) enum Message {
+    Record(Record),
+    Flush(oneshot::Sender<Result<(), std::io::Error>>),
+    Reopen(oneshot::Sender<Result<(), std::io::Error>>),
+    Rotate(oneshot::Sender<Result<(), std::io::Error>>),
+    Shutdown(oneshot::Sender<Result<(), std::io::Error>>),
+}
+
+// the bounded queue between the loggers and the writer task. only records count against the
+// capacity, control messages such as a flush are always accepted. once the writer task shut down
+// the queue is closed and drops every message sent to it
+pub(crate) struct Queue {
+    messages: Mutex<VecDeque<Message>>,
+    // only changed while `messages` is locked, so nothing is queued after the queue was emptied for good
+    closed: AtomicBool,
+    records: AtomicU64,
+    capacity: usize,
+    policy: OverflowPolicy,
+    dropped: AtomicU64,
+    wake_writer: Notify,
+    space: Notify,
+}
+
+impl std::fmt::Debug for Queue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Queue")
+            .field("capacity", &self.capacity)
+            .field("policy", &self.policy)
+            .field("dropped", &self.dropped.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+impl Queue {
+    pub(crate) fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Queue {
+            messages: Mutex::new(VecDeque::new()),
+            closed: AtomicBool::new(false),
+            records: AtomicU64::new(0),
+            capacity: capacity.max(1),
+            policy,
+            dropped: AtomicU64::new(0),
+            wake_writer: Notify::new(),
+            space: Notify::new(),
+        }
+    }
+
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    // a control message sent to a closed queue is dropped along with its sender, so the one waiting for
+    // the answer learns the writer task is gone
+    pub(crate) fn send_control(&self, message: Message) {
+        let mut messages = self.messages.lock().unwrap();
+        if self.closed.load(Ordering::Relaxed) {
+            return;
+        }
+
+        messages.push_back(message);
+        drop(messages);
+
+        self.wake_writer.notify_one();
+    }
+
+    pub(crate) async fn send_record(&self, record: Record) {
+        let mut record = Some(record);
+
+        loop {
+            let space = self.space.notified();
+            tokio::pin!(space);
+            space.as_mut().enable();
+
+            {
+                let mut messages = self.messages.lock().unwrap();
+
+                if self.closed.load(Ordering::Relaxed) {
+                    return;
+                }
+
+                if (self.records.load(Ordering::Relaxed) as usize) < self.capacity {
+                    messages.push_back(Message::Record(record.take().unwrap()));
+                    self.records.fetch_add(1, Ordering::Relaxed);
+                    drop(messages);
+
+                    self.wake_writer.notify_one();
+                    return;
+                }
+
+                match self.policy {
+                    OverflowPolicy::Block => {}
+                    OverflowPolicy::DropNewest => {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                    OverflowPolicy::DropOldest => {
+                        if let Some(oldest) = messages.iter().position(|message| matches!(message, Message::Record(_))) {
+                            messages.remove(oldest);
+                            messages.push_back(Message::Record(record.take().unwrap()));
+                            self.dropped.fetch_add(1, Ordering::Relaxed);
+                            drop(messages);
+
+                            self.wake_writer.notify_one();
+                            return;
+                        }
+                    }
+                }
+            }
+
+            space.await;
+        }
+    }
+
+    fn take_all(&self) -> VecDeque<Message> {
+        let messages = std::mem::take(&mut *self.messages.lock().unwrap());
+        self.records.store(0, Ordering::Relaxed);
+        self.space.notify_waiters();
+
+        messages
+    }
+
+    // drops whatever is still queued and everything sent later, and wakes the loggers waiting for room
+    fn close(&self) {
+        let mut messages = self.messages.lock().unwrap();
+        self.closed.store(true, Ordering::Relaxed);
+        let pending = std::mem::take(&mut *messages);
+        drop(messages);
+
+        self.records.store(0, Ordering::Relaxed);
+        self.space.notify_waiters();
+        drop(pending);
+    }
+}
+
+// the writer task: owns the sink, writes queued records in batches and flushes on an interval
+pub(crate) async fn run(queue: std::sync::Arc<Queue>, mut sink: Box<dyn LogSink>, flush_interval: Duration) {
+    let mut ticker = tokio::time::interval(flush_interval);
+    let mut reported_drops = 0;
+
+    loop {
+        tokio::select! {
+            _ = queue.wake_writer.notified() => {}
+            _ = ticker.tick() => {
+                if let Err(err) = sink.flush().await {
+                    eprintln!("failed to flush log sink: {}", err);
+                }
+            }
+        }
+
+        let dropped = queue.dropped();
+        if dropped > reported_drops {
+            let record = Record::now(
+                Level::Warn,
+                "logger".to_string(),
+                "Records Dropped".to_string(),
+                format!("{} log records dropped because the log queue was full", dropped - reported_drops)
+            );
+            reported_drops = dropped;
+
+            if let Err(err) = sink.write(&record).await {
+                eprintln!("failed to write log sink: {}", err);
+            }
+        }
+
+        for message in queue.take_all() {
+            match message {
+                Message::Record(record) => {
+                    if let Err(err) = sink.write(&record).await {
+                        eprintln!("failed to write log sink: {}", err);
+                    }
+                }
+                Message::Flush(done) => {
+                    let _ = done.send(sink.flush().await);
+                }
+                Message::Reopen(done) => {
+                    let _ = done.send(sink.reopen().await);
+                }
+                Message::Rotate(done) => {
+                    let _ = done.send(sink.rotate().await);
+                }
+                Message::Shutdown(done) => {
+                    let res = sink.flush().await;
+                    queue.close();
+                    let _ = done.send(res);
+                    return;
+                }
+            }
+        }
+    }
+}
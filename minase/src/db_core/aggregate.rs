@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use serde_derive::{Deserialize, Serialize};
+use crate::db_core::interrupt::Interrupt;
+use crate::db_core::query_error::QueryError;
+use crate::db_core::values::{CellKey, Column, Types, Value};
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Aggregate {
+    pub function: AggregateFunction,
+    pub column: usize,
+}
+
+impl AggregateFunction {
+    pub fn output_type(&self, input: &Types) -> Result<Types, QueryError> {
+        match (self, input) {
+            (AggregateFunction::Count, _) => Ok(Types::Int),
+            (AggregateFunction::Sum, Types::Int) => Ok(Types::Int),
+            (AggregateFunction::Sum, Types::Float) => Ok(Types::Float),
+            (AggregateFunction::Avg, Types::Int | Types::Float) => Ok(Types::Float),
+            (AggregateFunction::Min | AggregateFunction::Max, types) => Ok(types.clone()),
+            _ => Err(QueryError::TypeMismatch),
+        }
+    }
+
+    // folds the given rows of `column` into a single value. over no rows count is 0 and
+    // the others have no value, which is null in the result
+    pub fn apply(&self, column: &Column, rows: &[usize]) -> Result<Option<Value>, QueryError> {
+        if rows.is_empty() {
+            return Ok(match self {
+                AggregateFunction::Count => Some(Value::Int(0)),
+                _ => None,
+            });
+        }
+
+        macro_rules! extreme {
+            ($values:expr, $variant:ident, $keep:expr) => {{
+                let mut best = $values[rows[0]].clone();
+                for row in &rows[1..] {
+                    if $keep(&$values[*row], &best) {
+                        best = $values[*row].clone();
+                    }
+                }
+                Ok(Value::$variant(best))
+            }};
+        }
+
+        let value = match (self, column) {
+            (AggregateFunction::Count, _) => Ok(Value::Int(rows.len() as i32)),
+
+            // summed wide, so only a total that does not fit an int fails
+            (AggregateFunction::Sum, Column::Int(values)) => {
+                let sum: i64 = rows.iter().map(|row| values[*row] as i64).sum();
+                i32::try_from(sum).map(Value::Int).map_err(|_| QueryError::Overflow)
+            }
+            (AggregateFunction::Sum, Column::Float(values)) => {
+                Ok(Value::Float(rows.iter().map(|row| values[*row]).sum()))
+            }
+
+            (AggregateFunction::Avg, Column::Int(values)) => {
+                let sum: i64 = rows.iter().map(|row| values[*row] as i64).sum();
+                Ok(Value::Float((sum as f64 / rows.len() as f64) as f32))
+            }
+            (AggregateFunction::Avg, Column::Float(values)) => {
+                let sum: f64 = rows.iter().map(|row| values[*row] as f64).sum();
+                Ok(Value::Float((sum / rows.len() as f64) as f32))
+            }
+
+            // a NaN float is treated as null and never wins over a real value
+            (AggregateFunction::Min, Column::Float(values)) => {
+                extreme!(values, Float, |val: &f32, best: &f32| best.is_nan() || *val < *best)
+            }
+            (AggregateFunction::Max, Column::Float(values)) => {
+                extreme!(values, Float, |val: &f32, best: &f32| best.is_nan() || *val > *best)
+            }
+            (AggregateFunction::Min, Column::Int(values)) => extreme!(values, Int, |val, best| val < best),
+            (AggregateFunction::Max, Column::Int(values)) => extreme!(values, Int, |val, best| val > best),
+            (AggregateFunction::Min, Column::String(values)) => extreme!(values, String, |val, best| val < best),
+            (AggregateFunction::Max, Column::String(values)) => extreme!(values, String, |val, best| val > best),
+            (AggregateFunction::Min, Column::Bool(values)) => extreme!(values, Bool, |val, best| val < best),
+            (AggregateFunction::Max, Column::Bool(values)) => extreme!(values, Bool, |val, best| val > best),
+
+            _ => Err(QueryError::TypeMismatch),
+        };
+
+        value.map(Some)
+    }
+}
+
+// splits `rows` into groups of equal values in the `group_by` columns.
+// groups are returned in the order their first row appears
+pub fn group_rows(columns: &[Column], group_by: &[usize], rows: usize, interrupt: &Interrupt) -> Result<Vec<Vec<usize>>, QueryError> {
+    // without group by every row is in one group, even when there are none
+    if group_by.is_empty() {
+        return Ok(vec![(0..rows).collect()]);
+    }
+
+    let mut index: HashMap<Vec<CellKey>, usize> = HashMap::new();
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+
+    for row in 0..rows {
+        interrupt.check_row(row)?;
+
+        let key = group_by.iter().map(|column| columns[*column].key(row)).collect::<Vec<CellKey>>();
+
+        match index.get(&key) {
+            Some(group) => groups[*group].push(row),
+            None => {
+                index.insert(key, groups.len());
+                groups.push(vec![row]);
+            }
+        }
+    }
+
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use logger::Logger;
+    use crate::db_core::database::Database;
+    use crate::db_core::values::Expr;
+    use super::*;
+
+    fn aggregate(function: AggregateFunction) -> Aggregate {
+        Aggregate {
+            function,
+            column: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn int_sum_fails_on_overflow() {
+        let mut db = Database::new(Logger::noop());
+        db.add_table(vec![Types::Int]).await.unwrap();
+        db.insert(0, vec![Value::Int(i32::MAX)]).await.unwrap();
+        db.insert(0, vec![Value::Int(1)]).await.unwrap();
+
+        let sum = db.aggregate(0, vec![], vec![aggregate(AggregateFunction::Sum)], None).await;
+        assert!(matches!(sum, Err(QueryError::Overflow)));
+
+        // a total that fits is fine, even when the running sum does not
+        db.insert(0, vec![Value::Int(-2)]).await.unwrap();
+        let sum = db.aggregate(0, vec![], vec![aggregate(AggregateFunction::Sum)], None).await.unwrap();
+        assert!(matches!(sum.columns()[0].get(0), Some(Value::Int(sum)) if sum == i32::MAX - 1));
+    }
+
+    #[tokio::test]
+    async fn empty_table_has_one_row_without_group_by() {
+        let mut db = Database::new(Logger::noop());
+        db.add_table(vec![Types::Int]).await.unwrap();
+
+        let functions = [AggregateFunction::Count, AggregateFunction::Sum, AggregateFunction::Avg, AggregateFunction::Min];
+        let result = db.aggregate(0, vec![], functions.iter().copied().map(aggregate).collect(), None).await.unwrap();
+
+        assert_eq!(result.row_count(), 1);
+        assert!(matches!(result.columns()[0].get(0), Some(Value::Int(0))));
+        assert!(!result.is_null(0, 0));
+        assert!((1..functions.len()).all(|column| result.is_null(column, 0)));
+
+        // a null never matches a having condition
+        let having = Some((1, vec![Expr::Cell, Expr::Value(Value::Int(0)), Expr::Eq]));
+        let result = db.aggregate(0, vec![], vec![aggregate(AggregateFunction::Count), aggregate(AggregateFunction::Sum)], having).await.unwrap();
+        assert_eq!(result.row_count(), 0);
+
+        // with group by there are no groups
+        let result = db.aggregate(0, vec![0], vec![aggregate(AggregateFunction::Count)], None).await.unwrap();
+        assert_eq!(result.row_count(), 0);
+    }
+}
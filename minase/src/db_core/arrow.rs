@@ -0,0 +1,258 @@
+use std::path::PathBuf;
+use serde_derive::{Deserialize, Serialize};
+#[cfg(feature = "arrow")]
+use std::sync::Arc;
+#[cfg(feature = "arrow")]
+use arrow_array::{Array, ArrayRef, BooleanArray, Float32Array, Int32Array, RecordBatchOptions, StringArray};
+#[cfg(feature = "arrow")]
+use arrow_array::cast::AsArray;
+#[cfg(feature = "arrow")]
+use arrow_array::types::{Float32Type, Float64Type, Int32Type};
+#[cfg(feature = "arrow")]
+use arrow_schema::{DataType, Field, Schema};
+#[cfg(feature = "arrow")]
+use crate::db_core::database::Table;
+#[cfg(feature = "arrow")]
+use crate::db_core::interrupt::Interrupt;
+#[cfg(feature = "arrow")]
+use crate::db_core::query_error::QueryError;
+#[cfg(feature = "arrow")]
+use crate::db_core::values::{Column, Types};
+
+// so users of the conversions do not have to depend on the same arrow version themselves
+#[cfg(feature = "arrow")]
+pub use arrow_array::RecordBatch;
+
+
+// the layout of a binary table file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ArrowFormat {
+    // the arrow ipc file format, also known as feather. the stream format is read as well
+    Ipc,
+    Parquet,
+}
+
+// where the rows of an arrow or parquet import come from
+#[derive(Clone, Deserialize, Serialize)]
+pub enum ArrowSource {
+    // a file on the server
+    File(PathBuf),
+    // the file's contents, sent by the client
+    Data(#[serde(with = "serde_bytes")] Vec<u8>),
+}
+
+// leaves the data out, since queries are logged with their debug form
+impl std::fmt::Debug for ArrowSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArrowSource::File(path) => f.debug_tuple("File").field(path).finish(),
+            ArrowSource::Data(data) => f.debug_struct("Data")
+                .field("bytes", &data.len())
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ArrowImport {
+    // the table the rows went to, the new one when the import created it
+    pub table: usize,
+    pub rows: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ArrowExport {
+    pub rows: usize,
+    // the file's contents, when it was not written on the server
+    #[serde(with = "serde_bytes")]
+    pub data: Option<Vec<u8>>,
+}
+
+// the table as one record batch, its columns named c0, c1 and so on. a column is nullable when it holds nulls
+#[cfg(feature = "arrow")]
+pub fn to_record_batch(table: &Table) -> Result<RecordBatch, QueryError> {
+    let arrays: Vec<ArrayRef> = (0..table.columns.len()).map(|column| array(table, column)).collect();
+    let fields: Vec<Field> = arrays.iter().enumerate()
+        .map(|(column, array)| Field::new(format!("c{}", column), array.data_type().clone(), array.null_count() > 0))
+        .collect();
+
+    // a table without columns still has rows
+    let options = RecordBatchOptions::new().with_row_count(Some(table.row_count()));
+    RecordBatch::try_new_with_options(Arc::new(Schema::new(fields)), arrays, &options).map_err(invalid)
+}
+
+// the rows of the batches as a table. the columns are read as the types of `types` or, when there are
+// none, as the type closest to theirs: integers as int, floating point numbers as float, text as string
+#[cfg(feature = "arrow")]
+pub fn from_record_batches(schema: &Schema, batches: &[RecordBatch], types: Option<&[Types]>) -> Result<Table, QueryError> {
+    let column_types = match types {
+        Some(types) if types.len() != schema.fields().len() => {
+            return Err(QueryError::InvalidArrow(format!("the file has {} columns, the table {}", schema.fields().len(), types.len())));
+        }
+        Some(types) => types.to_vec(),
+        None => schema.fields().iter()
+            .map(|field| column_type(field.data_type()).ok_or_else(|| QueryError::InvalidArrow(
+                format!("column {} is of type {}, which tables can not hold", field.name(), field.data_type())
+            )))
+            .collect::<Result<_, _>>()?,
+    };
+
+    let mut columns: Vec<Column> = column_types.iter().map(Column::empty).collect();
+    let mut nulls = vec![Vec::new(); columns.len()];
+    let mut rows = 0;
+
+    for batch in batches {
+        for (id, array) in batch.columns().iter().enumerate() {
+            let failed = |err: String| QueryError::InvalidArrow(format!("column {}: {}", schema.field(id).name(), err));
+            let options = arrow_cast::CastOptions { safe: false, ..Default::default() };
+
+            if column_types[id] == Types::Float {
+                check_float_range(array, &options).map_err(failed)?;
+            }
+
+            let array = arrow_cast::cast_with_options(array, &data_type(&column_types[id]), &options)
+                .map_err(|err| failed(err.to_string()))?;
+
+            // null cells keep whatever value the array has under them
+            let values = match &column_types[id] {
+                Types::Int => Column::Int(array.as_primitive::<Int32Type>().values().iter().copied().collect()),
+                Types::Float => Column::Float(array.as_primitive::<Float32Type>().values().iter().copied().collect()),
+                Types::String => Column::String(array.as_string::<i32>().iter().map(|value| value.unwrap_or_default().to_string()).collect()),
+                Types::Bool => Column::Bool(array.as_boolean().iter().map(|value| value.unwrap_or_default()).collect()),
+            };
+            columns[id].append(values)?;
+
+            // a column without nulls keeps an empty mask, one with them gets a flag for every row
+            if array.null_count() > 0 || !nulls[id].is_empty() {
+                nulls[id].resize(rows, false);
+                nulls[id].extend((0..array.len()).map(|row| array.is_null(row)));
+            }
+        }
+
+        rows += batch.num_rows();
+    }
+
+    let mut table = Table::new(columns, column_types);
+    if nulls.iter().any(|nulls| !nulls.is_empty()) {
+        table.nulls = nulls;
+    }
+
+    Ok(table)
+}
+
+// `table` as an arrow ipc or a parquet file, parquet compressed with snappy
+#[cfg(feature = "arrow")]
+pub fn write(table: &Table, format: ArrowFormat) -> Result<Vec<u8>, QueryError> {
+    let batch = to_record_batch(table)?;
+
+    match format {
+        ArrowFormat::Ipc => {
+            let mut writer = arrow_ipc::writer::FileWriter::try_new(Vec::new(), &batch.schema()).map_err(invalid)?;
+            writer.write(&batch).map_err(invalid)?;
+            writer.finish().map_err(invalid)?;
+            writer.into_inner().map_err(invalid)
+        }
+        ArrowFormat::Parquet => {
+            let properties = parquet::file::properties::WriterProperties::builder()
+                .set_compression(parquet::basic::Compression::SNAPPY)
+                .build();
+
+            let mut writer = parquet::arrow::ArrowWriter::try_new(Vec::new(), batch.schema(), Some(properties))
+                .map_err(invalid)?;
+            writer.write(&batch).map_err(invalid)?;
+            writer.into_inner().map_err(invalid)
+        }
+    }
+}
+
+// the rows of an arrow ipc or a parquet file as a table, see `from_record_batches`
+#[cfg(feature = "arrow")]
+pub fn read(data: Vec<u8>, format: ArrowFormat, types: Option<&[Types]>, interrupt: &Interrupt) -> Result<Table, QueryError> {
+    let (schema, batches): (_, Box<dyn Iterator<Item = Result<RecordBatch, QueryError>>>) = match format {
+        // the file format starts with its magic, the stream format with the schema
+        ArrowFormat::Ipc if data.starts_with(b"ARROW1") => {
+            let reader = arrow_ipc::reader::FileReader::try_new(std::io::Cursor::new(data), None).map_err(invalid)?;
+            (reader.schema(), Box::new(reader.map(|batch| batch.map_err(invalid))))
+        }
+        ArrowFormat::Ipc => {
+            let reader = arrow_ipc::reader::StreamReader::try_new(std::io::Cursor::new(data), None).map_err(invalid)?;
+            (reader.schema(), Box::new(reader.map(|batch| batch.map_err(invalid))))
+        }
+        ArrowFormat::Parquet => {
+            let builder = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(data))
+                .map_err(invalid)?;
+            let schema = builder.schema().clone();
+            let reader = builder.build().map_err(invalid)?;
+            (schema, Box::new(reader.map(|batch| batch.map_err(invalid))))
+        }
+    };
+
+    let mut read = Vec::new();
+    for batch in batches {
+        interrupt.check()?;
+        read.push(batch?);
+    }
+
+    from_record_batches(&schema, &read, types)
+}
+
+#[cfg(feature = "arrow")]
+fn invalid(err: impl std::fmt::Display) -> QueryError {
+    QueryError::InvalidArrow(err.to_string())
+}
+
+#[cfg(feature = "arrow")]
+fn array(table: &Table, column: usize) -> ArrayRef {
+    let nulls = table.nulls.get(column).filter(|nulls| nulls.contains(&true));
+    let cells = |row: usize| nulls.is_none_or(|nulls| !nulls[row]);
+
+    match (&table.columns[column], nulls) {
+        (Column::Int(values), None) => Arc::new(Int32Array::from(values.to_vec())),
+        (Column::Int(values), Some(_)) => Arc::new(Int32Array::from_iter(values.iter().enumerate().map(|(row, value)| cells(row).then_some(*value)))),
+        (Column::Float(values), None) => Arc::new(Float32Array::from(values.to_vec())),
+        (Column::Float(values), Some(_)) => Arc::new(Float32Array::from_iter(values.iter().enumerate().map(|(row, value)| cells(row).then_some(*value)))),
+        (Column::String(values), _) => Arc::new(StringArray::from_iter(values.iter().enumerate().map(|(row, value)| cells(row).then_some(value)))),
+        (Column::Bool(values), _) => Arc::new(BooleanArray::from_iter(values.iter().enumerate().map(|(row, value)| cells(row).then_some(*value)))),
+    }
+}
+
+#[cfg(feature = "arrow")]
+fn data_type(types: &Types) -> DataType {
+    match types {
+        Types::Int => DataType::Int32,
+        Types::Float => DataType::Float32,
+        Types::String => DataType::Utf8,
+        Types::Bool => DataType::Boolean,
+    }
+}
+
+// wider types are narrowed. an integer that does not fit fails the conversion, a double is rounded
+// to single precision and fails it only when it is out of the range of a float
+#[cfg(feature = "arrow")]
+fn column_type(data_type: &DataType) -> Option<Types> {
+    match data_type {
+        DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64
+        | DataType::UInt8 | DataType::UInt16 | DataType::UInt32 | DataType::UInt64 => Some(Types::Int),
+        DataType::Float16 | DataType::Float32 | DataType::Float64 => Some(Types::Float),
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View => Some(Types::String),
+        DataType::Boolean => Some(Types::Bool),
+        // categorical columns, as pandas writes them
+        DataType::Dictionary(_, values) => column_type(values),
+        _ => None,
+    }
+}
+
+// casting a double to a float turns one out of range into an infinity instead of failing, so the values
+// are looked at as doubles first
+#[cfg(feature = "arrow")]
+fn check_float_range(array: &ArrayRef, options: &arrow_cast::CastOptions) -> Result<(), String> {
+    if matches!(array.data_type(), DataType::Float16 | DataType::Float32) {
+        return Ok(());
+    }
+
+    let doubles = arrow_cast::cast_with_options(array, &DataType::Float64, options).map_err(|err| err.to_string())?;
+    match doubles.as_primitive::<Float64Type>().iter().flatten().find(|value| value.is_finite() && value.abs() > f32::MAX as f64) {
+        Some(value) => Err(format!("{:e} is out of the range of a float", value)),
+        None => Ok(()),
+    }
+}
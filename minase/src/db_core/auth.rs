@@ -0,0 +1,243 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use serde_derive::{Deserialize, Serialize};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use crate::db_core::query_error::QueryError;
+use crate::db_core::storage::write_atomically;
+
+
+// the file in the data directory holding the users and their grants
+const CATALOG_FILE: &str = "catalog.mpk";
+
+const SALT_BYTES: usize = 16;
+const HASH_BYTES: usize = 32;
+// pbkdf2 rounds for new password hashes. stored with every hash, so it can be raised later
+const ROUNDS: u32 = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum Privilege {
+    // select, aggregate and join
+    Read,
+    // insert, update and delete
+    Write,
+    // add and drop tables
+    Ddl,
+    // everything, including managing users. only granted on all tables
+    Admin,
+}
+
+// a password as sent by a client. its debug form hides the password, since queries are logged
+#[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Password(pub String);
+
+impl std::fmt::Debug for Password {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Password(..)")
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct User {
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+    rounds: u32,
+    // a privilege on a table, by its oid, or on every table when there is none
+    grants: BTreeSet<(Privilege, Option<u64>)>,
+}
+
+fn hash_password(password: &str, salt: &[u8], rounds: u32) -> Vec<u8> {
+    let mut hash = vec![0; HASH_BYTES];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, rounds, &mut hash);
+
+    hash
+}
+
+// compares in constant time, so the time a login takes does not tell how much of a hash matched
+fn hashes_equal(left: &[u8], right: &[u8]) -> bool {
+    left.len() == right.len() && left.iter().zip(right).fold(0, |diff, (left, right)| diff | (left ^ right)) == 0
+}
+
+fn hmac(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// what a scram-sha-256 exchange needs to know of a user. the password hash is scram's salted password,
+// so users log in this way without the server seeing their password
+#[derive(Clone)]
+pub struct ScramKeys {
+    pub salt: Vec<u8>,
+    pub rounds: u32,
+    stored_key: Vec<u8>,
+    server_key: Vec<u8>,
+}
+
+impl std::fmt::Debug for ScramKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScramKeys").field("rounds", &self.rounds).finish_non_exhaustive()
+    }
+}
+
+impl ScramKeys {
+    fn new(salt: Vec<u8>, rounds: u32, salted_password: &[u8]) -> Self {
+        ScramKeys {
+            salt,
+            rounds,
+            stored_key: Sha256::digest(hmac(salted_password, b"Client Key")).to_vec(),
+            server_key: hmac(salted_password, b"Server Key"),
+        }
+    }
+
+    // keys for a user that does not exist, so the exchange goes on as for a wrong password
+    fn unknown() -> Self {
+        let salt: [u8; SALT_BYTES] = rand::random();
+        let salted_password: [u8; HASH_BYTES] = rand::random();
+
+        ScramKeys::new(salt.to_vec(), ROUNDS, &salted_password)
+    }
+
+    // checks the client's proof over `auth_message`, returning the server's signature for the client to
+    // check in turn when it holds
+    pub fn verify(&self, auth_message: &[u8], proof: &[u8]) -> Result<Vec<u8>, QueryError> {
+        let signature = hmac(&self.stored_key, auth_message);
+        if proof.len() != signature.len() {
+            return Err(QueryError::AuthenticationFailed);
+        }
+
+        let client_key: Vec<u8> = proof.iter().zip(&signature).map(|(proof, signature)| proof ^ signature).collect();
+        match hashes_equal(&Sha256::digest(client_key), &self.stored_key) {
+            true => Ok(hmac(&self.server_key, auth_message)),
+            false => Err(QueryError::AuthenticationFailed),
+        }
+    }
+}
+
+impl User {
+    fn new(password: &str) -> Self {
+        let salt: [u8; SALT_BYTES] = rand::random();
+
+        User {
+            hash: hash_password(password, &salt, ROUNDS),
+            salt: salt.to_vec(),
+            rounds: ROUNDS,
+            grants: BTreeSet::new(),
+        }
+    }
+
+    fn verify(&self, password: &str) -> bool {
+        hashes_equal(&hash_password(password, &self.salt, self.rounds), &self.hash)
+    }
+
+    fn allows(&self, privilege: Privilege, table: Option<u64>) -> bool {
+        self.grants.contains(&(Privilege::Admin, None))
+            || self.grants.contains(&(privilege, None))
+            || table.is_some() && self.grants.contains(&(privilege, table))
+    }
+}
+
+// the users of a server and what they may do
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Catalog {
+    users: BTreeMap<String, User>,
+}
+
+impl Catalog {
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    pub fn create_user(&mut self, name: &str, password: &str) -> Result<(), QueryError> {
+        if self.users.contains_key(name) {
+            return Err(QueryError::UserExists);
+        }
+
+        self.users.insert(name.to_string(), User::new(password));
+        Ok(())
+    }
+
+    pub fn drop_user(&mut self, name: &str) -> Result<(), QueryError> {
+        self.users.remove(name).map(|_| ()).ok_or(QueryError::UserNotFound)
+    }
+
+    pub fn set_password(&mut self, name: &str, password: &str) -> Result<(), QueryError> {
+        let user = self.users.get_mut(name).ok_or(QueryError::UserNotFound)?;
+        let grants = std::mem::take(&mut user.grants);
+
+        *user = User::new(password);
+        user.grants = grants;
+
+        Ok(())
+    }
+
+    // fails the same way for an unknown user as for a wrong password
+    pub fn authenticate(&self, name: &str, password: &str) -> Result<(), QueryError> {
+        match self.users.get(name) {
+            Some(user) if user.verify(password) => Ok(()),
+            Some(_) => Err(QueryError::AuthenticationFailed),
+            None => {
+                // hash anyway, so an unknown user takes as long to reject as a wrong password
+                let _ = hash_password(password, &[0; SALT_BYTES], ROUNDS);
+                Err(QueryError::AuthenticationFailed)
+            }
+        }
+    }
+
+    // the scram keys of `name`, made up ones for an unknown user so the exchange does not tell it apart
+    pub fn scram_keys(&self, name: &str) -> ScramKeys {
+        match self.users.get(name) {
+            Some(user) => ScramKeys::new(user.salt.clone(), user.rounds, &user.hash),
+            None => ScramKeys::unknown(),
+        }
+    }
+
+    pub fn grant(&mut self, name: &str, privilege: Privilege, table: Option<u64>) -> Result<(), QueryError> {
+        if privilege == Privilege::Admin && table.is_some() {
+            return Err(QueryError::InvalidQuery);
+        }
+
+        let user = self.users.get_mut(name).ok_or(QueryError::UserNotFound)?;
+        user.grants.insert((privilege, table));
+
+        Ok(())
+    }
+
+    // only takes back exactly this grant, a grant on every table still covers a single table
+    pub fn revoke(&mut self, name: &str, privilege: Privilege, table: Option<u64>) -> Result<(), QueryError> {
+        let user = self.users.get_mut(name).ok_or(QueryError::UserNotFound)?;
+        user.grants.remove(&(privilege, table));
+
+        Ok(())
+    }
+
+    // whether `name` may use `privilege` on the table with the given oid, or on every table
+    pub fn authorize(&self, name: &str, privilege: Privilege, table: Option<u64>) -> Result<(), QueryError> {
+        match self.users.get(name) {
+            Some(user) if user.allows(privilege, table) => Ok(()),
+            _ => Err(QueryError::PermissionDenied),
+        }
+    }
+
+    // the catalog saved in `data_dir`, or an empty one when nothing was saved there yet
+    pub fn load(data_dir: &Path) -> Result<Self, std::io::Error> {
+        let buffer = match std::fs::read(data_dir.join(CATALOG_FILE)) {
+            Ok(buffer) => buffer,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Catalog::default()),
+            Err(err) => return Err(err),
+        };
+
+        Catalog::deserialize(&mut rmp_serde::Deserializer::new(&buffer[..]))
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn save(&self, data_dir: &Path, fsync: bool) -> Result<(), std::io::Error> {
+        let mut buffer = vec![];
+        self.serialize(&mut rmp_serde::Serializer::new(&mut buffer))
+            .map_err(std::io::Error::other)?;
+
+        write_atomically(data_dir, CATALOG_FILE, &buffer, fsync)
+    }
+}
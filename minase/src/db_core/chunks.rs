@@ -0,0 +1,236 @@
+use std::ops::{Index, IndexMut};
+use std::sync::Arc;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// rows per chunk. every chunk but the last is full, so a row is found without a search
+const CHUNK_ROWS: usize = 1024;
+
+// a named type rather than `impl Iterator`, which could hold on to the column until it is dropped
+pub type Iter<'c, T> = std::iter::FlatMap<std::slice::Iter<'c, Arc<Vec<T>>>, std::slice::Iter<'c, T>, fn(&'c Arc<Vec<T>>) -> std::slice::Iter<'c, T>>;
+
+// the values of a column, kept in chunks shared by every snapshot and transaction holding the table.
+// copying a table only copies the pointers to its chunks, and writing a row copies the chunk it is in.
+// serialized as a plain sequence, the same as a vector
+#[derive(Clone, Debug)]
+pub struct Chunks<T> {
+    chunks: Vec<Arc<Vec<T>>>,
+    len: usize,
+}
+
+impl<T: Clone> Chunks<T> {
+    pub fn new() -> Self {
+        Chunks {
+            chunks: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, row: usize) -> Option<&T> {
+        match row < self.len {
+            true => Some(&self.chunks[row / CHUNK_ROWS][row % CHUNK_ROWS]),
+            false => None,
+        }
+    }
+
+    // copies the chunk holding `row` first when it is shared
+    pub fn get_mut(&mut self, row: usize) -> Option<&mut T> {
+        match row < self.len {
+            true => Some(&mut Arc::make_mut(&mut self.chunks[row / CHUNK_ROWS])[row % CHUNK_ROWS]),
+            false => None,
+        }
+    }
+
+    pub fn push(&mut self, value: T) {
+        match self.chunks.last_mut() {
+            Some(chunk) if chunk.len() < CHUNK_ROWS => Arc::make_mut(chunk).push(value),
+            _ => self.chunks.push(Arc::new(vec![value])),
+        }
+
+        self.len += 1;
+    }
+
+    // moves the values of `other` to the end
+    pub fn append(&mut self, other: Chunks<T>) {
+        match self.len % CHUNK_ROWS {
+            // the chunks of `other` stay shared when they line up with the ones already here
+            0 => {
+                self.len += other.len;
+                self.chunks.extend(other.chunks);
+            }
+            _ => other.iter().cloned().for_each(|value| self.push(value)),
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.chunks.iter().flat_map(|chunk| chunk.iter())
+    }
+
+    // removes `rows`, given in ascending order. the chunks before the first of them stay shared,
+    // the rows after it are moved up and copied
+    pub fn remove_rows(&mut self, rows: &[usize]) {
+        let Some(first) = rows.first() else {
+            return;
+        };
+
+        let kept = first / CHUNK_ROWS;
+        let tail = self.chunks.split_off(kept);
+        self.len = kept * CHUNK_ROWS;
+
+        let mut removed = rows.iter().peekable();
+        for (offset, value) in tail.iter().flat_map(|chunk| chunk.iter()).enumerate() {
+            if removed.next_if_eq(&&(kept * CHUNK_ROWS + offset)).is_none() {
+                self.push(value.clone());
+            }
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().cloned().collect()
+    }
+
+    // the memory the chunks hold, counting shared chunks in full
+    pub fn capacity(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.capacity()).sum()
+    }
+}
+
+impl<T> Index<usize> for Chunks<T> {
+    type Output = T;
+
+    fn index(&self, row: usize) -> &T {
+        assert!(row < self.len, "row {} out of range for {} rows", row, self.len);
+        &self.chunks[row / CHUNK_ROWS][row % CHUNK_ROWS]
+    }
+}
+
+impl<T: Clone> IndexMut<usize> for Chunks<T> {
+    fn index_mut(&mut self, row: usize) -> &mut T {
+        let len = self.len;
+        self.get_mut(row).unwrap_or_else(|| panic!("row {} out of range for {} rows", row, len))
+    }
+}
+
+impl<T: Clone> FromIterator<T> for Chunks<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(values: I) -> Self {
+        let mut chunks = Chunks::new();
+        values.into_iter().for_each(|value| chunks.push(value));
+        chunks
+    }
+}
+
+impl<T: Clone> From<Vec<T>> for Chunks<T> {
+    fn from(values: Vec<T>) -> Self {
+        values.into_iter().collect()
+    }
+}
+
+impl<T: Clone> Default for Chunks<T> {
+    fn default() -> Self {
+        Chunks::new()
+    }
+}
+
+impl<T: Serialize> Serialize for Chunks<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.chunks.iter().flat_map(|chunk| chunk.iter()))
+    }
+}
+
+impl<'de, T: Deserialize<'de> + Clone> Deserialize<'de> for Chunks<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(Chunks::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(len: usize) -> Chunks<usize> {
+        (0..len).collect()
+    }
+
+    #[test]
+    fn pushes_across_chunks() {
+        let values = numbered(2 * CHUNK_ROWS + 5);
+
+        assert_eq!(values.len(), 2 * CHUNK_ROWS + 5);
+        assert_eq!(values.chunks.len(), 3);
+        assert!(values.chunks[..2].iter().all(|chunk| chunk.len() == CHUNK_ROWS));
+        assert_eq!(values[CHUNK_ROWS - 1], CHUNK_ROWS - 1);
+        assert_eq!(values[CHUNK_ROWS], CHUNK_ROWS);
+        assert_eq!(values.get(2 * CHUNK_ROWS + 5), None);
+        assert_eq!(values.to_vec(), (0..2 * CHUNK_ROWS + 5).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn appends_across_chunks() {
+        // lined up, the chunks of the appended values are shared
+        let mut aligned = numbered(CHUNK_ROWS);
+        let other = numbered(CHUNK_ROWS + 3);
+        aligned.append(other.clone());
+
+        assert_eq!(aligned.len(), 2 * CHUNK_ROWS + 3);
+        assert!(Arc::ptr_eq(&aligned.chunks[1], &other.chunks[0]));
+        assert_eq!(aligned.to_vec(), (0..CHUNK_ROWS).chain(0..CHUNK_ROWS + 3).collect::<Vec<_>>());
+
+        // otherwise they fill up the last chunk first
+        let mut unaligned = numbered(10);
+        unaligned.append(numbered(CHUNK_ROWS));
+
+        assert_eq!(unaligned.len(), CHUNK_ROWS + 10);
+        assert_eq!(unaligned.chunks.len(), 2);
+        assert_eq!(unaligned.chunks[0].len(), CHUNK_ROWS);
+        assert_eq!(unaligned.to_vec(), (0..10).chain(0..CHUNK_ROWS).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn removes_rows_across_chunks() {
+        let len = 3 * CHUNK_ROWS;
+        let removed = [CHUNK_ROWS + 1, 2 * CHUNK_ROWS - 1, 2 * CHUNK_ROWS, len - 1];
+
+        let original = numbered(len);
+        let mut values = original.clone();
+        values.remove_rows(&removed);
+
+        assert_eq!(values.len(), len - removed.len());
+        assert_eq!(values.to_vec(), (0..len).filter(|row| !removed.contains(row)).collect::<Vec<_>>());
+        assert!(values.chunks[..values.chunks.len() - 1].iter().all(|chunk| chunk.len() == CHUNK_ROWS));
+
+        // the chunk before the first removed row is still the one of the original
+        assert!(Arc::ptr_eq(&values.chunks[0], &original.chunks[0]));
+        assert!(!Arc::ptr_eq(&values.chunks[1], &original.chunks[1]));
+        assert_eq!(original.to_vec(), (0..len).collect::<Vec<_>>());
+
+        let mut none_removed = original.clone();
+        none_removed.remove_rows(&[]);
+        assert!(none_removed.chunks.iter().zip(&original.chunks).all(|(chunk, other)| Arc::ptr_eq(chunk, other)));
+    }
+
+    #[test]
+    fn copies_only_the_chunk_written() {
+        let snapshot = numbered(3 * CHUNK_ROWS);
+        let mut written = snapshot.clone();
+
+        written[CHUNK_ROWS + 7] = 0;
+        written.push(1);
+
+        assert_eq!(snapshot[CHUNK_ROWS + 7], CHUNK_ROWS + 7);
+        assert_eq!(snapshot.len(), 3 * CHUNK_ROWS);
+        assert_eq!(snapshot.to_vec(), (0..3 * CHUNK_ROWS).collect::<Vec<_>>());
+
+        assert_eq!(written[CHUNK_ROWS + 7], 0);
+        assert_eq!(written.len(), 3 * CHUNK_ROWS + 1);
+        assert!(Arc::ptr_eq(&written.chunks[0], &snapshot.chunks[0]));
+        assert!(!Arc::ptr_eq(&written.chunks[1], &snapshot.chunks[1]));
+        assert!(Arc::ptr_eq(&written.chunks[2], &snapshot.chunks[2]));
+    }
+}
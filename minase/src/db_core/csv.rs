@@ -0,0 +1,245 @@
+use std::path::PathBuf;
+use serde_derive::{Deserialize, Serialize};
+use crate::db_core::database::Table;
+use crate::db_core::interrupt::Interrupt;
+use crate::db_core::query_error::QueryError;
+use crate::db_core::values::{Column, Types, Value};
+
+
+// how a csv file is read and written
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CsvOptions {
+    pub delimiter: char,
+    // whether the first line names the columns. on import it is only used to count them
+    pub header: bool,
+    pub quote: char,
+    // the field standing for a null cell, an empty field when there is none. stored tables have no
+    // nulls, so on import such a field makes its row fail
+    pub null: Option<String>,
+    // how many rows can fail before the import is given up, the ones that did are skipped and reported
+    pub max_errors: usize,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            header: true,
+            quote: '"',
+            null: None,
+            max_errors: 0,
+        }
+    }
+}
+
+// where the rows of an import come from
+#[derive(Clone, Deserialize, Serialize)]
+pub enum CsvSource {
+    // a file on the server
+    File(PathBuf),
+    // the file's contents, sent by the client
+    Data(String),
+}
+
+// leaves the data out, since queries are logged with their debug form
+impl std::fmt::Debug for CsvSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CsvSource::File(path) => f.debug_tuple("File").field(path).finish(),
+            CsvSource::Data(data) => f.debug_struct("Data")
+                .field("bytes", &data.len())
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+// a row that could not be imported, `line` counting from 1 with the header
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CsvRowError {
+    pub line: u64,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CsvImport {
+    // the table the rows went to, the new one when the import created it
+    pub table: usize,
+    pub rows: usize,
+    // the rows that were skipped, never more than `max_errors`
+    pub errors: Vec<CsvRowError>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CsvExport {
+    pub rows: usize,
+    // the file's contents, when it was not written on the server
+    pub data: Option<String>,
+}
+
+// the rows of a csv file as columns of `types`
+#[derive(Debug)]
+pub struct CsvRows {
+    pub types: Vec<Types>,
+    pub columns: Vec<Column>,
+    pub errors: Vec<CsvRowError>,
+}
+
+// reads `text` into columns of `types` or, when there are none, of the types every field of a column
+// can be read as: int, then float, then bool, then string
+pub fn read(text: &str, options: &CsvOptions, types: Option<&[Types]>, interrupt: &Interrupt) -> Result<CsvRows, QueryError> {
+    let mut reader = ::csv::ReaderBuilder::new()
+        .delimiter(byte(options.delimiter)?)
+        .quote(byte(options.quote)?)
+        .has_headers(options.header)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+
+    let mut errors = Vec::new();
+    let mut fail = |line: u64, message: String| {
+        errors.push(CsvRowError { line, message });
+        match errors.len() > options.max_errors {
+            true => Err(QueryError::InvalidCsv(errors.clone())),
+            false => Ok(()),
+        }
+    };
+
+    let header = match options.header {
+        true => Some(reader.headers().map_err(|err| csv_error(&err))?.len()),
+        false => None,
+    };
+
+    let mut records = Vec::new();
+    for (row, record) in reader.records().enumerate() {
+        interrupt.check_row(row)?;
+
+        match record {
+            Ok(record) => records.push(record),
+            Err(err) => fail(err.position().map_or(0, |position| position.line()), err.to_string())?,
+        }
+    }
+
+    let width = match (types, header) {
+        (Some(types), Some(header)) if header != types.len() => {
+            return Err(QueryError::InvalidCsv(vec![CsvRowError {
+                line: 1,
+                message: format!("the header has {} columns, the table {}", header, types.len()),
+            }]));
+        }
+        (Some(types), _) => types.len(),
+        (None, Some(header)) => header,
+        (None, None) => records.first().map_or(0, |record| record.len()),
+    };
+
+    if width == 0 {
+        return Err(QueryError::InvalidCsv(vec![CsvRowError { line: 1, message: "no columns".to_string() }]));
+    }
+
+    let line = |record: &::csv::StringRecord| record.position().map_or(0, |position| position.line());
+    let is_null = |field: &str| options.null.as_deref() == Some(field);
+
+    let mut rows = Vec::with_capacity(records.len());
+    for record in records {
+        match record.len() == width {
+            true => rows.push(record),
+            false => fail(line(&record), format!("expected {} fields, found {}", width, record.len()))?,
+        }
+    }
+
+    let types = match types {
+        Some(types) => types.to_vec(),
+        None => (0..width)
+            .map(|column| infer(rows.iter().map(|record| &record[column]).filter(|field| !is_null(field))))
+            .collect(),
+    };
+
+    let mut columns: Vec<Column> = types.iter().map(Column::empty).collect();
+    for (row, record) in rows.iter().enumerate() {
+        interrupt.check_row(row)?;
+
+        let values: Result<Vec<Value>, String> = record.iter().zip(&types).enumerate()
+            .map(|(column, (field, types))| match is_null(field) {
+                true => Err(format!("c{} is null", column)),
+                false => parse(field, types).ok_or_else(|| format!("c{}: {:?} is not {:?}", column, field, types)),
+            })
+            .collect();
+
+        match values {
+            Ok(values) => {
+                for (column, value) in columns.iter_mut().zip(values) {
+                    column.push(value)?;
+                }
+            }
+            Err(message) => fail(line(record), message)?,
+        }
+    }
+
+    Ok(CsvRows { types, columns, errors })
+}
+
+// the rows of `table`, with a header naming the columns c0, c1 and so on when asked for one
+pub fn write(table: &Table, options: &CsvOptions) -> Result<String, QueryError> {
+    let mut writer = ::csv::WriterBuilder::new()
+        .delimiter(byte(options.delimiter)?)
+        .quote(byte(options.quote)?)
+        .from_writer(Vec::new());
+
+    if options.header {
+        writer.write_record((0..table.columns().len()).map(|column| format!("c{}", column)))
+            .map_err(|err| csv_error(&err))?;
+    }
+
+    let null = options.null.clone().unwrap_or_default();
+    for row in 0..table.row_count() {
+        let record = table.columns().iter().enumerate().map(|(column_id, column)| {
+            match column.get(row).filter(|_| !table.is_null(column_id, row)) {
+                Some(Value::Int(value)) => value.to_string(),
+                Some(Value::Float(value)) => value.to_string(),
+                Some(Value::String(value)) => value,
+                Some(Value::Bool(value)) => value.to_string(),
+                None => null.clone(),
+            }
+        });
+
+        writer.write_record(record).map_err(|err| csv_error(&err))?;
+    }
+
+    let data = writer.into_inner().map_err(|err| QueryError::FileError(err.to_string()))?;
+    String::from_utf8(data).map_err(|err| QueryError::FileError(err.to_string()))
+}
+
+fn byte(c: char) -> Result<u8, QueryError> {
+    u8::try_from(c).ok().filter(u8::is_ascii).ok_or(QueryError::InvalidQuery)
+}
+
+fn csv_error(err: &::csv::Error) -> QueryError {
+    QueryError::InvalidCsv(vec![CsvRowError {
+        line: err.position().map_or(0, |position| position.line()),
+        message: err.to_string(),
+    }])
+}
+
+fn parse(field: &str, types: &Types) -> Option<Value> {
+    match types {
+        Types::Int => field.trim().parse().ok().map(Value::Int),
+        Types::Float => field.trim().parse().ok().map(Value::Float),
+        Types::String => Some(Value::String(field.to_string())),
+        Types::Bool => match field.trim() {
+            field if field.eq_ignore_ascii_case("true") => Some(Value::Bool(true)),
+            field if field.eq_ignore_ascii_case("false") => Some(Value::Bool(false)),
+            _ => None,
+        },
+    }
+}
+
+// the narrowest type every field can be read as, string for a column without any
+fn infer<'f>(fields: impl Iterator<Item = &'f str> + Clone) -> Types {
+    let mut present = fields.clone().peekable();
+    if present.peek().is_none() {
+        return Types::String;
+    }
+
+    [Types::Int, Types::Float, Types::Bool].into_iter()
+        .find(|types| fields.clone().all(|field| parse(field, types).is_some()))
+        .unwrap_or(Types::String)
+}
@@ -1,33 +1,606 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use serde_derive::{Deserialize, Serialize};
-use logger::Logger;
+use tokio::sync::MutexGuard;
+use logger::{Level, Logger};
+use crate::db_core::aggregate::{Aggregate, group_rows};
+#[cfg(feature = "arrow")]
+use crate::db_core::arrow::{self, ArrowExport, ArrowFormat, ArrowImport, ArrowSource};
+use crate::db_core::csv::{self, CsvExport, CsvImport, CsvOptions, CsvSource};
+use crate::db_core::interrupt::{Canceller, Interrupt};
+use crate::db_core::join::{Join, join_tables};
+use crate::db_core::ordering::{OrderBy, sort_rows};
+use crate::db_core::prepared::Prepared;
+use crate::db_core::query::Query;
 use crate::db_core::query_error::QueryError;
+use crate::db_core::storage::Store;
+use crate::db_core::transaction::Transaction;
 use crate::db_core::values::{Column, evaluate, Expr, ExprEvaluator, ToTypes, Types, Value};
 
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Table {
-    columns: Vec<Column>,
-    column_types: Vec<Types>,
+    pub(crate) columns: Vec<Column>,
+    pub(crate) column_types: Vec<Types>,
+
+    // per column, which rows hold no value. only results such as a left join produce nulls,
+    // so this is empty for stored tables and an inner vector is empty for a column without nulls
+    #[serde(default)]
+    pub(crate) nulls: Vec<Vec<bool>>,
+
+    // stays the same for the life of a stored table, unlike its position which shifts when an
+    // earlier table is dropped. 0 for tables that are only results
+    #[serde(default)]
+    pub(crate) oid: u64,
+}
+
+impl Table {
+    pub fn new(columns: Vec<Column>, column_types: Vec<Types>) -> Self {
+        Table {
+            columns,
+            column_types,
+            nulls: Vec::new(),
+            oid: 0,
+        }
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    pub fn column_types(&self) -> &[Types] {
+        &self.column_types
+    }
+
+    pub fn is_null(&self, column: usize, row: usize) -> bool {
+        self.nulls
+            .get(column)
+            .and_then(|nulls| nulls.get(row))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.columns.first().map(Column::len).unwrap_or(0)
+    }
+
+    // copies the given columns, in the given order, into a new table
+    pub fn project(&self, columns: &[usize]) -> Result<Table, QueryError> {
+        if columns.iter().any(|column| *column >= self.columns.len()) {
+            return Err(QueryError::ColumnNotFound);
+        }
+
+        Ok(Table {
+            columns: columns.iter().map(|column| self.columns[*column].clone()).collect(),
+            column_types: columns.iter().map(|column| self.column_types[*column].clone()).collect(),
+            nulls: match self.nulls.is_empty() {
+                true => Vec::new(),
+                false => columns.iter().map(|column| self.nulls[*column].clone()).collect(),
+            },
+            oid: 0,
+        })
+    }
+
+    // copies the given rows, in the given order, into a new table with the same column types
+    pub fn take_rows(&self, row_ids: &[usize]) -> Table {
+        Table {
+            columns: self.columns.iter().map(|column| column.take(row_ids)).collect(),
+            column_types: self.column_types.clone(),
+            nulls: self.nulls.iter().map(|nulls| {
+                if nulls.is_empty() {
+                    Vec::new()
+                } else {
+                    row_ids.iter().map(|row| nulls[*row]).collect()
+                }
+            }).collect(),
+            oid: 0,
+        }
+    }
+}
+
+// the rows of a bulk insert, given column by column or row by row
+#[derive(Clone, Deserialize, Serialize)]
+pub enum Batch {
+    // one column per column of the table, all of the same length
+    Columns(Vec<Column>),
+    Rows(Vec<Vec<Value>>),
+}
+
+impl Batch {
+    pub fn row_count(&self) -> usize {
+        match self {
+            Batch::Columns(columns) => columns.first().map(Column::len).unwrap_or(0),
+            Batch::Rows(rows) => rows.len(),
+        }
+    }
+}
+
+// queries are logged with their debug form, a batch would fill the log with its rows
+impl std::fmt::Debug for Batch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let layout = match self {
+            Batch::Columns(_) => "Columns",
+            Batch::Rows(_) => "Rows",
+        };
+
+        f.debug_struct("Batch")
+            .field("layout", &layout)
+            .field("rows", &self.row_count())
+            .finish_non_exhaustive()
+    }
+}
+
+// which rows a mutation sends back. a delete sends back nothing with `None` and the removed rows with either of the others
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Returning {
+    None,
+    Old,
+    New,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MutationResult {
+    // rows the condition selected
+    pub matched: usize,
+    // rows whose values were changed, or removed by a delete
+    pub modified: usize,
+    pub returning: Option<Table>,
+}
+
+// stops a statement once its query was cancelled or ran past its deadline. `$row` is the row a
+// scan is at, the interrupt is only looked at every so many rows
+macro_rules! interrupt {
+    ($interrupt:expr, $row:expr, $logger:expr, $table:expr, $operation:ident) => {
+        if let Err(err) = $interrupt.check_row($row) {
+            $logger.warn(
+                "Query Interrupted".to_string(),
+                format!("{} on table {}: {}", stringify!($operation), $table, err)
+            ).await;
+
+            return Err(err);
+        }
+    };
+}
+
+// runs a statement that writes to the tables. outside of a transaction the statement works on a fresh
+// snapshot while holding the store's writer lock and is published when it succeeds, inside of one
+// it works on the transaction's tables. either way a statement that fails part way leaves no trace
+macro_rules! write_statement {
+    ($db:ident, $statement:expr) => {{
+        let store = $db.store.clone();
+        let res = match $db.begin_statement(&store).await {
+            Ok(writer) => {
+                let res = $statement.await;
+                let res = $db.end_statement(res).await;
+                drop(writer);
+                res
+            }
+            Err(err) => Err(err),
+        };
+        res
+    }};
+}
+
+// what the last statement of a session did, for the slow query log
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct QueryStats {
+    // rows the statement looked at, including the ones its condition rejected
+    pub rows_scanned: usize,
+    // rows sent back, or changed by a mutation
+    pub rows_returned: usize,
 }
 
 #[derive(Debug)]
-pub struct Database<'a > {
-    tables: Vec<Table>,
-    pub logger: &'a mut Logger,
+pub struct Database {
+    store: Arc<Store>,
+    // the session's view of the tables: the snapshot of the running statement or transaction,
+    // plus the private copies of every table it wrote to
+    tables: Vec<Arc<Table>>,
+    schema_changed: bool,
+    // the versions of the tables before the running statement of a transaction, to undo it should it fail
+    statement_backup: Option<(Vec<Arc<Table>>, bool)>,
+    transaction: Option<Transaction>,
+    stats: QueryStats,
+    interrupt: Interrupt,
+    // the statements the session prepared, by name
+    statements: HashMap<String, Prepared>,
+    // where files named by imports and exports on the server are kept, they are refused without one
+    file_dir: Option<PathBuf>,
+    // the tables the running query was checked against, by its privileges or when it was prepared, as position
+    // and oid. a statement that finds another table at one of the positions fails instead of touching a table
+    // nobody checked
+    pinned: Vec<(usize, u64)>,
+    pub logger: Logger,
 }
 
-impl<'a > Database<'a > {
-    pub fn new(logger: &'a mut Logger) -> Self {
+impl Database {
+    pub fn new(logger: Logger) -> Self {
+        Database::with_store(logger, Arc::new(Store::new()))
+    }
+
+    // a session on tables shared with every other session of the same store
+    pub fn with_store(logger: Logger, store: Arc<Store>) -> Self {
         Database {
+            store,
             tables: Vec::new(),
+            schema_changed: false,
+            statement_backup: None,
+            transaction: None,
+            stats: QueryStats::default(),
+            interrupt: Interrupt::default(),
+            statements: HashMap::new(),
+            file_dir: None,
+            pinned: Vec::new(),
             logger
         }
     }
 
+    // `dir` has to be canonical, paths are checked against it after their symlinks were followed
+    pub fn set_file_dir(&mut self, dir: Option<PathBuf>) {
+        self.file_dir = dir;
+    }
+
+    // the stats of the statements run since the last call
+    pub fn take_stats(&mut self) -> QueryStats {
+        std::mem::take(&mut self.stats)
+    }
+
+    // the oid of the table at `table` as the session currently sees it
+    pub fn table_oid(&mut self, table: usize) -> Option<u64> {
+        self.refresh();
+        self.tables.get(table).map(|table| table.oid)
+    }
+
+    // to be called before every query with the tables it was checked against
+    pub fn pin_tables(&mut self, tables: Vec<(usize, u64)>) {
+        self.pinned = tables;
+    }
+
+    // stops the query the session is running when called from another task
+    pub fn canceller(&self) -> Canceller {
+        self.interrupt.canceller()
+    }
+
+    // to be called before every query. the query fails with a timeout once it runs for longer than `timeout`
+    pub fn start_query(&mut self, timeout: Option<Duration>) {
+        self.interrupt.start(timeout);
+    }
+
+    fn record_stats(&mut self, rows_scanned: usize, rows_returned: usize) {
+        self.stats.rows_scanned += rows_scanned;
+        self.stats.rows_returned += rows_returned;
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    // outside of a transaction every statement sees the latest committed tables
+    fn refresh(&mut self) {
+        if self.transaction.is_none() {
+            self.tables = self.store.snapshot().tables;
+            self.schema_changed = false;
+        }
+    }
+
+    // the latest committed tables, failing when a table the query was checked against is no longer where it was
+    async fn refresh_pinned(&mut self) -> Result<(), QueryError> {
+        self.refresh();
+
+        let tables = &self.tables;
+        let moved = self.pinned.iter()
+            .find(|(table, oid)| tables.get(*table).map(|table| table.oid) != Some(*oid));
+
+        if let Some((table, _)) = moved {
+            self.logger.warn(
+                "Query Denied".to_string(),
+                format!("table {} was dropped or replaced after the query was checked", table)
+            ).await;
+
+            return Err(QueryError::PermissionDenied);
+        }
+
+        Ok(())
+    }
+
+    async fn begin_statement<'s>(&mut self, store: &'s Store) -> Result<Option<MutexGuard<'s, ()>>, QueryError> {
+        if self.transaction.is_some() {
+            self.refresh_pinned().await?;
+            self.statement_backup = Some((self.tables.clone(), self.schema_changed));
+            return Ok(None);
+        }
+
+        // checked under the lock, so no other writer moves the tables before the statement is done
+        let writer = store.lock_writer().await;
+        self.refresh_pinned().await?;
+
+        Ok(Some(writer))
+    }
+
+    async fn end_statement<T>(&mut self, res: Result<T, QueryError>) -> Result<T, QueryError> {
+        let backup = self.statement_backup.take();
+
+        match (&res, self.transaction.is_some()) {
+            (Ok(_), true) => {}
+            (Ok(_), false) => {
+                self.store.publish(self.tables.clone(), self.schema_changed);
+                self.schema_changed = false;
+            }
+            (Err(err), in_transaction) => {
+                if in_transaction {
+                    if let Some((tables, schema_changed)) = backup {
+                        self.tables = tables;
+                        self.schema_changed = schema_changed;
+                    }
+                } else {
+                    self.refresh();
+                }
+
+                self.logger.warn(
+                    "Statement Rolled Back".to_string(),
+                    format!("statement failed and was undone: {}", err)
+                ).await;
+            }
+        }
+
+        res
+    }
+
+    pub async fn begin(&mut self) -> Result<(), QueryError> {
+        if self.transaction.is_some() {
+            self.logger.error(
+                "Transaction In Progress".to_string(),
+                "begin: a transaction is already in progress".to_string()
+            ).await;
+
+            return Err(QueryError::TransactionInProgress);
+        }
+
+        let base = self.store.snapshot();
+        self.tables = base.tables.clone();
+        self.schema_changed = false;
+        self.transaction = Some(Transaction::begin(base));
+
+        self.logger.info("Begin".to_string(), "transaction started".to_string()).await;
+        Ok(())
+    }
+
+    pub async fn commit(&mut self) -> Result<(), QueryError> {
+        let transaction = match self.transaction.take() {
+            Some(transaction) => transaction,
+            None => {
+                self.logger.error(
+                    "No Transaction".to_string(),
+                    "commit: no transaction in progress".to_string()
+                ).await;
+
+                return Err(QueryError::NoTransaction);
+            }
+        };
+
+        let committed = {
+            let _writer = self.store.lock_writer().await;
+            self.store.commit(&transaction.base, std::mem::take(&mut self.tables), self.schema_changed)
+        };
+        self.schema_changed = false;
+
+        if !committed {
+            self.logger.error(
+                "Write Conflict".to_string(),
+                "commit: a table written by the transaction was changed by another session, the transaction was rolled back".to_string()
+            ).await;
+
+            return Err(QueryError::WriteConflict);
+        }
+
+        self.logger.info("Commit".to_string(), "transaction committed".to_string()).await;
+        Ok(())
+    }
+
+    pub async fn rollback(&mut self) -> Result<(), QueryError> {
+        match self.transaction.take() {
+            Some(_) => {
+                self.refresh();
+
+                self.logger.info("Rollback".to_string(), "transaction rolled back".to_string()).await;
+                Ok(())
+            }
+            None => {
+                self.logger.error(
+                    "No Transaction".to_string(),
+                    "rollback: no transaction in progress".to_string()
+                ).await;
+
+                Err(QueryError::NoTransaction)
+            }
+        }
+    }
+
+    pub async fn savepoint(&mut self, name: String) -> Result<(), QueryError> {
+        match self.transaction.as_mut() {
+            Some(transaction) => {
+                transaction.savepoint(name.clone(), &self.tables, self.schema_changed);
+
+                self.logger.info("Savepoint".to_string(), format!("savepoint {} created", name)).await;
+                Ok(())
+            }
+            None => {
+                self.logger.error(
+                    "No Transaction".to_string(),
+                    format!("savepoint {}: no transaction in progress", name)
+                ).await;
+
+                Err(QueryError::NoTransaction)
+            }
+        }
+    }
+
+    pub async fn rollback_to(&mut self, name: String) -> Result<(), QueryError> {
+        let tables = match self.transaction.as_mut() {
+            Some(transaction) => transaction.rollback_to(&name),
+            None => {
+                self.logger.error(
+                    "No Transaction".to_string(),
+                    format!("rollback to {}: no transaction in progress", name)
+                ).await;
+
+                return Err(QueryError::NoTransaction);
+            }
+        };
+
+        match tables {
+            Some((tables, schema_changed)) => {
+                self.tables = tables;
+                self.schema_changed = schema_changed;
+
+                self.logger.info("Rollback".to_string(), format!("rolled back to savepoint {}", name)).await;
+                Ok(())
+            }
+            None => {
+                self.logger.error(
+                    "Savepoint Not Found".to_string(),
+                    format!("rollback to {}: savepoint not found", name)
+                ).await;
+
+                Err(QueryError::SavepointNotFound)
+            }
+        }
+    }
+
+    pub async fn release(&mut self, name: String) -> Result<(), QueryError> {
+        let released = match self.transaction.as_mut() {
+            Some(transaction) => transaction.release(&name),
+            None => {
+                self.logger.error(
+                    "No Transaction".to_string(),
+                    format!("release {}: no transaction in progress", name)
+                ).await;
+
+                return Err(QueryError::NoTransaction);
+            }
+        };
+
+        if !released {
+            self.logger.error(
+                "Savepoint Not Found".to_string(),
+                format!("release {}: savepoint not found", name)
+            ).await;
+
+            return Err(QueryError::SavepointNotFound);
+        }
+
+        self.logger.info("Release".to_string(), format!("released savepoint {}", name)).await;
+        Ok(())
+    }
+
+    // checks `query` against the tables as the session sees them and keeps it under `name` until deallocated
+    pub async fn prepare(&mut self, name: String, query: Query) -> Result<(), QueryError> {
+        if self.statements.contains_key(&name) {
+            self.logger.error(
+                "Statement Exists".to_string(),
+                format!("prepare {}: a statement with this name exists", name)
+            ).await;
+
+            return Err(QueryError::StatementExists);
+        }
+
+        self.refresh();
+        let tables = &self.tables;
+
+        match Prepared::new(query, |table| tables.get(table).map(|table| (table.oid, table.column_types()))) {
+            Ok(prepared) => {
+                self.logger.info(
+                    "Prepare".to_string(),
+                    format!("prepared statement {} with {} parameters", name, prepared.params().len())
+                ).await;
+                self.statements.insert(name, prepared);
+
+                Ok(())
+            }
+            Err(err) => {
+                self.logger.error(
+                    "Prepare Failed".to_string(),
+                    format!("prepare {}: {}", name, err)
+                ).await;
+
+                Err(err)
+            }
+        }
+    }
+
+    // the prepared statement `name` with `params` bound to its parameters, ready to run on the tables it was
+    // prepared against. a statement whose tables were dropped or replaced since is thrown away
+    pub async fn bind(&mut self, name: &str, params: &[Value]) -> Result<(Query, Vec<(usize, u64)>), QueryError> {
+        self.refresh();
+        let tables = &self.tables;
+
+        let stale = self.statements.get(name).is_some_and(|prepared| {
+            prepared.tables().iter().any(|(table, oid)| tables.get(*table).map(|table| table.oid) != Some(*oid))
+        });
+        if stale {
+            self.statements.remove(name);
+            self.logger.warn(
+                "Statement Invalidated".to_string(),
+                format!("execute {}: a table it was prepared against was dropped or replaced, it has to be prepared again", name)
+            ).await;
+
+            return Err(QueryError::StatementNotFound);
+        }
+
+        let res = match self.statements.get(name) {
+            Some(prepared) => prepared.bind(params).map(|query| (query, prepared.tables().to_vec())),
+            None => Err(QueryError::StatementNotFound),
+        };
+
+        if let Err(err) = &res {
+            self.logger.error(
+                "Bind Failed".to_string(),
+                format!("execute {}: {}", name, err)
+            ).await;
+        }
+
+        res
+    }
+
+    pub async fn deallocate(&mut self, name: &str) -> Result<(), QueryError> {
+        if self.statements.remove(name).is_none() {
+            self.logger.error(
+                "Statement Not Found".to_string(),
+                format!("deallocate {}: statement not found", name)
+            ).await;
+
+            return Err(QueryError::StatementNotFound);
+        }
+
+        self.logger.info("Deallocate".to_string(), format!("deallocated statement {}", name)).await;
+        Ok(())
+    }
+
+    // the table for reading, shared with the snapshot it came from
+    pub async fn read_table(&mut self, id: usize) -> Result<&Table, QueryError> {
+        match self.tables.get(id) {
+            Some(table) => {
+                Ok(table)
+            }
+            None => {
+                self.logger.error(
+                    "Table Not Found".to_string(),
+                    format!("table {} not found", id)
+                ).await;
+
+                Err(QueryError::TableNotFound)
+            }
+        }
+    }
+
+    // the table for writing. the first write to a table in a statement or transaction copies it, sharing
+    // the chunks of its columns, so other snapshots keep seeing the version they started with
     pub async fn get_table(&mut self, id: usize) -> Result<&mut Table, QueryError> {
         match self.tables.get_mut(id) {
             Some(table) => {
-                Ok(table)
+                Ok(Arc::make_mut(table))
             }
             None => {
                 self.logger.error(
@@ -41,13 +614,17 @@ impl<'a > Database<'a > {
     }
 
     pub async fn select_table(&mut self, id: usize) -> Result<Table, QueryError> {
-       Ok(self.get_table(id).await?.clone())
+        self.refresh_pinned().await?;
+        let table = self.read_table(id).await?.clone();
+
+        self.record_stats(table.row_count(), table.row_count());
+        Ok(table)
     }
 
-    pub async fn get_table_with_column_check(&mut self, id: usize, column: usize) -> Result<&mut Table, QueryError> {
-        match self.tables.get_mut(id) {
+    pub async fn read_table_with_column_check(&mut self, id: usize, column: usize) -> Result<&Table, QueryError> {
+        match self.tables.get(id) {
             Some(table) => {
-                if table.columns.len() < column {
+                if table.columns.len() <= column {
                     self.logger.error(
                         "Column Not Found".to_string(),
                         format!("column {} not found in table {}", column, id)
@@ -69,29 +646,60 @@ impl<'a > Database<'a > {
         }
     }
 
-    pub async fn add_table(&mut self, column_types: Vec<Types>) {
-        let columns = column_types.iter().map(|col| {
-            match col {
-                Types::Int => Column::Int(Vec::new()),
-                Types::Float => Column::Float(Vec::new()),
-                Types::String => Column::String(Vec::new()),
-                Types::Bool => Column::Bool(Vec::new()),
+    pub async fn get_table_with_column_check(&mut self, id: usize, column: usize) -> Result<&mut Table, QueryError> {
+        match self.tables.get_mut(id) {
+            Some(table) => {
+                if table.columns.len() <= column {
+                    self.logger.error(
+                        "Column Not Found".to_string(),
+                        format!("column {} not found in table {}", column, id)
+                    ).await;
+
+                    return Err(QueryError::ColumnNotFound);
+                }
+
+                Ok(Arc::make_mut(table))
             }
-        }).collect::<Vec<Column>>();
+            None => {
+                self.logger.error(
+                    "Table Not Found".to_string(),
+                    format!("table {} not found", id)
+                ).await;
 
-        self.tables.push(Table {
-            columns,
-            column_types,
-        });
+                Err(QueryError::TableNotFound)
+            }
+        }
+    }
+
+    // the position of the new table. fails when a table the session's privileges were checked on has
+    // been dropped or replaced since
+    pub async fn add_table(&mut self, column_types: Vec<Types>) -> Result<usize, QueryError> {
+        write_statement!(self, self.create_table(column_types))
+    }
+
+    async fn create_table(&mut self, column_types: Vec<Types>) -> Result<usize, QueryError> {
+        let columns = column_types.iter().map(Column::empty).collect::<Vec<Column>>();
+
+        let mut table = Table::new(columns, column_types);
+        table.oid = self.store.allocate_oid();
+
+        self.tables.push(Arc::new(table));
+        self.schema_changed = true;
 
         self.logger.info(
             "Table Added".to_string(),
             format!("added table {} to database", self.tables.len() - 1)
         ).await;
+
+        Ok(self.tables.len() - 1)
     }
 
-    pub async fn drop_table(&mut self, id: usize) -> Result<(), QueryError>{
-        if self.tables.len() < id {
+    pub async fn drop_table(&mut self, id: usize) -> Result<(), QueryError> {
+        write_statement!(self, self.remove_table(id))
+    }
+
+    async fn remove_table(&mut self, id: usize) -> Result<(), QueryError>{
+        if self.tables.len() <= id {
             self.logger.error(
                 "Table Not Found".to_string(),
                 format!("table {} not found", id)
@@ -100,6 +708,7 @@ impl<'a > Database<'a > {
             return Err(QueryError::TableNotFound);
         }
         self.tables.remove(id);
+        self.schema_changed = true;
 
         self.logger.info(
             "Table Dropped".to_string(),
@@ -109,20 +718,26 @@ impl<'a > Database<'a > {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn select(
         &mut self,
         table: usize,
         column_target: usize,
 
         #[allow(unused_variables)]
-        condition: Vec<Expr>
+        condition: Vec<Expr>,
 
+        order_by: Vec<OrderBy>,
+        limit: Option<usize>,
+        offset: usize,
     ) -> Result<Table, QueryError> {
         let mut row_ids: Vec<usize> = Vec::new();
 
-        let target_table = self.get_table_with_column_check(table, column_target).await?;
+        self.refresh_pinned().await?;
+        let interrupt = self.interrupt.clone();
+        let target_table = self.read_table_with_column_check(table, column_target).await?;
 
-        if condition.len() == 0 {
+        if condition.is_empty() {
             self.logger.error(
                 "No Condition".to_string(),
                 format!("select on table {}: no condition given", table)
@@ -131,9 +746,31 @@ impl<'a > Database<'a > {
             return Err(QueryError::NoOperation);
         }
 
+        if let Some(key) = order_by.iter().find(|key| key.column >= target_table.columns.len()) {
+            self.logger.error(
+                "Column Not Found".to_string(),
+                format!("select on table {}: order by column {} not found", table, key.column)
+            ).await;
+
+            return Err(QueryError::ColumnNotFound);
+        }
+
+        // without an order the first `offset + limit` matches are the result, so the scan can stop early
+        let enough = match limit {
+            Some(limit) if order_by.is_empty() => offset.saturating_add(limit),
+            _ => usize::MAX,
+        };
+        let mut scanned = 0;
+
         macro_rules! check {
             ($values:expr, $type_col:ident) => {
                 for (row_id, row_value) in $values.iter().enumerate() {
+                    interrupt!(interrupt, row_id, self.logger, table, select);
+                    if row_ids.len() >= enough {
+                        break;
+                    }
+                    scanned += 1;
+
                     let res = evaluate!(condition.clone(), row_value.clone(), $type_col, self.logger, table, column_target, select);
                     if let Value::Bool(true) = res {
                         row_ids.push(row_id);
@@ -142,85 +779,267 @@ impl<'a > Database<'a > {
             };
         }
 
-        match target_table.columns.get_mut(column_target) {
-            Some(column) => {
-                match column {
-                    Column::Int(values) => {
-                        check!(values, Int);
-                    }
-                    Column::Float(values) => {
-                        check!(values, Float);
-                    }
-                    Column::String(values) => {
-                        check!(values, String);
+        if let Some(column) = target_table.columns.get(column_target) {
+            match column {
+                Column::Int(values) => {
+                    check!(values, Int);
+                }
+                Column::Float(values) => {
+                    check!(values, Float);
+                }
+                Column::String(values) => {
+                    check!(values, String);
+                }
+                Column::Bool(values) => {
+                    check!(values, Bool);
+                }
+            }
+        }
+
+        let matched = row_ids.len();
+        sort_rows(&target_table.columns, &mut row_ids, &order_by, limit, offset);
+
+        let new_table = target_table.take_rows(&row_ids);
+        self.record_stats(scanned, row_ids.len());
+
+        self.logger.info(
+            "Select".to_string(),
+            format!(
+                "Select query executed on table {} with column {:?}, {} rows matched and {} rows returned",
+                table,
+                column_target,
+                matched,
+                row_ids.len()
+            )
+        ).await;
+
+        Ok(new_table)
+    }
+
+    pub async fn aggregate(
+        &mut self,
+        table: usize,
+        group_by: Vec<usize>,
+        aggregates: Vec<Aggregate>,
+        having: Option<(usize, Vec<Expr>)>,
+    ) -> Result<Table, QueryError> {
+        self.refresh_pinned().await?;
+        let interrupt = self.interrupt.clone();
+        let target_table = self.read_table(table).await?;
+        let column_count = target_table.columns.len();
+
+        let mut referenced = group_by.iter().copied().chain(aggregates.iter().map(|aggregate| aggregate.column));
+        if let Some(column) = referenced.find(|column| *column >= column_count) {
+            self.logger.error(
+                "Column Not Found".to_string(),
+                format!("aggregate on table {}: column {} not found", table, column)
+            ).await;
+
+            return Err(QueryError::ColumnNotFound);
+        }
+
+        // the result has the group by columns first, followed by one column per aggregate
+        let mut column_types = group_by.iter()
+            .map(|column| target_table.column_types[*column].clone())
+            .collect::<Vec<Types>>();
+
+        for aggregate in &aggregates {
+            match aggregate.function.output_type(&target_table.column_types[aggregate.column]) {
+                Ok(types) => column_types.push(types),
+                Err(err) => {
+                    self.logger.error(
+                        "Type Mismatch".to_string(),
+                        format!(
+                            "aggregate on table {}: {:?} can not be applied to column {}",
+                            table,
+                            aggregate.function,
+                            aggregate.column
+                        )
+                    ).await;
+
+                    return Err(err);
+                }
+            }
+        }
+
+        let scanned = target_table.row_count();
+        let groups = match group_rows(&target_table.columns, &group_by, scanned, &interrupt) {
+            Ok(groups) => groups,
+            Err(err) => {
+                self.logger.warn(
+                    "Query Interrupted".to_string(),
+                    format!("aggregate on table {}: {}", table, err)
+                ).await;
+
+                return Err(err);
+            }
+        };
+        let mut columns = column_types.iter().map(Column::empty).collect::<Vec<Column>>();
+        let mut nulls = vec![Vec::new(); column_types.len()];
+
+        for (group, rows) in groups.iter().enumerate() {
+            interrupt!(interrupt, group, self.logger, table, aggregate);
+
+            // only the one group of an aggregate without group by can be empty
+            for (id, column) in group_by.iter().enumerate() {
+                columns[id].push(target_table.columns[*column].get(rows[0]).unwrap())?;
+            }
+
+            for (id, aggregate) in aggregates.iter().enumerate() {
+                let column = group_by.len() + id;
+
+                match aggregate.function.apply(&target_table.columns[aggregate.column], rows) {
+                    Ok(Some(value)) => columns[column].push(value)?,
+                    Ok(None) => {
+                        columns[column].push(column_types[column].default_value())?;
+                        nulls[column].resize(group, false);
+                        nulls[column].push(true);
                     }
-                    Column::Bool(values) => {
-                        check!(values, Bool);
+                    Err(err) => {
+                        self.logger.error(
+                            "Aggregate Failed".to_string(),
+                            format!("aggregate on table {}: {:?} of column {}: {}", table, aggregate.function, aggregate.column, err)
+                        ).await;
+
+                        return Err(err);
                     }
                 }
             }
-            None => {}
         }
 
-        let mut new_columns = Vec::new();
+        let mut result = Table::new(columns, column_types);
+        if nulls.iter().any(|nulls| !nulls.is_empty()) {
+            for nulls in nulls.iter_mut().filter(|nulls| !nulls.is_empty()) {
+                nulls.resize(groups.len(), false);
+            }
+            result.nulls = nulls;
+        }
 
+        if let Some((having_column, condition)) = having {
+            let mut row_ids: Vec<usize> = Vec::new();
 
-        for column in target_table.columns.iter() {
-            match column {
-                Column::Int(int_col) => {
-                    let mut values = vec![];
-                    for row in &row_ids {
-                        let value = int_col.get(*row).unwrap();
-                        values.push(value.clone())
+            // a null aggregate never matches
+            macro_rules! check {
+                ($values:expr, $type_col:ident) => {
+                    for (row_id, row_value) in $values.iter().enumerate() {
+                        interrupt!(interrupt, row_id, self.logger, table, aggregate);
+                        if result.is_null(having_column, row_id) {
+                            continue;
+                        }
+                        let res = evaluate!(condition.clone(), row_value.clone(), $type_col, self.logger, table, having_column, aggregate);
+                        if let Value::Bool(true) = res {
+                            row_ids.push(row_id);
+                        }
                     }
-                    new_columns.push(Column::Int(values))
+                };
+            }
+
+            match result.columns.get(having_column) {
+                Some(Column::Int(values)) => {
+                    check!(values, Int)
                 }
-                Column::Float(float_col) => {
-                    let mut values = vec![];
-                    for row in &row_ids {
-                        let value = float_col.get(*row).unwrap();
-                        values.push(value.clone())
-                    }
-                    new_columns.push(Column::Float(values))
+                Some(Column::Float(values)) => {
+                    check!(values, Float)
                 }
-                Column::String(string_col) => {
-                    let mut values = vec![];
-                    for row in &row_ids {
-                        let value = string_col.get(*row).unwrap();
-                        values.push(value.clone())
-                    }
-                    new_columns.push(Column::String(values))
+                Some(Column::String(values)) => {
+                    check!(values, String)
                 }
-                Column::Bool(bool_col) => {
-                    let mut values = vec![];
-                    for row in &row_ids {
-                        let value = bool_col.get(*row).unwrap();
-                        values.push(value.clone())
-                    }
-                    new_columns.push(Column::Bool(values))
+                Some(Column::Bool(values)) => {
+                    check!(values, Bool)
+                }
+                None => {
+                    self.logger.error(
+                        "Column Not Found".to_string(),
+                        format!("aggregate on table {}: having column {} not found in result", table, having_column)
+                    ).await;
+
+                    return Err(QueryError::ColumnNotFound);
                 }
             }
+
+            result = result.take_rows(&row_ids);
         }
 
-        let new_table = Table {
-            columns: new_columns,
-            column_types: target_table.column_types.clone()
-        };
+        self.record_stats(scanned, result.row_count());
+
+        self.logger.info(
+            "Aggregate".to_string(),
+            format!(
+                "Aggregate query executed on table {} with {} groups and {} rows returned",
+                table,
+                groups.len(),
+                result.row_count()
+            )
+        ).await;
+
+        Ok(result)
+    }
+
+    pub async fn join(
+        &mut self,
+        table: usize,
+        joins: Vec<Join>,
+        columns: Vec<usize>,
+    ) -> Result<Table, QueryError> {
+        self.refresh_pinned().await?;
+
+        for id in std::iter::once(table).chain(joins.iter().map(|join| join.table)) {
+            if id >= self.tables.len() {
+                self.logger.error(
+                    "Table Not Found".to_string(),
+                    format!("table {} not found", id)
+                ).await;
+
+                return Err(QueryError::TableNotFound);
+            }
+        }
+
+        let joined = joins.iter()
+            .map(|join| (&*self.tables[join.table], join))
+            .collect::<Vec<_>>();
+
+        let scanned = self.tables[table].row_count() + joined.iter().map(|(table, _)| table.row_count()).sum::<usize>();
+
+        match join_tables(&self.tables[table], &joined, &columns, &self.interrupt) {
+            Ok(result) => {
+                self.record_stats(scanned, result.row_count());
+
+                self.logger.info(
+                    "Join".to_string(),
+                    format!(
+                        "Join query executed on table {} with {} joins and {} rows returned",
+                        table,
+                        joins.len(),
+                        result.row_count()
+                    )
+                ).await;
+
+                Ok(result)
+            }
+            Err(err @ (QueryError::Cancelled | QueryError::Timeout)) => {
+                self.logger.warn(
+                    "Query Interrupted".to_string(),
+                    format!("join on table {}: {}", table, err)
+                ).await;
 
-        self.logger.info(
-            "Select".to_string(),
-            format!(
-                "Select query executed on table {} with column {:?} and {} rows returned",
-                table,
-                column_target,
-                row_ids.len()
-            )
-        ).await;
+                Err(err)
+            }
+            Err(err) => {
+                self.logger.error(
+                    "Join Failed".to_string(),
+                    format!("join on table {}: {}", table, err)
+                ).await;
 
-        Ok(new_table)
+                Err(err)
+            }
+        }
     }
 
     pub async fn insert(&mut self, table: usize, value: Vec<Value>) -> Result<(), QueryError> {
+        write_statement!(self, self.insert_row(table, value))
+    }
+
+    async fn insert_row(&mut self, table: usize, value: Vec<Value>) -> Result<(), QueryError> {
         let target_table = self.get_table(table).await?;
 
         // check if the number of values is equal to the number of columns
@@ -262,24 +1081,21 @@ impl<'a > Database<'a > {
         }
 
         for (id, val) in value.iter().enumerate() {
-            match target_table.columns.get_mut(id) {
-                Some(column) => {
-                    match column {
-                        Column::Int(int_vals) => {
-                            check!(id, int_vals, val, Int)
-                        }
-                        Column::Float(float_vals) => {
-                            check!(id, float_vals, val, Float)
-                        }
-                        Column::String(string_vals) => {
-                            check!(id, string_vals, val, String)
-                        }
-                        Column::Bool(bool_vals) => {
-                            check!(id, bool_vals, val, Bool)
-                        }
+            if let Some(column) = target_table.columns.get_mut(id) {
+                match column {
+                    Column::Int(int_vals) => {
+                        check!(id, int_vals, val, Int)
+                    }
+                    Column::Float(float_vals) => {
+                        check!(id, float_vals, val, Float)
+                    }
+                    Column::String(string_vals) => {
+                        check!(id, string_vals, val, String)
+                    }
+                    Column::Bool(bool_vals) => {
+                        check!(id, bool_vals, val, Bool)
                     }
                 }
-                None => {}
             }
         }
 
@@ -293,16 +1109,328 @@ impl<'a > Database<'a > {
         Ok(())
     }
 
+    // appends every row of the batch or, when one of them does not fit the table, none
+    pub async fn insert_batch(&mut self, table: usize, batch: Batch) -> Result<(), QueryError> {
+        write_statement!(self, self.insert_rows(table, batch))
+    }
+
+    async fn insert_rows(&mut self, table: usize, batch: Batch) -> Result<(), QueryError> {
+        let interrupt = self.interrupt.clone();
+        let column_types = self.read_table(table).await?.column_types.clone();
+        let row_count = batch.row_count();
+
+        // rows are turned into columns, so both layouts are checked and appended the same way
+        let columns = match batch {
+            Batch::Columns(columns) => columns,
+            Batch::Rows(rows) => {
+                let mut columns: Vec<Column> = column_types.iter().map(Column::empty).collect();
+
+                for (row_id, row) in rows.into_iter().enumerate() {
+                    interrupt!(interrupt, row_id, self.logger, table, insert);
+
+                    if row.len() != columns.len() {
+                        self.logger.error(
+                            "Size Mismatch".to_string(),
+                            format!("batch insertion on table {}: row {} does not have a value for every column", table, row_id)
+                        ).await;
+
+                        return Err(QueryError::SizeMismatch);
+                    }
+
+                    for (column, value) in columns.iter_mut().zip(row) {
+                        if column.push(value).is_err() {
+                            self.logger.error(
+                                "Type Mismatch".to_string(),
+                                format!("batch insertion on table {}: row {} has values of the wrong types", table, row_id)
+                            ).await;
+
+                            return Err(QueryError::TypeMismatch);
+                        }
+                    }
+                }
+
+                columns
+            }
+        };
+
+        if columns.len() != column_types.len() || columns.iter().any(|column| column.len() != row_count) {
+            self.logger.error(
+                "Size Mismatch".to_string(),
+                format!("batch insertion on table {}: columns do not match the table's or differ in length", table)
+            ).await;
+
+            return Err(QueryError::SizeMismatch);
+        }
+
+        if columns.iter().map(|column| column.to_types()).ne(column_types.iter().cloned()) {
+            self.logger.error(
+                "Type Mismatch".to_string(),
+                format!("batch insertion on table {}: column types are not the same as the table's", table)
+            ).await;
+
+            return Err(QueryError::TypeMismatch);
+        }
+
+        // checked above, so appending cannot fail halfway through
+        let target_table = self.get_table(table).await?;
+        for (column, values) in target_table.columns.iter_mut().zip(columns) {
+            column.append(values)?;
+        }
+
+        self.record_stats(0, row_count);
+
+        self.logger.info(
+            "Insert Batch".to_string(),
+            format!("Insert batch query executed on table {}, {} rows inserted", table, row_count)
+        ).await;
+        Ok(())
+    }
+
+    // reads csv rows into `table`, or into a new table typed after the rows when there is none. the rows
+    // are inserted all at once, or not at all when more of them fail than `max_errors` allows
+    pub async fn import_csv(&mut self, source: CsvSource, table: Option<usize>, options: CsvOptions) -> Result<CsvImport, QueryError> {
+        let text = match source {
+            CsvSource::Data(data) => data,
+            CsvSource::File(path) => {
+                let data = self.read_file(&path, "csv import").await?;
+                String::from_utf8(data).map_err(|err| QueryError::FileError(err.to_string()))?
+            }
+        };
+
+        // rows are parsed against the types the table has now, insert_rows checks them again under the lock
+        self.refresh_pinned().await?;
+        let column_types = match table {
+            Some(table) => Some(self.read_table(table).await?.column_types.clone()),
+            None => None,
+        };
+
+        let rows = match csv::read(&text, &options, column_types.as_deref(), &self.interrupt) {
+            Ok(rows) => rows,
+            Err(err) => {
+                self.logger.error(
+                    "Csv Import Failed".to_string(),
+                    format!("csv import into {}: {}", table.map_or("a new table".to_string(), |table| format!("table {}", table)), err)
+                ).await;
+
+                return Err(err);
+            }
+        };
+
+        let row_count = rows.columns.first().map_or(0, Column::len);
+        let table = write_statement!(self, self.store_columns(table, rows.types, rows.columns))?;
+
+        self.logger.info(
+            "Csv Imported".to_string(),
+            format!("imported {} rows into table {}, {} rows skipped", row_count, table, rows.errors.len())
+        ).await;
+
+        Ok(CsvImport {
+            table,
+            rows: row_count,
+            errors: rows.errors,
+        })
+    }
+
+    // the rows of an import, appended to `table` or to a new table when there is none
+    async fn store_columns(&mut self, table: Option<usize>, column_types: Vec<Types>, columns: Vec<Column>) -> Result<usize, QueryError> {
+        let table = match table {
+            Some(table) => table,
+            None => self.create_table(column_types).await?,
+        };
+
+        self.insert_rows(table, Batch::Columns(columns)).await?;
+        Ok(table)
+    }
+
+    // `table` as csv, written to `path` on the server or sent back when there is none
+    pub async fn export_csv(&mut self, table: &Table, path: Option<PathBuf>, options: CsvOptions) -> Result<CsvExport, QueryError> {
+        let data = csv::write(table, &options)?;
+        let rows = table.row_count();
+
+        let Some(path) = path else {
+            return Ok(CsvExport { rows, data: Some(data) });
+        };
+
+        self.write_file(&path, data.into_bytes(), "csv export").await?;
+        self.logger.info(
+            "Csv Exported".to_string(),
+            format!("exported {} rows to {}", rows, path.display())
+        ).await;
+
+        Ok(CsvExport { rows, data: None })
+    }
+
+    // reads an arrow ipc or parquet file into `table`, or into a new table typed after the file's columns
+    // when there is none. files holding nulls can not be imported, stored tables have none
+    #[cfg(feature = "arrow")]
+    pub async fn import_arrow(&mut self, source: ArrowSource, format: ArrowFormat, table: Option<usize>) -> Result<ArrowImport, QueryError> {
+        let data = match source {
+            ArrowSource::Data(data) => data,
+            ArrowSource::File(path) => self.read_file(&path, "arrow import").await?,
+        };
+
+        self.refresh_pinned().await?;
+        let column_types = match table {
+            Some(table) => Some(self.read_table(table).await?.column_types.clone()),
+            None => None,
+        };
+
+        let imported = arrow::read(data, format, column_types.as_deref(), &self.interrupt).and_then(|imported| {
+            match imported.nulls.iter().position(|nulls| nulls.contains(&true)) {
+                Some(column) => Err(QueryError::InvalidArrow(format!("column c{} holds nulls", column))),
+                None => Ok(imported),
+            }
+        });
+
+        let imported = match imported {
+            Ok(imported) => imported,
+            Err(err) => {
+                self.logger.error(
+                    "Arrow Import Failed".to_string(),
+                    format!("{:?} import into {}: {}", format, table.map_or("a new table".to_string(), |table| format!("table {}", table)), err)
+                ).await;
+
+                return Err(err);
+            }
+        };
+
+        let rows = imported.row_count();
+        let table = write_statement!(self, self.store_columns(table, imported.column_types, imported.columns))?;
+
+        self.logger.info(
+            "Arrow Imported".to_string(),
+            format!("imported {} rows into table {} from {:?}", rows, table, format)
+        ).await;
+
+        Ok(ArrowImport { table, rows })
+    }
+
+    // `table` as an arrow ipc or parquet file, written to `path` on the server or sent back when there is none
+    #[cfg(feature = "arrow")]
+    pub async fn export_arrow(&mut self, table: &Table, path: Option<PathBuf>, format: ArrowFormat) -> Result<ArrowExport, QueryError> {
+        let data = arrow::write(table, format)?;
+        let rows = table.row_count();
+
+        let Some(path) = path else {
+            return Ok(ArrowExport { rows, data: Some(data) });
+        };
+
+        self.write_file(&path, data, "arrow export").await?;
+        self.logger.info(
+            "Arrow Exported".to_string(),
+            format!("exported {} rows to {} as {:?}", rows, path.display(), format)
+        ).await;
+
+        Ok(ArrowExport { rows, data: None })
+    }
+
+    async fn read_file(&mut self, path: &Path, operation: &str) -> Result<Vec<u8>, QueryError> {
+        let path = self.file_path(path, operation).await?;
+
+        match tokio::fs::read(&path).await {
+            Ok(data) => Ok(data),
+            Err(err) => {
+                self.logger.error(
+                    "File Error".to_string(),
+                    format!("{} from {}: {}", operation, path.display(), err)
+                ).await;
+
+                Err(QueryError::FileError(err.to_string()))
+            }
+        }
+    }
+
+    // writes a hidden file next to `path` and renames it, so readers never see a half written file
+    // and a failed export leaves the previous one in place
+    async fn write_file(&mut self, path: &Path, data: Vec<u8>, operation: &str) -> Result<(), QueryError> {
+        let path = self.file_path(path, operation).await?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let partial = path.with_file_name(format!(".{}.{:016x}.partial", name, rand::random::<u64>()));
+
+        let written = match tokio::fs::write(&partial, data).await {
+            Ok(()) => tokio::fs::rename(&partial, &path).await,
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = written {
+            let _ = tokio::fs::remove_file(&partial).await;
+            self.logger.error(
+                "File Error".to_string(),
+                format!("{} to {}: {}", operation, path.display(), err)
+            ).await;
+
+            return Err(QueryError::FileError(err.to_string()));
+        }
+
+        Ok(())
+    }
+
+    // `path` inside the file directory, denied when there is none or the path leads out of it
+    async fn file_path(&mut self, path: &Path, operation: &str) -> Result<PathBuf, QueryError> {
+        let resolved = match &self.file_dir {
+            Some(dir) => resolve_file(dir, path).await,
+            None => Err("files on the server are turned off, file_dir is not set".to_string()),
+        };
+
+        match resolved {
+            Ok(path) => Ok(path),
+            Err(err) => {
+                self.logger.warn(
+                    "File Access Denied".to_string(),
+                    format!("{} of {}: {}", operation, path.display(), err)
+                ).await;
+
+                Err(QueryError::PermissionDenied)
+            }
+        }
+    }
+
+    // every column an update sets has to exist, checked before any row is changed
+    async fn check_targets(&mut self, table: usize, targets: &[(usize, Vec<Expr>)]) -> Result<(), QueryError> {
+        // a missing table is reported when it is read
+        let Some(columns) = self.tables.get(table).map(|table| table.columns.len()) else {
+            return Ok(());
+        };
+
+        match targets.iter().find(|(column, _)| *column >= columns) {
+            Some((column, _)) => {
+                self.logger.error(
+                    "Column Not Found".to_string(),
+                    format!("column {} not found in table {}", column, table)
+                ).await;
+
+                Err(QueryError::ColumnNotFound)
+            }
+            None => Ok(()),
+        }
+    }
+
     pub async fn update(
         &mut self,
         table: usize,
         condition_column: usize,
         targets: Vec<(usize, Vec<Expr>)>,
+        condition: Vec<Expr>,
+        returning: Returning,
+    ) -> Result<MutationResult, QueryError> {
+        write_statement!(self, self.update_rows(table, condition_column, targets, condition, returning))
+    }
+
+    async fn update_rows(
+        &mut self,
+        table: usize,
+        condition_column: usize,
+        targets: Vec<(usize, Vec<Expr>)>,
 
         #[allow(unused_variables)]
-        condition: Vec<Expr>
-    ) -> Result<(), QueryError> {
+        condition: Vec<Expr>,
+
+        returning: Returning,
+    ) -> Result<MutationResult, QueryError> {
+        self.check_targets(table, &targets).await?;
+        let interrupt = self.interrupt.clone();
         let target_table = self.get_table_with_column_check(table, condition_column).await?;
+        let scanned = target_table.row_count();
 
         #[allow(unused_mut)]
         let mut row_ids: Vec<usize> = Vec::new();
@@ -310,6 +1438,7 @@ impl<'a > Database<'a > {
         macro_rules! check {
             ($values:expr, $type_col:ident) => {
                 for (row_id, row_value) in $values.iter().enumerate() {
+                    interrupt!(interrupt, row_id, self.logger, table, update);
                     let res = evaluate!(condition.clone(), row_value.clone(), $type_col, self.logger, table, condition_column, update);
                     if let Value::Bool(true) = res {
                         row_ids.push(row_id);
@@ -318,7 +1447,7 @@ impl<'a > Database<'a > {
             };
         }
 
-        if condition.len() == 0 {
+        if condition.is_empty() {
             self.logger.error(
                 "No Condition".to_string(),
                 format!("update on table {}: no condition given", table)
@@ -327,113 +1456,216 @@ impl<'a > Database<'a > {
             return Err(QueryError::NoOperation);
         }
 
-        match target_table.columns.get_mut(condition_column) {
-            Some(column) => {
-                match column {
-                    Column::Int(values) => {
-                        check!(values, Int)
-                    }
-                    Column::Float(values) => {
-                        check!(values, Float)
-                    }
-                    Column::String(values) => {
-                        check!(values, String)
-                    }
-                    Column::Bool(values) => {
-                        check!(values, Bool)
-                    }
+        if let Some(column) = target_table.columns.get_mut(condition_column) {
+            match column {
+                Column::Int(values) => {
+                    check!(values, Int)
+                }
+                Column::Float(values) => {
+                    check!(values, Float)
+                }
+                Column::String(values) => {
+                    check!(values, String)
+                }
+                Column::Bool(values) => {
+                    check!(values, Bool)
                 }
             }
-            None => {}
         }
 
+        let old_rows = match returning {
+            Returning::Old => Some(target_table.take_rows(&row_ids)),
+            _ => None,
+        };
+        let mut modified = vec![false; row_ids.len()];
+
         for (column, value) in targets {
-            match target_table.columns.get_mut(column) {
-                Some(column) => {
-                    match column {
-                        Column::Int(int_vals) => {
-                            for row in &row_ids {
-                                let old_value = int_vals[*row];
-                                let new_value = evaluate!(value.clone(), old_value.clone(), Int, self.logger, table, condition_column, update);
-                                int_vals[*row] = new_value.clone().into_int().unwrap();
+            if let Some(column) = target_table.columns.get_mut(column) {
+                match column {
+                    Column::Int(int_vals) => {
+                        for (position, row) in row_ids.iter().enumerate() {
+                            interrupt!(interrupt, position, self.logger, table, update);
+                            let old_value = int_vals[*row];
+                            let new_value = evaluate!(value.clone(), old_value, Int, self.logger, table, condition_column, update);
+                            let new_value = new_value.into_int().ok_or(QueryError::TypeMismatch)?;
+                            if new_value != old_value {
+                                modified[position] = true;
+                                int_vals[*row] = new_value;
                             }
                         }
-                        Column::Float(float_vals) => {
-                            for row in &row_ids {
-                                let old_value = float_vals[*row];
-                                let new_value = evaluate!(value.clone(), old_value.clone(), Float, self.logger, table, condition_column, update);
-                                float_vals[*row] = new_value.clone().into_float().unwrap();
+                    }
+                    Column::Float(float_vals) => {
+                        for (position, row) in row_ids.iter().enumerate() {
+                            interrupt!(interrupt, position, self.logger, table, update);
+                            let old_value = float_vals[*row];
+                            let new_value = evaluate!(value.clone(), old_value, Float, self.logger, table, condition_column, update);
+                            let new_value = new_value.into_float().ok_or(QueryError::TypeMismatch)?;
+                            if new_value.to_bits() != old_value.to_bits() {
+                                modified[position] = true;
+                                float_vals[*row] = new_value;
                             }
                         }
-                        Column::String(string_vals) => {
-                            for row in &row_ids {
-                                let old_value = string_vals[*row].clone();
-                                let new_value = evaluate!(value.clone(), old_value.clone(), String, self.logger, table, condition_column, update);
-                                string_vals[*row] = new_value.clone().into_string().unwrap();
+                    }
+                    Column::String(string_vals) => {
+                        for (position, row) in row_ids.iter().enumerate() {
+                            interrupt!(interrupt, position, self.logger, table, update);
+                            let old_value = string_vals[*row].clone();
+                            let new_value = evaluate!(value.clone(), old_value.clone(), String, self.logger, table, condition_column, update);
+                            let new_value = new_value.into_string().ok_or(QueryError::TypeMismatch)?;
+                            if new_value != old_value {
+                                modified[position] = true;
+                                string_vals[*row] = new_value;
                             }
                         }
-                        Column::Bool(bool_vals) => {
-                            for row in &row_ids {
-                                let old_value = bool_vals[*row];
-                                let new_value = evaluate!(value.clone(), old_value.clone(), Bool, self.logger, table, condition_column, update);
-                                bool_vals[*row] = new_value.clone().into_bool().unwrap();
+                    }
+                    Column::Bool(bool_vals) => {
+                        for (position, row) in row_ids.iter().enumerate() {
+                            interrupt!(interrupt, position, self.logger, table, update);
+                            let old_value = bool_vals[*row];
+                            let new_value = evaluate!(value.clone(), old_value, Bool, self.logger, table, condition_column, update);
+                            let new_value = new_value.into_bool().ok_or(QueryError::TypeMismatch)?;
+                            if new_value != old_value {
+                                modified[position] = true;
+                                bool_vals[*row] = new_value;
                             }
                         }
                     }
                 }
-                None => {}
             }
         }
 
-        Ok(())
+        let result = MutationResult {
+            matched: row_ids.len(),
+            modified: modified.iter().filter(|modified| **modified).count(),
+            returning: match returning {
+                Returning::None => None,
+                Returning::Old => old_rows,
+                Returning::New => Some(target_table.take_rows(&row_ids)),
+            },
+        };
+
+        self.record_stats(scanned, result.modified);
+
+        self.logger.info(
+            "Update".to_string(),
+            format!(
+                "Update query executed on table {}, {} rows matched and {} rows modified",
+                table,
+                result.matched,
+                result.modified
+            )
+        ).await;
+
+        Ok(result)
     }
 
     pub async fn update_all(
         &mut self,
         table: usize,
         targets: Vec<(usize, Vec<Expr>)>,
-    ) -> Result<(), QueryError> {
+        returning: Returning,
+    ) -> Result<MutationResult, QueryError> {
+        write_statement!(self, self.update_all_rows(table, targets, returning))
+    }
+
+    async fn update_all_rows(
+        &mut self,
+        table: usize,
+        targets: Vec<(usize, Vec<Expr>)>,
+        returning: Returning,
+    ) -> Result<MutationResult, QueryError> {
+        self.check_targets(table, &targets).await?;
+        let interrupt = self.interrupt.clone();
         let target_table = self.get_table_with_column_check(table, 0).await?;
 
+        let old_rows = match returning {
+            Returning::Old => Some(target_table.clone()),
+            _ => None,
+        };
+        let mut modified = vec![false; target_table.row_count()];
+
         for (column_id, value) in targets {
-            match target_table.columns.get_mut(column_id) {
-                Some(column) => {
-                    match column {
-                        Column::Int(int_vals) => {
-                            for row in 0..int_vals.len() {
-                                let old_value = int_vals[row];
-                                let new_value = evaluate!(value.clone(), old_value.clone(), Int, self.logger, table, column_id, update_all);
-                                int_vals[row] = new_value.clone().into_int().unwrap();
+            if let Some(column) = target_table.columns.get_mut(column_id) {
+                match column {
+                    Column::Int(int_vals) => {
+                        for row in 0..int_vals.len() {
+                            interrupt!(interrupt, row, self.logger, table, update_all);
+                            let old_value = int_vals[row];
+                            let new_value = evaluate!(value.clone(), old_value, Int, self.logger, table, column_id, update_all);
+                            let new_value = new_value.into_int().ok_or(QueryError::TypeMismatch)?;
+                            // an unchanged row leaves its chunk shared
+                            if new_value != old_value {
+                                modified[row] = true;
+                                int_vals[row] = new_value;
                             }
                         }
-                        Column::Float(float_vals) => {
-                            for row in 0..float_vals.len() {
-                                let old_value = float_vals[row];
-                                let new_value = evaluate!(value.clone(), old_value.clone(), Float, self.logger, table, column_id, update_all);
-                                float_vals[row] = new_value.clone().into_float().unwrap();
+                    }
+                    Column::Float(float_vals) => {
+                        for row in 0..float_vals.len() {
+                            interrupt!(interrupt, row, self.logger, table, update_all);
+                            let old_value = float_vals[row];
+                            let new_value = evaluate!(value.clone(), old_value, Float, self.logger, table, column_id, update_all);
+                            let new_value = new_value.into_float().ok_or(QueryError::TypeMismatch)?;
+                            // an unchanged row leaves its chunk shared
+                            if new_value.to_bits() != old_value.to_bits() {
+                                modified[row] = true;
+                                float_vals[row] = new_value;
                             }
                         }
-                        Column::String(string_vals) => {
-                            for row in 0..string_vals.len() {
-                                let old_value = string_vals[row].clone();
-                                let new_value = evaluate!(value.clone(), old_value.clone(), String, self.logger, table, column_id, update_all);
-                                string_vals[row] = new_value.clone().into_string().unwrap();
+                    }
+                    Column::String(string_vals) => {
+                        for row in 0..string_vals.len() {
+                            interrupt!(interrupt, row, self.logger, table, update_all);
+                            let old_value = string_vals[row].clone();
+                            let new_value = evaluate!(value.clone(), old_value.clone(), String, self.logger, table, column_id, update_all);
+                            let new_value = new_value.into_string().ok_or(QueryError::TypeMismatch)?;
+                            // an unchanged row leaves its chunk shared
+                            if new_value != old_value {
+                                modified[row] = true;
+                                string_vals[row] = new_value;
                             }
                         }
-                        Column::Bool(bool_vals) => {
-                            for row in 0..bool_vals.len() {
-                                let old_value = bool_vals[row];
-                                let new_value = evaluate!(value.clone(), old_value.clone(), Bool, self.logger, table, column_id, update_all);
-                                bool_vals[row] = new_value.clone().into_bool().unwrap();
+                    }
+                    Column::Bool(bool_vals) => {
+                        for row in 0..bool_vals.len() {
+                            interrupt!(interrupt, row, self.logger, table, update_all);
+                            let old_value = bool_vals[row];
+                            let new_value = evaluate!(value.clone(), old_value, Bool, self.logger, table, column_id, update_all);
+                            let new_value = new_value.into_bool().ok_or(QueryError::TypeMismatch)?;
+                            // an unchanged row leaves its chunk shared
+                            if new_value != old_value {
+                                modified[row] = true;
+                                bool_vals[row] = new_value;
                             }
                         }
                     }
                 }
-                None => {}
             }
         }
 
-        Ok(())
+        let result = MutationResult {
+            matched: modified.len(),
+            modified: modified.iter().filter(|modified| **modified).count(),
+            returning: match returning {
+                Returning::None => None,
+                Returning::Old => old_rows,
+                Returning::New => Some(target_table.clone()),
+            },
+        };
+
+        self.record_stats(result.matched, result.modified);
+
+        self.logger.info(
+            "Update All".to_string(),
+            format!(
+                "Update all query executed on table {}, {} rows matched and {} rows modified",
+                table,
+                result.matched,
+                result.modified
+            )
+        ).await;
+
+        Ok(result)
     }
 
 
@@ -441,11 +1673,25 @@ impl<'a > Database<'a > {
         &mut self,
         table: usize,
         column: usize,
+        condition: Vec<Expr>,
+        returning: Returning,
+    ) -> Result<MutationResult, QueryError> {
+        write_statement!(self, self.delete_rows(table, column, condition, returning))
+    }
+
+    async fn delete_rows(
+        &mut self,
+        table: usize,
+        column: usize,
 
         #[allow(unused_variables)]
-        condition: Vec<Expr>
-    ) -> Result<(), QueryError> {
+        condition: Vec<Expr>,
+
+        returning: Returning,
+    ) -> Result<MutationResult, QueryError> {
+        let interrupt = self.interrupt.clone();
         let target_table = self.get_table_with_column_check(table, column).await?;
+        let scanned = target_table.row_count();
 
         #[allow(unused_mut)]
         let mut row_ids: Vec<usize> = Vec::new();
@@ -453,6 +1699,7 @@ impl<'a > Database<'a > {
         macro_rules! check {
             ($values:expr, $type_col:ident) => {
                 for (row_id, row_value) in $values.iter().enumerate() {
+                    interrupt!(interrupt, row_id, self.logger, table, delete);
                     let res = evaluate!(condition.clone(), row_value.clone(), $type_col, self.logger, table, column, delete);
                     if let Value::Bool(true) = res {
                         row_ids.push(row_id);
@@ -461,60 +1708,88 @@ impl<'a > Database<'a > {
             };
         }
 
-        match target_table.columns.get_mut(column) {
-            Some(column) => {
-                match column {
-                    Column::Int(values) => {
-                        check!(values, Int)
-                    }
-                    Column::Float(values) => {
-                        check!(values, Float)
-                    }
-                    Column::String(values) => {
-                        check!(values, String)
-                    }
-                    Column::Bool(values) => {
-                        check!(values, Bool)
-                    }
-                }
-            }
-            None => {}
-        }
-
-        row_ids.reverse();
-
-        for column in target_table.columns.iter_mut() {
+        if let Some(column) = target_table.columns.get_mut(column) {
             match column {
-                Column::Int(ref mut int_col) => {
-                    for row in &row_ids {
-                        int_col.remove(*row);
-                    }
+                Column::Int(values) => {
+                    check!(values, Int)
                 }
-                Column::Float(ref mut float_col) => {
-                    for row in &row_ids {
-                        float_col.remove(*row);
-                    }
+                Column::Float(values) => {
+                    check!(values, Float)
                 }
-                Column::String(ref mut string_col) => {
-                    for row in &row_ids {
-                        string_col.remove(*row);
-                    }
+                Column::String(values) => {
+                    check!(values, String)
                 }
-                Column::Bool(ref mut bool_col) => {
-                    for row in &row_ids {
-                        bool_col.remove(*row);
-                    }
+                Column::Bool(values) => {
+                    check!(values, Bool)
                 }
             }
         }
 
-        Ok(())
+        let removed = match returning {
+            Returning::None => None,
+            Returning::Old | Returning::New => Some(target_table.take_rows(&row_ids)),
+        };
+
+        let result = MutationResult {
+            matched: row_ids.len(),
+            modified: row_ids.len(),
+            returning: removed,
+        };
+
+        for column in target_table.columns.iter_mut() {
+            column.remove_rows(&row_ids);
+        }
+
+        self.record_stats(scanned, result.modified);
+
+        self.logger.info(
+            "Delete".to_string(),
+            format!(
+                "Delete query executed on table {}, {} rows deleted",
+                table,
+                result.modified
+            )
+        ).await;
+
+        Ok(result)
     }
 
     pub async fn logger_flush(&mut self) {
         self.logger.flush_buffer().await;
     }
+    // changes the minimum level for every session sharing this logger
+    pub async fn set_log_level(&mut self, level: Level) {
+        self.logger.set_level(level);
+        self.logger.info("Log Level".to_string(), format!("minimum log level set to {}", level)).await;
+    }
+
     pub async fn logger_info(&mut self, title: String, message: String) {
         self.logger.info(title, message).await;
     }
-}
\ No newline at end of file
+}
+// `path` joined to `dir` with its symlinks followed. absolute paths, `..` and links leading out of `dir`
+// are refused. a file that does not exist yet, such as the target of an export, is placed in its parent
+async fn resolve_file(dir: &Path, path: &Path) -> Result<PathBuf, String> {
+    if !path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+        return Err("only paths relative to file_dir without `..` are allowed".to_string());
+    }
+    let Some(name) = path.file_name() else {
+        return Err("the path does not name a file".to_string());
+    };
+
+    let joined = dir.join(path);
+    let resolved = match tokio::fs::canonicalize(&joined).await {
+        Ok(resolved) => resolved,
+        Err(_) => {
+            let parent = joined.parent().unwrap_or(dir);
+            tokio::fs::canonicalize(parent).await
+                .map_err(|err| format!("{}: {}", parent.display(), err))?
+                .join(name)
+        }
+    };
+
+    match resolved.starts_with(dir) && resolved != dir {
+        true => Ok(resolved),
+        false => Err("the path leads out of file_dir".to_string()),
+    }
+}
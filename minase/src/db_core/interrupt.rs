@@ -0,0 +1,58 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crate::db_core::query_error::QueryError;
+
+
+// how many rows a scan goes through between two checks for an interrupt
+const CHECK_EVERY: usize = 1024;
+
+// cancels the query a session is running, from outside of the session
+#[derive(Clone, Debug, Default)]
+pub struct Canceller {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Canceller {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+// whether the query a session is running has to stop, because it was cancelled or ran past its deadline
+#[derive(Clone, Debug, Default)]
+pub struct Interrupt {
+    canceller: Canceller,
+    deadline: Option<Instant>,
+}
+
+impl Interrupt {
+    pub fn canceller(&self) -> Canceller {
+        self.canceller.clone()
+    }
+
+    // a new query starts: a cancel that came in before it is forgotten and the deadline is set anew
+    pub fn start(&mut self, timeout: Option<Duration>) {
+        self.canceller.cancelled.store(false, Ordering::Relaxed);
+        self.deadline = timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    pub fn check(&self) -> Result<(), QueryError> {
+        if self.canceller.cancelled.load(Ordering::Relaxed) {
+            return Err(QueryError::Cancelled);
+        }
+
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(QueryError::Timeout),
+            _ => Ok(()),
+        }
+    }
+
+    // `check` for the first row of a scan and every `CHECK_EVERY` rows after it
+    pub fn check_row(&self, row: usize) -> Result<(), QueryError> {
+        match row % CHECK_EVERY {
+            0 => self.check(),
+            _ => Ok(()),
+        }
+    }
+}
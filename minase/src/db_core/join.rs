@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use serde_derive::{Deserialize, Serialize};
+use crate::db_core::database::Table;
+use crate::db_core::interrupt::Interrupt;
+use crate::db_core::query_error::QueryError;
+use crate::db_core::values::{CellKey, Column, Expr, ExprEvaluator, Value};
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    Cross,
+}
+
+// column numbers in a join refer to the combined row: the columns of the first table,
+// followed by the columns of every joined table in the order they are joined
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum JoinCondition {
+    // pairs of (combined column so far, column of the joined table) that have to be equal.
+    // executed as a hash join on the joined table
+    Equals(Vec<(usize, usize)>),
+    // an expression over `Expr::Column`s of the combined row including the joined table.
+    // executed as a nested loop
+    Predicate(Vec<Expr>),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Join {
+    pub table: usize,
+    pub kind: JoinKind,
+    pub on: Option<JoinCondition>,
+}
+
+// a row of the join result, the row id in every joined table or `None` when a left join found no match
+type JoinedRow = Vec<Option<usize>>;
+
+struct Layout<'a> {
+    tables: Vec<&'a Table>,
+    // the (table, column) each combined column comes from
+    columns: Vec<(usize, usize)>,
+}
+
+impl<'a> Layout<'a> {
+    fn push(&mut self, table: &'a Table) {
+        let position = self.tables.len();
+
+        self.columns.extend((0..table.columns.len()).map(|column| (position, column)));
+        self.tables.push(table);
+    }
+
+    fn value(&self, row: &[Option<usize>], column: usize) -> Option<Value> {
+        let (table, column) = *self.columns.get(column)?;
+        let row_id = (*row.get(table)?)?;
+
+        if self.tables[table].is_null(column, row_id) {
+            return None;
+        }
+
+        self.tables[table].columns[column].get(row_id)
+    }
+
+    fn key(&self, row: &[Option<usize>], column: usize) -> Option<CellKey> {
+        let (table, column) = self.columns[column];
+        join_key(self.tables[table], column, row[table]?)
+    }
+}
+
+// the key a cell is joined on, none for a cell that never equals anything: a null, or a NaN,
+// which counts as null like it does when ordering
+fn join_key(table: &Table, column: usize, row: usize) -> Option<CellKey> {
+    if table.is_null(column, row) {
+        return None;
+    }
+
+    match &table.columns[column] {
+        Column::Float(values) if values[row].is_nan() => None,
+        values => Some(values.key(row)),
+    }
+}
+
+fn referenced_columns(expr: &[Expr]) -> impl Iterator<Item = usize> + '_ {
+    expr.iter().filter_map(|part| match part {
+        Expr::Column(column) => Some(*column),
+        _ => None,
+    })
+}
+
+// joins `first` with every table in `joins`, in order, and projects `columns` of the combined row.
+// an empty projection returns every column. the interrupt is checked as rows are paired up
+pub fn join_tables(first: &Table, joins: &[(&Table, &Join)], columns: &[usize], interrupt: &Interrupt) -> Result<Table, QueryError> {
+    let mut layout = Layout {
+        tables: vec![first],
+        columns: Vec::new(),
+    };
+    layout.columns.extend((0..first.columns.len()).map(|column| (0, column)));
+
+    let mut rows: Vec<JoinedRow> = (0..first.row_count()).map(|row| vec![Some(row)]).collect();
+    // the pairs of rows looked at so far, over every join
+    let mut visited: usize = 0;
+
+    for (table, join) in joins {
+        let left_columns = layout.columns.len();
+        layout.push(table);
+
+        let right_rows = table.row_count();
+        let mut joined: Vec<JoinedRow> = Vec::new();
+
+        match (join.kind, &join.on) {
+            (JoinKind::Cross, Some(_)) => return Err(QueryError::InvalidQuery),
+
+            (_, None) => {
+                for row in &rows {
+                    if right_rows == 0 && join.kind == JoinKind::Left {
+                        joined.push(extend(row, None));
+                    }
+
+                    for right in 0..right_rows {
+                        interrupt.check_row(visited)?;
+                        visited += 1;
+
+                        joined.push(extend(row, Some(right)));
+                    }
+                }
+            }
+
+            (kind, Some(JoinCondition::Equals(pairs))) => {
+                if pairs.is_empty() {
+                    return Err(QueryError::InvalidQuery);
+                }
+                if pairs.iter().any(|(left, right)| *left >= left_columns || *right >= table.columns.len()) {
+                    return Err(QueryError::ColumnNotFound);
+                }
+                if pairs.iter().any(|(left, right)| {
+                    let (left_table, left_column) = layout.columns[*left];
+                    layout.tables[left_table].column_types[left_column] != table.column_types[*right]
+                }) {
+                    return Err(QueryError::TypeMismatch);
+                }
+
+                let mut index: HashMap<Vec<CellKey>, Vec<usize>> = HashMap::new();
+
+                for right in 0..right_rows {
+                    interrupt.check_row(visited)?;
+                    visited += 1;
+
+                    let key = pairs.iter()
+                        .map(|(_, column)| join_key(table, *column, right))
+                        .collect::<Option<Vec<CellKey>>>();
+
+                    if let Some(key) = key {
+                        index.entry(key).or_default().push(right);
+                    }
+                }
+
+                for row in &rows {
+                    interrupt.check_row(visited)?;
+                    visited += 1;
+
+                    // a null or NaN key never equals anything, so the row can only survive a left join
+                    let key = pairs.iter()
+                        .map(|(column, _)| layout.key(row, *column))
+                        .collect::<Option<Vec<CellKey>>>();
+
+                    match key.as_ref().and_then(|key| index.get(key)) {
+                        Some(matches) => {
+                            for right in matches {
+                                joined.push(extend(row, Some(*right)));
+                            }
+                        }
+                        None => {
+                            if kind == JoinKind::Left {
+                                joined.push(extend(row, None));
+                            }
+                        }
+                    }
+                }
+            }
+
+            (kind, Some(JoinCondition::Predicate(predicate))) => {
+                if referenced_columns(predicate).any(|column| column >= layout.columns.len()) {
+                    return Err(QueryError::ColumnNotFound);
+                }
+
+                for row in &rows {
+                    let mut matched = false;
+
+                    for right in 0..right_rows {
+                        interrupt.check_row(visited)?;
+                        visited += 1;
+
+                        let candidate = extend(row, Some(right));
+
+                        // a predicate that reads a null cell never matches
+                        if referenced_columns(predicate).any(|column| layout.value(&candidate, column).is_none()) {
+                            continue;
+                        }
+
+                        let res = ExprEvaluator::evaluate_columns(predicate, |column| layout.value(&candidate, column))?;
+
+                        match res {
+                            Value::Bool(true) => {
+                                joined.push(candidate);
+                                matched = true;
+                            }
+                            Value::Bool(false) => {}
+                            _ => return Err(QueryError::TypeMismatch),
+                        }
+                    }
+
+                    if !matched && kind == JoinKind::Left {
+                        joined.push(extend(row, None));
+                    }
+                }
+            }
+        }
+
+        rows = joined;
+    }
+
+    project(&layout, &rows, columns)
+}
+
+fn extend(row: &[Option<usize>], right: Option<usize>) -> JoinedRow {
+    let mut row = row.to_vec();
+    row.push(right);
+    row
+}
+
+fn project(layout: &Layout, rows: &[JoinedRow], columns: &[usize]) -> Result<Table, QueryError> {
+    let columns = if columns.is_empty() {
+        (0..layout.columns.len()).collect::<Vec<usize>>()
+    } else {
+        columns.to_vec()
+    };
+
+    if columns.iter().any(|column| *column >= layout.columns.len()) {
+        return Err(QueryError::ColumnNotFound);
+    }
+
+    let mut result_columns = Vec::with_capacity(columns.len());
+    let mut column_types = Vec::with_capacity(columns.len());
+    let mut nulls = Vec::with_capacity(columns.len());
+
+    for column in columns {
+        let (table, table_column) = layout.columns[column];
+        let types = layout.tables[table].column_types[table_column].clone();
+
+        let mut values = Column::empty(&types);
+        let mut column_nulls = Vec::new();
+
+        for (row_id, row) in rows.iter().enumerate() {
+            match layout.value(row, column) {
+                Some(value) => values.push(value)?,
+                None => {
+                    if column_nulls.is_empty() {
+                        column_nulls = vec![false; rows.len()];
+                    }
+                    column_nulls[row_id] = true;
+                    values.push(types.default_value())?;
+                }
+            }
+        }
+
+        result_columns.push(values);
+        column_types.push(types);
+        nulls.push(column_nulls);
+    }
+
+    let mut table = Table::new(result_columns, column_types);
+    if nulls.iter().any(|nulls| !nulls.is_empty()) {
+        table.nulls = nulls;
+    }
+
+    Ok(table)
+}
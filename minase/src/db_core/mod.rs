@@ -1,6 +1,18 @@
 pub mod query_error;
 pub mod query;
 pub mod values;
+pub mod chunks;
 pub mod database;
+pub mod ordering;
+pub mod aggregate;
+pub mod join;
+pub mod transaction;
+pub mod storage;
 
 
+pub mod interrupt;
+pub mod auth;
+pub mod sql;
+pub mod prepared;
+pub mod csv;
+pub mod arrow;
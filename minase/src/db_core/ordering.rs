@@ -0,0 +1,128 @@
+use std::cmp::Ordering;
+use serde_derive::{Deserialize, Serialize};
+use crate::db_core::values::Column;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Direction {
+    Ascending,
+    Descending,
+}
+
+// where null cells are placed, independent of the sort direction.
+// columns have no null values of their own yet, so a `NaN` float is treated as null
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum NullsOrder {
+    First,
+    Last,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OrderBy {
+    pub column: usize,
+    pub direction: Direction,
+    pub nulls: NullsOrder,
+}
+
+impl OrderBy {
+    pub fn asc(column: usize) -> Self {
+        OrderBy {
+            column,
+            direction: Direction::Ascending,
+            nulls: NullsOrder::Last,
+        }
+    }
+
+    pub fn desc(column: usize) -> Self {
+        OrderBy {
+            column,
+            direction: Direction::Descending,
+            nulls: NullsOrder::First,
+        }
+    }
+}
+
+fn compare_key(column: &Column, key: &OrderBy, left: usize, right: usize) -> Ordering {
+    let ordering = match column {
+        Column::Int(values) => values[left].cmp(&values[right]),
+        Column::String(values) => values[left].cmp(&values[right]),
+        Column::Bool(values) => values[left].cmp(&values[right]),
+        Column::Float(values) => {
+            let (left, right) = (values[left], values[right]);
+
+            match (left.is_nan(), right.is_nan()) {
+                (true, true) => return Ordering::Equal,
+                (true, false) => {
+                    return match key.nulls {
+                        NullsOrder::First => Ordering::Less,
+                        NullsOrder::Last => Ordering::Greater,
+                    };
+                }
+                (false, true) => {
+                    return match key.nulls {
+                        NullsOrder::First => Ordering::Greater,
+                        NullsOrder::Last => Ordering::Less,
+                    };
+                }
+                (false, false) => left.partial_cmp(&right).unwrap(),
+            }
+        }
+    };
+
+    match key.direction {
+        Direction::Ascending => ordering,
+        Direction::Descending => ordering.reverse(),
+    }
+}
+
+// compares two rows of `columns` by every key in turn, falling back to the row id
+// so that the order is total and rows that compare equal keep their insertion order
+pub fn compare_rows(columns: &[Column], order_by: &[OrderBy], left: usize, right: usize) -> Ordering {
+    for key in order_by {
+        let ordering = compare_key(&columns[key.column], key, left, right);
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    left.cmp(&right)
+}
+
+// sorts `row_ids` and keeps only the window selected by `offset` and `limit`.
+// when a limit is given only the first `offset + limit` rows are fully sorted
+pub fn sort_rows(
+    columns: &[Column],
+    row_ids: &mut Vec<usize>,
+    order_by: &[OrderBy],
+    limit: Option<usize>,
+    offset: usize,
+) {
+    if !order_by.is_empty() {
+        let compare = |left: &usize, right: &usize| compare_rows(columns, order_by, *left, *right);
+
+        match limit {
+            Some(limit) if offset.saturating_add(limit) < row_ids.len() => {
+                let keep = offset + limit;
+
+                if keep > 0 {
+                    row_ids.select_nth_unstable_by(keep - 1, compare);
+                }
+                row_ids.truncate(keep);
+                row_ids.sort_unstable_by(compare);
+            }
+            _ => {
+                row_ids.sort_unstable_by(compare);
+            }
+        }
+    }
+
+    let end = match limit {
+        Some(limit) => offset.saturating_add(limit).min(row_ids.len()),
+        None => row_ids.len(),
+    };
+    let start = offset.min(end);
+
+    row_ids.truncate(end);
+    row_ids.drain(..start);
+}
@@ -0,0 +1,320 @@
+use crate::db_core::join::JoinCondition;
+use crate::db_core::query::Query;
+use crate::db_core::query_error::QueryError;
+use crate::db_core::values::{Expr, ToTypes, Types, Value};
+
+
+// a query checked once against the tables, so executing it only has to check the values bound to its parameters
+#[derive(Clone, Debug)]
+pub struct Prepared {
+    query: Query,
+    // the type each parameter has to be bound to, `None` when nothing in the query decides it
+    params: Vec<Option<Types>>,
+    // the position and oid of every table the query was checked against
+    tables: Vec<(usize, u64)>,
+}
+
+impl Prepared {
+    // checks that the tables and columns the query reads exist and that its expressions are well formed and
+    // typed, inferring the type of each parameter from what it is compared or combined with.
+    // `tables` gives the oid and the column types of the table at a position
+    pub fn new<'t, F>(query: Query, tables: F) -> Result<Prepared, QueryError>
+    where
+        F: Fn(usize) -> Option<(u64, &'t [Types])>
+    {
+        let mut checker = Checker::default();
+        let mut checked = Vec::new();
+        let mut table = |id: usize| {
+            let (oid, types) = tables(id).ok_or(QueryError::TableNotFound)?;
+            if !checked.contains(&(id, oid)) {
+                checked.push((id, oid));
+            }
+            Ok(types)
+        };
+
+        match &query {
+            Query::Select { table: id, columns, condition, order_by, .. } => {
+                let types = table(*id)?;
+                checker.condition(condition, column_type(types, *columns)?)?;
+                for key in order_by {
+                    column_type(types, key.column)?;
+                }
+            }
+            Query::SelectTable { table: id } => {
+                table(*id)?;
+            }
+            Query::Aggregate { table: id, group_by, aggregates, having } => {
+                let types = table(*id)?;
+
+                // the result table, the group by columns followed by one column per aggregate
+                let mut result = Vec::new();
+                for column in group_by {
+                    result.push(column_type(types, *column)?.clone());
+                }
+                for aggregate in aggregates {
+                    result.push(aggregate.function.output_type(column_type(types, aggregate.column)?)?);
+                }
+
+                if let Some((column, condition)) = having {
+                    checker.condition(condition, column_type(&result, *column)?)?;
+                }
+            }
+            Query::Join { table: id, joins, columns } => {
+                let mut combined = table(*id)?.to_vec();
+
+                for join in joins {
+                    let joined = table(join.table)?;
+
+                    match &join.on {
+                        Some(JoinCondition::Equals(pairs)) => {
+                            for (left, right) in pairs {
+                                if column_type(&combined, *left)? != column_type(joined, *right)? {
+                                    return Err(QueryError::TypeMismatch);
+                                }
+                            }
+                            combined.extend_from_slice(joined);
+                        }
+                        Some(JoinCondition::Predicate(predicate)) => {
+                            combined.extend_from_slice(joined);
+                            let operand = checker.expression(predicate, None, &combined)?;
+                            checker.expect(operand, &Types::Bool)?;
+                        }
+                        None => combined.extend_from_slice(joined),
+                    }
+                }
+
+                for column in columns {
+                    column_type(&combined, *column)?;
+                }
+            }
+            Query::Insert { table: id, values } => {
+                let types = table(*id)?;
+                if values.len() != types.len() {
+                    return Err(QueryError::SizeMismatch);
+                }
+                for (value, types) in values.iter().zip(types) {
+                    match value {
+                        Expr::Value(value) if value.to_types() == *types => {}
+                        Expr::Value(_) => return Err(QueryError::TypeMismatch),
+                        Expr::Param(_) => {
+                            let operand = checker.expression(std::slice::from_ref(value), None, &[])?;
+                            checker.expect(operand, types)?;
+                        }
+                        _ => return Err(QueryError::InvalidQuery),
+                    }
+                }
+            }
+            Query::Update { table: id, condition_column, targets, condition, .. } => {
+                let types = table(*id)?;
+                checker.condition(condition, column_type(types, *condition_column)?)?;
+                checker.targets(targets, types)?;
+            }
+            Query::UpdateAll { table: id, targets, .. } => {
+                checker.targets(targets, table(*id)?)?;
+            }
+            Query::Delete { table: id, column, condition, .. } => {
+                let types = table(*id)?;
+                checker.condition(condition, column_type(types, *column)?)?;
+            }
+            // only statements that read or write rows are worth preparing
+            _ => return Err(QueryError::InvalidQuery),
+        }
+
+        Ok(Prepared {
+            query,
+            params: checker.params,
+            tables: checked,
+        })
+    }
+
+    pub fn params(&self) -> &[Option<Types>] {
+        &self.params
+    }
+
+    pub fn tables(&self) -> &[(usize, u64)] {
+        &self.tables
+    }
+
+    // the query with `values` in place of its parameters
+    pub fn bind(&self, values: &[Value]) -> Result<Query, QueryError> {
+        if values.len() != self.params.len() {
+            return Err(QueryError::SizeMismatch);
+        }
+
+        for (value, types) in values.iter().zip(&self.params) {
+            if types.as_ref().is_some_and(|types| value.to_types() != *types) {
+                return Err(QueryError::TypeMismatch);
+            }
+        }
+
+        let mut query = self.query.clone();
+        for expr in expressions(&mut query) {
+            for part in expr.iter_mut() {
+                if let Expr::Param(param) = part {
+                    *part = Expr::Value(values[*param].clone());
+                }
+            }
+        }
+
+        Ok(query)
+    }
+}
+
+fn column_type(types: &[Types], column: usize) -> Result<&Types, QueryError> {
+    types.get(column).ok_or(QueryError::ColumnNotFound)
+}
+
+// every expression of the query that can hold a parameter
+fn expressions(query: &mut Query) -> Vec<&mut Vec<Expr>> {
+    match query {
+        Query::Select { condition, .. } | Query::Delete { condition, .. } => vec![condition],
+        Query::Aggregate { having, .. } => having.iter_mut().map(|(_, condition)| condition).collect(),
+        Query::Join { joins, .. } => joins.iter_mut()
+            .filter_map(|join| match &mut join.on {
+                Some(JoinCondition::Predicate(predicate)) => Some(predicate),
+                _ => None,
+            })
+            .collect(),
+        Query::Update { condition, targets, .. } => {
+            std::iter::once(condition).chain(targets.iter_mut().map(|(_, value)| value)).collect()
+        }
+        Query::UpdateAll { targets, .. } => targets.iter_mut().map(|(_, value)| value).collect(),
+        Query::Insert { values, .. } => vec![values],
+        _ => vec![],
+    }
+}
+
+// the type of a value on the stack while checking an expression
+#[derive(Clone, Debug)]
+enum Operand {
+    Known(Types),
+    Param(usize),
+    // the result of combining parameters whose types are not known yet
+    Unknown,
+}
+
+#[derive(Default)]
+struct Checker {
+    params: Vec<Option<Types>>,
+}
+
+impl Checker {
+    // a parameter used as two different types can never be bound
+    fn infer(&mut self, param: usize, types: &Types) -> Result<(), QueryError> {
+        match &self.params[param] {
+            Some(known) if known != types => Err(QueryError::TypeMismatch),
+            _ => {
+                self.params[param] = Some(types.clone());
+                Ok(())
+            }
+        }
+    }
+
+    // the type both operands of a binary operator share, when it is known
+    fn unify(&mut self, left: Operand, right: Operand) -> Result<Option<Types>, QueryError> {
+        match (self.resolve(left), self.resolve(right)) {
+            (Operand::Known(left), Operand::Known(right)) => match left == right {
+                true => Ok(Some(left)),
+                false => Err(QueryError::TypeMismatch),
+            },
+            (Operand::Known(types), Operand::Param(param)) | (Operand::Param(param), Operand::Known(types)) => {
+                self.infer(param, &types)?;
+                Ok(Some(types))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn resolve(&self, operand: Operand) -> Operand {
+        match operand {
+            Operand::Param(param) => match &self.params[param] {
+                Some(types) => Operand::Known(types.clone()),
+                None => operand,
+            },
+            operand => operand,
+        }
+    }
+
+    fn expect(&mut self, operand: Operand, types: &Types) -> Result<(), QueryError> {
+        match self.resolve(operand) {
+            Operand::Known(known) if known != *types => Err(QueryError::TypeMismatch),
+            Operand::Param(param) => self.infer(param, types),
+            _ => Ok(()),
+        }
+    }
+
+    // the type of an expression, with `cell` the type of `Expr::Cell` and `columns` the types of `Expr::Column`s.
+    // follows the rules `ExprEvaluator` applies to values
+    fn expression(&mut self, expr: &[Expr], cell: Option<&Types>, columns: &[Types]) -> Result<Operand, QueryError> {
+        let mut stack = Vec::new();
+
+        for part in expr {
+            let operand = match part {
+                Expr::Value(value) => Operand::Known(value.to_types()),
+                Expr::Cell => Operand::Known(cell.ok_or(QueryError::CellValueNotSet)?.clone()),
+                Expr::Column(column) => Operand::Known(column_type(columns, *column)?.clone()),
+                Expr::Param(param) => {
+                    if *param >= self.params.len() {
+                        self.params.resize(param + 1, None);
+                    }
+                    Operand::Param(*param)
+                }
+                Expr::Not => {
+                    let operand = stack.pop().ok_or(QueryError::StackUnderflow)?;
+                    self.expect(operand, &Types::Bool)?;
+                    Operand::Known(Types::Bool)
+                }
+                operator => {
+                    if stack.len() < 2 {
+                        return Err(QueryError::StackUnderflow);
+                    }
+                    let right = stack.pop().unwrap();
+                    let left = stack.pop().unwrap();
+                    let types = self.unify(left, right)?;
+
+                    let allowed = match operator {
+                        Expr::Add => matches!(types, None | Some(Types::Int | Types::Float | Types::String)),
+                        Expr::Eq | Expr::Neq => true,
+                        _ => matches!(types, None | Some(Types::Int | Types::Float)),
+                    };
+                    if !allowed {
+                        return Err(QueryError::TypeMismatch);
+                    }
+
+                    match operator {
+                        Expr::Add | Expr::Sub | Expr::Mul | Expr::Div => types.map_or(Operand::Unknown, Operand::Known),
+                        _ => Operand::Known(Types::Bool),
+                    }
+                }
+            };
+
+            stack.push(operand);
+        }
+
+        match stack.len() {
+            1 => Ok(stack.pop().unwrap()),
+            _ => Err(QueryError::NoOperation),
+        }
+    }
+
+    // a condition on the cell of a column, which has to be true or false
+    fn condition(&mut self, condition: &[Expr], cell: &Types) -> Result<(), QueryError> {
+        if condition.is_empty() {
+            return Err(QueryError::NoOperation);
+        }
+
+        let operand = self.expression(condition, Some(cell), &[])?;
+        self.expect(operand, &Types::Bool)
+    }
+
+    // the new value of every target is computed from its old one and has to keep the column's type
+    fn targets(&mut self, targets: &[(usize, Vec<Expr>)], columns: &[Types]) -> Result<(), QueryError> {
+        for (column, value) in targets {
+            let types = column_type(columns, *column)?;
+            let operand = self.expression(value, Some(types), &[])?;
+            self.expect(operand, types)?;
+        }
+
+        Ok(())
+    }
+}
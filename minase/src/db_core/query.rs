@@ -1,43 +1,321 @@
+use std::path::PathBuf;
 use serde_derive::{Deserialize, Serialize};
+use logger::Level;
+use crate::db_core::aggregate::Aggregate;
+use crate::db_core::arrow::{ArrowFormat, ArrowSource};
+use crate::db_core::auth::{Password, Privilege};
+use crate::db_core::csv::{CsvOptions, CsvSource};
+use crate::db_core::database::{Batch, Returning};
+use crate::db_core::join::Join;
+use crate::db_core::ordering::OrderBy;
 use crate::db_core::values::{Expr, Types, Value};
 
 
+// identifies a session to a cancel request. the secret keeps other clients from cancelling
+// queries of sessions they did not open
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct CancelKey {
+    pub session: u64,
+    pub secret: u64,
+}
+
+// leaves the secret out, since queries are logged with their debug form
+impl std::fmt::Debug for CancelKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancelKey")
+            .field("session", &self.session)
+            .finish_non_exhaustive()
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Query {
     Select {
         table: usize,
         columns: usize,
         condition: Vec<Expr>,
+        order_by: Vec<OrderBy>,
+        limit: Option<usize>,
+        offset: usize,
     },
     SelectTable {
         table: usize,
     },
+    Aggregate {
+        table: usize,
+        group_by: Vec<usize>,
+        aggregates: Vec<Aggregate>,
+        having: Option<(usize, Vec<Expr>)>,
+    },
+    Join {
+        table: usize,
+        joins: Vec<Join>,
+        columns: Vec<usize>,
+    },
+    // each value is an `Expr::Value`, or an `Expr::Param` in a prepared statement
     Insert {
         table: usize,
-        values: Vec<Value>,
+        values: Vec<Expr>,
+    },
+    // inserts many rows at once, all of them or none
+    InsertBatch {
+        table: usize,
+        batch: Batch,
     },
     Update {
         table: usize,
         condition_column: usize,
         targets: Vec<(usize, Vec<Expr>)>,
         condition: Vec<Expr>,
-
+        returning: Returning,
     },
     UpdateAll {
         table: usize,
         targets: Vec<(usize, Vec<Expr>)>,
+        returning: Returning,
     },
     Delete {
         table: usize,
         column: usize,
         condition: Vec<Expr>,
+        returning: Returning,
     },
+    // answered with the position of the new table
     AddTable {
          columns: Vec<Types>
     },
     DropTable {
         id: usize,
     },
+    Begin,
+    Commit,
+    Rollback,
+    Savepoint {
+        name: String,
+    },
+    RollbackTo {
+        name: String,
+    },
+    Release {
+        name: String,
+    },
     Exit,
     FlushLogs,
-}
\ No newline at end of file
+    SetLogLevel {
+        level: Level,
+    },
+    // queries taking at least this long are written to the slow query log
+    SetSlowQueryThreshold {
+        millis: u64,
+    },
+    // runs `query` with its own deadline in place of the server's, 0 runs it without one
+    WithTimeout {
+        millis: u64,
+        query: Box<Query>,
+    },
+    // the key another connection needs to cancel the queries of this session
+    CancelKey,
+    // cancels the query the session with the given key is running, meant to be sent on a second connection
+    Cancel {
+        key: CancelKey,
+    },
+    // has to come before any other query when the server requires authentication
+    Login {
+        user: String,
+        password: Password,
+    },
+    CreateUser {
+        name: String,
+        password: Password,
+    },
+    DropUser {
+        name: String,
+    },
+    // admins can set every password, other users only their own
+    SetPassword {
+        name: String,
+        password: Password,
+    },
+    // on the table at the given position, or on every table, including tables added later, when there is none
+    Grant {
+        user: String,
+        privilege: Privilege,
+        table: Option<usize>,
+    },
+    Revoke {
+        user: String,
+        privilege: Privilege,
+        table: Option<usize>,
+    },
+    // validates `query` once and keeps it for the session under `name`. its expressions can hold
+    // `Expr::Param`s, bound every time it is executed
+    Prepare {
+        name: String,
+        query: Box<Query>,
+    },
+    // runs a prepared statement with `params` bound to its parameters, `Expr::Param(0)` to the first
+    Execute {
+        name: String,
+        params: Vec<Value>,
+    },
+    Deallocate {
+        name: String,
+    },
+    // reads csv rows into the table at `table`, checked against its column types, or into a new table
+    // typed after the rows when there is none
+    ImportCsv {
+        source: CsvSource,
+        table: Option<usize>,
+        options: CsvOptions,
+    },
+    // the result of `query`, which has to be a select, join or aggregate, as csv. written to `path` on
+    // the server, or sent back when there is none
+    ExportCsv {
+        query: Box<Query>,
+        path: Option<PathBuf>,
+        options: CsvOptions,
+    },
+    // reads an arrow ipc or parquet file into the table at `table`, or into a new table typed after
+    // the file's columns when there is none
+    ImportArrow {
+        source: ArrowSource,
+        format: ArrowFormat,
+        table: Option<usize>,
+    },
+    // the result of `query`, like for export csv, as an arrow ipc or parquet file
+    ExportArrow {
+        query: Box<Query>,
+        path: Option<PathBuf>,
+        format: ArrowFormat,
+    },
+}
+
+impl Query {
+    // the name of the variant, used to label metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            Query::Select { .. } => "Select",
+            Query::SelectTable { .. } => "SelectTable",
+            Query::Aggregate { .. } => "Aggregate",
+            Query::Join { .. } => "Join",
+            Query::Insert { .. } => "Insert",
+            Query::InsertBatch { .. } => "InsertBatch",
+            Query::Update { .. } => "Update",
+            Query::UpdateAll { .. } => "UpdateAll",
+            Query::Delete { .. } => "Delete",
+            Query::AddTable { .. } => "AddTable",
+            Query::DropTable { .. } => "DropTable",
+            Query::Begin => "Begin",
+            Query::Commit => "Commit",
+            Query::Rollback => "Rollback",
+            Query::Savepoint { .. } => "Savepoint",
+            Query::RollbackTo { .. } => "RollbackTo",
+            Query::Release { .. } => "Release",
+            Query::Exit => "Exit",
+            Query::FlushLogs => "FlushLogs",
+            Query::SetLogLevel { .. } => "SetLogLevel",
+            Query::SetSlowQueryThreshold { .. } => "SetSlowQueryThreshold",
+            Query::WithTimeout { .. } => "WithTimeout",
+            Query::CancelKey => "CancelKey",
+            Query::Cancel { .. } => "Cancel",
+            Query::Login { .. } => "Login",
+            Query::CreateUser { .. } => "CreateUser",
+            Query::DropUser { .. } => "DropUser",
+            Query::SetPassword { .. } => "SetPassword",
+            Query::Grant { .. } => "Grant",
+            Query::Revoke { .. } => "Revoke",
+            Query::Prepare { .. } => "Prepare",
+            Query::Execute { .. } => "Execute",
+            Query::Deallocate { .. } => "Deallocate",
+            Query::ImportCsv { .. } => "ImportCsv",
+            Query::ExportCsv { .. } => "ExportCsv",
+            Query::ImportArrow { .. } => "ImportArrow",
+            Query::ExportArrow { .. } => "ExportArrow",
+        }
+    }
+
+    // whether the server answers the query. every query but exit is answered, so a client learns
+    // when one is denied
+    pub fn has_reply(&self) -> bool {
+        match self {
+            Query::WithTimeout { query, .. } => query.has_reply(),
+            Query::Exit => false,
+            _ => true,
+        }
+    }
+
+    // the privileges a session needs to run the query, each on the table at a position or on every table.
+    // queries needing none, such as the ones of a transaction, can be run by every user
+    pub fn required_privileges(&self) -> Vec<(Privilege, Option<usize>)> {
+        match self {
+            Query::Select { table, .. } | Query::SelectTable { table } | Query::Aggregate { table, .. } => {
+                vec![(Privilege::Read, Some(*table))]
+            }
+            Query::Join { table, joins, .. } => {
+                std::iter::once(*table)
+                    .chain(joins.iter().map(|join| join.table))
+                    .map(|table| (Privilege::Read, Some(table)))
+                    .collect()
+            }
+            Query::Insert { table, .. } | Query::InsertBatch { table, .. } => vec![(Privilege::Write, Some(*table))],
+            // sending rows back reads them
+            Query::Update { table, returning, .. }
+            | Query::UpdateAll { table, returning, .. }
+            | Query::Delete { table, returning, .. } => match returning {
+                Returning::None => vec![(Privilege::Write, Some(*table))],
+                _ => vec![(Privilege::Write, Some(*table)), (Privilege::Read, Some(*table))],
+            },
+            Query::AddTable { .. } => vec![(Privilege::Ddl, None)],
+            Query::DropTable { id } => vec![(Privilege::Ddl, Some(*id))],
+            Query::FlushLogs
+            | Query::SetLogLevel { .. }
+            | Query::SetSlowQueryThreshold { .. }
+            | Query::CreateUser { .. }
+            | Query::DropUser { .. }
+            | Query::SetPassword { .. }
+            | Query::Grant { .. }
+            | Query::Revoke { .. } => vec![(Privilege::Admin, None)],
+            Query::WithTimeout { query, .. } | Query::Prepare { query, .. } => query.required_privileges(),
+            // files on the server are only for admins to read and write
+            Query::ImportCsv { source, table, .. } => {
+                import_privileges(*table, matches!(source, CsvSource::File(_)))
+            }
+            Query::ImportArrow { source, table, .. } => {
+                import_privileges(*table, matches!(source, ArrowSource::File(_)))
+            }
+            Query::ExportCsv { query, path, .. } | Query::ExportArrow { query, path, .. } => {
+                let mut privileges = query.required_privileges();
+                if path.is_some() {
+                    privileges.push((Privilege::Admin, None));
+                }
+                privileges
+            }
+            // checked once the statement is bound, as the query it was prepared from
+            Query::Execute { .. }
+            | Query::Deallocate { .. }
+            | Query::Begin
+            | Query::Commit
+            | Query::Rollback
+            | Query::Savepoint { .. }
+            | Query::RollbackTo { .. }
+            | Query::Release { .. }
+            | Query::Exit
+            | Query::CancelKey
+            | Query::Cancel { .. }
+            | Query::Login { .. } => vec![],
+        }
+    }
+}
+
+// writing to the table, or creating one when there is none, plus admin to read a file on the server
+fn import_privileges(table: Option<usize>, from_file: bool) -> Vec<(Privilege, Option<usize>)> {
+    let mut privileges = match table {
+        Some(table) => vec![(Privilege::Write, Some(table))],
+        None => vec![(Privilege::Ddl, None)],
+    };
+    if from_file {
+        privileges.push((Privilege::Admin, None));
+    }
+
+    privileges
+}
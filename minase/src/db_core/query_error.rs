@@ -1,6 +1,7 @@
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use serde_derive::{Deserialize, Serialize};
+use crate::db_core::csv::CsvRowError;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum QueryError {
@@ -12,7 +13,62 @@ pub enum QueryError {
     SizeMismatch,
     StackUnderflow,
     ColumnNotFound,
-    InvalidQuery
+    InvalidQuery,
+    NoTransaction,
+    TransactionInProgress,
+    SavepointNotFound,
+    WriteConflict,
+    Cancelled,
+    Timeout,
+    NotAuthenticated,
+    AuthenticationFailed,
+    PermissionDenied,
+    UserExists,
+    UserNotFound,
+    StatementExists,
+    StatementNotFound,
+    // a result too large for its column type, such as the sum of an int column
+    Overflow,
+    // the rows of a csv file that could not be read, the first ones when there were too many
+    InvalidCsv(Vec<CsvRowError>),
+    // a file the server was asked to read or write, with the reason it could not
+    FileError(String),
+    // an arrow ipc or parquet file that could not be read, or holds columns tables can not
+    InvalidArrow(String),
+}
+
+impl QueryError {
+    // the name of the variant, used to label metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            QueryError::TypeMismatch => "TypeMismatch",
+            QueryError::OperatorMismatch => "OperatorMismatch",
+            QueryError::NoOperation => "NoOperation",
+            QueryError::CellValueNotSet => "CellValueNotSet",
+            QueryError::TableNotFound => "TableNotFound",
+            QueryError::SizeMismatch => "SizeMismatch",
+            QueryError::StackUnderflow => "StackUnderflow",
+            QueryError::ColumnNotFound => "ColumnNotFound",
+            QueryError::InvalidQuery => "InvalidQuery",
+            QueryError::NoTransaction => "NoTransaction",
+            QueryError::TransactionInProgress => "TransactionInProgress",
+            QueryError::SavepointNotFound => "SavepointNotFound",
+            QueryError::WriteConflict => "WriteConflict",
+            QueryError::Cancelled => "Cancelled",
+            QueryError::Timeout => "Timeout",
+            QueryError::NotAuthenticated => "NotAuthenticated",
+            QueryError::AuthenticationFailed => "AuthenticationFailed",
+            QueryError::PermissionDenied => "PermissionDenied",
+            QueryError::UserExists => "UserExists",
+            QueryError::UserNotFound => "UserNotFound",
+            QueryError::StatementExists => "StatementExists",
+            QueryError::StatementNotFound => "StatementNotFound",
+            QueryError::Overflow => "Overflow",
+            QueryError::InvalidCsv(_) => "InvalidCsv",
+            QueryError::FileError(_) => "FileError",
+            QueryError::InvalidArrow(_) => "InvalidArrow",
+        }
+    }
 }
 
 impl Display for QueryError {
@@ -45,6 +101,64 @@ impl Display for QueryError {
             QueryError::InvalidQuery => {
                 write!(f, "Query Error: Invalid Query")
             }
+            QueryError::NoTransaction => {
+                write!(f, "Query Error: No Transaction")
+            }
+            QueryError::TransactionInProgress => {
+                write!(f, "Query Error: Transaction In Progress")
+            }
+            QueryError::SavepointNotFound => {
+                write!(f, "Query Error: Savepoint Not Found")
+            }
+            QueryError::WriteConflict => {
+                write!(f, "Query Error: Write Conflict")
+            }
+            QueryError::Cancelled => {
+                write!(f, "Query Error: Cancelled")
+            }
+            QueryError::Timeout => {
+                write!(f, "Query Error: Timeout")
+            }
+            QueryError::NotAuthenticated => {
+                write!(f, "Query Error: Not Authenticated")
+            }
+            QueryError::AuthenticationFailed => {
+                write!(f, "Query Error: Authentication Failed")
+            }
+            QueryError::PermissionDenied => {
+                write!(f, "Query Error: Permission Denied")
+            }
+            QueryError::UserExists => {
+                write!(f, "Query Error: User Exists")
+            }
+            QueryError::UserNotFound => {
+                write!(f, "Query Error: User Not Found")
+            }
+            QueryError::StatementExists => {
+                write!(f, "Query Error: Statement Exists")
+            }
+            QueryError::StatementNotFound => {
+                write!(f, "Query Error: Statement Not Found")
+            }
+            QueryError::Overflow => {
+                write!(f, "Query Error: Overflow")
+            }
+            QueryError::InvalidCsv(errors) => {
+                write!(f, "Query Error: Invalid Csv")?;
+                if let Some(first) = errors.first() {
+                    write!(f, ": line {}: {}", first.line, first.message)?;
+                }
+                match errors.len() {
+                    0 | 1 => Ok(()),
+                    count => write!(f, " and {} more", count - 1),
+                }
+            }
+            QueryError::FileError(reason) => {
+                write!(f, "Query Error: File Error: {}", reason)
+            }
+            QueryError::InvalidArrow(reason) => {
+                write!(f, "Query Error: Invalid Arrow: {}", reason)
+            }
         }
     }
 }
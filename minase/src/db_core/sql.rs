@@ -0,0 +1,974 @@
+use crate::db_core::database::{Batch, Returning, Table};
+use crate::db_core::ordering::{NullsOrder, OrderBy};
+use crate::db_core::query::Query;
+use crate::db_core::query_error::QueryError;
+use crate::db_core::values::{Expr, Types, Value};
+
+// translates a subset of sql into queries. tables and columns have no names, so they are written
+// by position: `t0` is the first table and `c0` the first column of a table
+#[derive(Clone, Debug, PartialEq)]
+pub enum SqlError {
+    // the text is not sql as understood here
+    Syntax(String),
+    // valid sql that has no query to run it
+    Unsupported(String),
+}
+
+impl std::fmt::Display for SqlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SqlError::Syntax(message) => write!(f, "Syntax Error: {}", message),
+            SqlError::Unsupported(message) => write!(f, "Unsupported: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SqlError {}
+
+// a translated statement. a query can only return whole rows, `columns` picks the ones the statement selected
+// or asked to be returned
+#[derive(Clone, Debug)]
+pub struct Statement {
+    pub query: Query,
+    pub columns: Option<Vec<usize>>,
+}
+
+impl Statement {
+    fn query(query: Query) -> Self {
+        Statement {
+            query,
+            columns: None,
+        }
+    }
+
+    // the columns of the query's result the statement asked for
+    pub fn project(&self, table: Table) -> Result<Table, QueryError> {
+        match &self.columns {
+            Some(columns) => table.project(columns),
+            None => Ok(table),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    // keywords and names, lowercase
+    Word(String),
+    Number(String),
+    String(String),
+    Symbol(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Number(number) => write!(f, "{}", number),
+            Token::String(string) => write!(f, "'{}'", string),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+// how deeply NOT and parentheses can nest in an expression. the parser recurses for each level,
+// so without a limit a long enough statement overflows the stack
+const MAX_DEPTH: usize = 64;
+
+// longer symbols first, so `<=` is not read as `<` followed by `=`
+const SYMBOLS: [&str; 15] = ["<>", "!=", "<=", ">=", "=", "<", ">", "+", "-", "*", "/", "(", ")", ",", ";"];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, SqlError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some(&(start, char)) = chars.peek() {
+        if char.is_whitespace() {
+            chars.next();
+        } else if text[start..].starts_with("--") {
+            while chars.next_if(|(_, char)| *char != '\n').is_some() {}
+        } else if char.is_ascii_alphabetic() || char == '_' {
+            let mut end = start;
+            while let Some((index, char)) = chars.next_if(|(_, char)| char.is_ascii_alphanumeric() || *char == '_') {
+                end = index + char.len_utf8();
+            }
+            tokens.push(Token::Word(text[start..end].to_ascii_lowercase()));
+        } else if char.is_ascii_digit() || char == '.' {
+            let mut end = start;
+            while let Some((index, char)) = chars.next_if(|(_, char)| char.is_ascii_digit() || *char == '.') {
+                end = index + char.len_utf8();
+            }
+            tokens.push(Token::Number(text[start..end].to_string()));
+        } else if char == '\'' {
+            chars.next();
+            let mut string = String::new();
+
+            // a quote inside a string is written twice
+            loop {
+                match chars.next() {
+                    Some((_, '\'')) if chars.next_if(|(_, char)| *char == '\'').is_some() => string.push('\''),
+                    Some((_, '\'')) => break,
+                    Some((_, char)) => string.push(char),
+                    None => return Err(SqlError::Syntax("unterminated string".to_string())),
+                }
+            }
+            tokens.push(Token::String(string));
+        } else {
+            let Some(symbol) = SYMBOLS.iter().find(|symbol| text[start..].starts_with(**symbol)) else {
+                return Err(SqlError::Syntax(format!("unexpected character {:?}", char)));
+            };
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            tokens.push(Token::Symbol(symbol));
+        }
+    }
+
+    Ok(tokens)
+}
+
+// the statements of `text`, separated by semicolons
+pub fn parse(text: &str) -> Result<Vec<Statement>, SqlError> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        position: 0,
+    };
+    let mut statements = Vec::new();
+
+    loop {
+        while parser.eat_symbol(";") {}
+        if parser.peek().is_none() {
+            return Ok(statements);
+        }
+
+        statements.push(parser.statement()?);
+
+        if parser.peek().is_some() && !parser.eat_symbol(";") {
+            return Err(parser.unexpected("end of statement"));
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn unexpected(&self, expected: &str) -> SqlError {
+        match self.peek() {
+            Some(token) => SqlError::Syntax(format!("expected {}, found {}", expected, token)),
+            None => SqlError::Syntax(format!("expected {}, found the end of the statement", expected)),
+        }
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word == keyword)
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek_keyword(keyword);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), SqlError> {
+        match self.eat_keyword(keyword) {
+            true => Ok(()),
+            false => Err(self.unexpected(&keyword.to_ascii_uppercase())),
+        }
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(found)) if *found == symbol);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), SqlError> {
+        match self.eat_symbol(symbol) {
+            true => Ok(()),
+            false => Err(self.unexpected(&format!("`{}`", symbol))),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, SqlError> {
+        match self.next() {
+            Some(Token::Word(word)) => Ok(word),
+            _ => {
+                self.position -= 1;
+                Err(self.unexpected("a name"))
+            }
+        }
+    }
+
+    // `prefix` followed by a position, such as `t0` or `c12`
+    fn position_name(&mut self, prefix: char, what: &str) -> Result<usize, SqlError> {
+        match self.peek() {
+            Some(Token::Word(word)) if word.starts_with(prefix) => {
+                let position = word[1..].parse()
+                    .map_err(|_| SqlError::Syntax(format!("{} has to be written as {}<number>, found {}", what, prefix, word)))?;
+                self.position += 1;
+                Ok(position)
+            }
+            _ => Err(self.unexpected(&format!("a {} such as {}0", what, prefix))),
+        }
+    }
+
+    fn table(&mut self) -> Result<usize, SqlError> {
+        self.position_name('t', "table")
+    }
+
+    fn column(&mut self) -> Result<usize, SqlError> {
+        self.position_name('c', "column")
+    }
+
+    fn count(&mut self) -> Result<usize, SqlError> {
+        match self.next() {
+            Some(Token::Number(number)) => number.parse()
+                .map_err(|_| SqlError::Syntax(format!("expected a count, found {}", number))),
+            _ => {
+                self.position -= 1;
+                Err(self.unexpected("a count"))
+            }
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, SqlError> {
+        match self.peek() {
+            Some(Token::Word(word)) => match word.as_str() {
+                "select" => self.select(),
+                "insert" => self.insert(),
+                "update" => self.update(),
+                "delete" => self.delete(),
+                "create" => self.create(),
+                "drop" => self.drop(),
+                "begin" | "start" | "commit" | "end" | "rollback" | "savepoint" | "release" => self.transaction(),
+                "alter" | "truncate" | "with" | "grant" | "revoke" | "copy" | "explain" | "merge" | "values" | "set"
+                | "show" | "reset" | "prepare" | "execute" | "deallocate" | "declare" | "fetch" | "close" | "lock"
+                | "vacuum" | "analyze" | "comment" | "call" | "do" | "listen" | "notify" | "discard" => {
+                    Err(SqlError::Unsupported(format!("{} statements", word.to_ascii_uppercase())))
+                }
+                _ => Err(self.unexpected("a statement")),
+            },
+            _ => Err(self.unexpected("a statement")),
+        }
+    }
+
+    fn transaction(&mut self) -> Result<Statement, SqlError> {
+        let query = match self.name()?.as_str() {
+            "begin" => {
+                self.eat_keyword("transaction");
+                Query::Begin
+            }
+            "start" => {
+                self.expect_keyword("transaction")?;
+                Query::Begin
+            }
+            "commit" | "end" => {
+                self.eat_keyword("transaction");
+                Query::Commit
+            }
+            "rollback" => {
+                self.eat_keyword("transaction");
+                match self.eat_keyword("to") {
+                    true => {
+                        self.eat_keyword("savepoint");
+                        Query::RollbackTo { name: self.name()? }
+                    }
+                    false => Query::Rollback,
+                }
+            }
+            "savepoint" => Query::Savepoint { name: self.name()? },
+            _ => {
+                self.eat_keyword("savepoint");
+                Query::Release { name: self.name()? }
+            }
+        };
+
+        Ok(Statement::query(query))
+    }
+
+    // `*` for every column, or a list of columns
+    fn columns(&mut self) -> Result<Option<Vec<usize>>, SqlError> {
+        if self.eat_symbol("*") {
+            return Ok(None);
+        }
+
+        let mut columns = vec![self.column()?];
+        while self.eat_symbol(",") {
+            columns.push(self.column()?);
+        }
+        Ok(Some(columns))
+    }
+
+    // WHERE <condition>, as the column the condition reads and the condition on its cell
+    fn condition(&mut self) -> Result<Option<(usize, Vec<Expr>)>, SqlError> {
+        if !self.eat_keyword("where") {
+            return Ok(None);
+        }
+
+        let (column, condition) = single_column(self.expression()?)?;
+        Ok(Some((column.unwrap_or(0), condition)))
+    }
+
+    // RETURNING <* | columns>
+    fn returning(&mut self) -> Result<Option<Option<Vec<usize>>>, SqlError> {
+        match self.eat_keyword("returning") {
+            true => Ok(Some(self.columns()?)),
+            false => Ok(None),
+        }
+    }
+
+    // SELECT <* | columns> FROM <table> [WHERE <condition>] [ORDER BY ...] [LIMIT <count>] [OFFSET <count>]
+    fn select(&mut self) -> Result<Statement, SqlError> {
+        self.expect_keyword("select")?;
+        let columns = self.columns()?;
+
+        self.expect_keyword("from")?;
+        let table = self.table()?;
+        let condition = self.condition()?;
+
+        let mut order_by = Vec::new();
+        if self.eat_keyword("order") {
+            self.expect_keyword("by")?;
+
+            loop {
+                let column = self.column()?;
+                let mut key = match self.eat_keyword("desc") {
+                    true => OrderBy::desc(column),
+                    false => {
+                        self.eat_keyword("asc");
+                        OrderBy::asc(column)
+                    }
+                };
+
+                if self.eat_keyword("nulls") {
+                    key.nulls = match self.name()?.as_str() {
+                        "first" => NullsOrder::First,
+                        "last" => NullsOrder::Last,
+                        _ => {
+                            self.position -= 1;
+                            return Err(self.unexpected("FIRST or LAST"));
+                        }
+                    };
+                }
+                order_by.push(key);
+
+                if !self.eat_symbol(",") {
+                    break;
+                }
+            }
+        }
+
+        let limit = match self.eat_keyword("limit") {
+            true => Some(self.count()?),
+            false => None,
+        };
+        let offset = match self.eat_keyword("offset") {
+            true => self.count()?,
+            false => 0,
+        };
+
+        let query = match (condition, order_by.is_empty() && limit.is_none() && offset == 0) {
+            (None, true) => Query::SelectTable { table },
+            (condition, _) => {
+                let (column, condition) = condition.unwrap_or_else(every_row);
+
+                Query::Select {
+                    table,
+                    columns: column,
+                    condition,
+                    order_by,
+                    limit,
+                    offset,
+                }
+            }
+        };
+
+        Ok(Statement {
+            query,
+            columns,
+        })
+    }
+
+    // INSERT INTO <table> VALUES (<value>, ...), ...
+    fn insert(&mut self) -> Result<Statement, SqlError> {
+        self.expect_keyword("insert")?;
+        self.expect_keyword("into")?;
+        let table = self.table()?;
+
+        if self.peek() == Some(&Token::Symbol("(")) {
+            return Err(SqlError::Unsupported("a column list in INSERT, values are given for every column in order".to_string()));
+        }
+
+        self.expect_keyword("values")?;
+        let mut rows = vec![self.row()?];
+        while self.eat_symbol(",") {
+            rows.push(self.row()?);
+        }
+
+        // several rows are inserted together, all or none of them
+        let query = match rows.len() {
+            1 => Query::Insert { table, values: rows.pop().unwrap().into_iter().map(Expr::Value).collect() },
+            _ => Query::InsertBatch { table, batch: Batch::Rows(rows) },
+        };
+
+        Ok(Statement::query(query))
+    }
+
+    // UPDATE <table> SET <column> = <expr>, ... [WHERE <condition>] [RETURNING <* | columns>]
+    fn update(&mut self) -> Result<Statement, SqlError> {
+        self.expect_keyword("update")?;
+        let table = self.table()?;
+        self.expect_keyword("set")?;
+
+        let mut targets = Vec::new();
+        loop {
+            let column = self.column()?;
+            self.expect_symbol("=")?;
+
+            // a new value is computed from the old value of its own column
+            let value = match single_column(self.expression()?)? {
+                (Some(read), _) if read != column => {
+                    return Err(SqlError::Unsupported(format!("setting c{} from another column", column)));
+                }
+                (_, value) => value,
+            };
+            targets.push((column, value));
+
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+
+        if self.peek_keyword("from") {
+            return Err(SqlError::Unsupported("FROM in UPDATE".to_string()));
+        }
+
+        let condition = self.condition()?;
+        let returning = self.returning()?;
+        let mode = match returning {
+            Some(_) => Returning::New,
+            None => Returning::None,
+        };
+
+        let query = match condition {
+            Some((condition_column, condition)) => Query::Update {
+                table,
+                condition_column,
+                targets,
+                condition,
+                returning: mode,
+            },
+            None => Query::UpdateAll {
+                table,
+                targets,
+                returning: mode,
+            },
+        };
+
+        Ok(Statement {
+            query,
+            columns: returning.flatten(),
+        })
+    }
+
+    // DELETE FROM <table> [WHERE <condition>] [RETURNING <* | columns>]
+    fn delete(&mut self) -> Result<Statement, SqlError> {
+        self.expect_keyword("delete")?;
+        self.expect_keyword("from")?;
+        let table = self.table()?;
+
+        if self.peek_keyword("using") {
+            return Err(SqlError::Unsupported("USING in DELETE".to_string()));
+        }
+
+        let (column, condition) = self.condition()?.unwrap_or_else(every_row);
+        let returning = self.returning()?;
+
+        Ok(Statement {
+            query: Query::Delete {
+                table,
+                column,
+                condition,
+                returning: match returning {
+                    Some(_) => Returning::Old,
+                    None => Returning::None,
+                },
+            },
+            columns: returning.flatten(),
+        })
+    }
+
+    // CREATE TABLE <name> (c0 <type>, c1 <type>, ...). tables are numbered in the order they are created,
+    // so the name is not kept
+    fn create(&mut self) -> Result<Statement, SqlError> {
+        self.expect_keyword("create")?;
+        if !self.eat_keyword("table") {
+            let what = self.name().unwrap_or_default().to_ascii_uppercase();
+            return Err(SqlError::Unsupported(format!("CREATE {} statements", what)));
+        }
+        if self.peek_keyword("if") {
+            return Err(SqlError::Unsupported("IF NOT EXISTS".to_string()));
+        }
+
+        self.name()?;
+        self.expect_symbol("(")?;
+
+        let mut columns = Vec::new();
+        loop {
+            // columns are read by position, a name has to say which one it is
+            if self.column()? != columns.len() {
+                self.position -= 1;
+                return Err(self.unexpected(&format!("c{}, columns are named after their position", columns.len())));
+            }
+            columns.push(self.column_type()?);
+
+            // stored columns have no nulls, every column is already not null
+            if self.eat_keyword("not") {
+                self.expect_keyword("null")?;
+            }
+            if let Some(Token::Word(word)) = self.peek() {
+                return Err(SqlError::Unsupported(format!("column constraints such as {}", word.to_ascii_uppercase())));
+            }
+
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        self.expect_symbol(")")?;
+
+        Ok(Statement::query(Query::AddTable { columns }))
+    }
+
+    fn column_type(&mut self) -> Result<Types, SqlError> {
+        let name = self.name()?;
+
+        let types = match name.as_str() {
+            "int" | "integer" | "int4" | "smallint" | "int2" => Types::Int,
+            "real" | "float4" | "float" => Types::Float,
+            "text" | "varchar" => Types::String,
+            "bool" | "boolean" => Types::Bool,
+            "bigint" | "int8" => {
+                return Err(SqlError::Unsupported("BIGINT, Int columns are 32 bits, use INTEGER".to_string()));
+            }
+            "double" | "float8" | "numeric" | "decimal" => {
+                return Err(SqlError::Unsupported(format!("{}, Float columns are 32 bits, use REAL", name.to_ascii_uppercase())));
+            }
+            _ => return Err(SqlError::Unsupported(format!("the type {}", name.to_ascii_uppercase()))),
+        };
+
+        // the length of a varchar is not enforced
+        if types == Types::String && self.eat_symbol("(") {
+            self.count()?;
+            self.expect_symbol(")")?;
+        }
+
+        Ok(types)
+    }
+
+    // DROP TABLE <table>
+    fn drop(&mut self) -> Result<Statement, SqlError> {
+        self.expect_keyword("drop")?;
+        if !self.eat_keyword("table") {
+            let what = self.name().unwrap_or_default().to_ascii_uppercase();
+            return Err(SqlError::Unsupported(format!("DROP {} statements", what)));
+        }
+        if self.peek_keyword("if") {
+            return Err(SqlError::Unsupported("IF EXISTS".to_string()));
+        }
+
+        let id = self.table()?;
+        if self.peek() == Some(&Token::Symbol(",")) {
+            return Err(SqlError::Unsupported("dropping more than one table at once".to_string()));
+        }
+        if self.peek_keyword("cascade") || self.peek_keyword("restrict") {
+            return Err(SqlError::Unsupported("CASCADE and RESTRICT".to_string()));
+        }
+
+        Ok(Statement::query(Query::DropTable { id }))
+    }
+
+    // (<value>, ...)
+    fn row(&mut self) -> Result<Vec<Value>, SqlError> {
+        self.expect_symbol("(")?;
+
+        let mut values = vec![self.literal()?];
+        while self.eat_symbol(",") {
+            values.push(self.literal()?);
+        }
+
+        self.expect_symbol(")")?;
+        Ok(values)
+    }
+
+    fn literal(&mut self) -> Result<Value, SqlError> {
+        let negative = self.eat_symbol("-");
+
+        match self.next() {
+            Some(Token::Number(number)) => number_value(&number, negative),
+            Some(Token::String(string)) if !negative => Ok(Value::String(string)),
+            Some(Token::Word(word)) if !negative && word == "true" => Ok(Value::Bool(true)),
+            Some(Token::Word(word)) if !negative && word == "false" => Ok(Value::Bool(false)),
+            Some(Token::Word(word)) if !negative && word == "null" => {
+                Err(SqlError::Unsupported("NULL, stored columns have no nulls".to_string()))
+            }
+            _ => {
+                self.position -= 1;
+                Err(self.unexpected("a value"))
+            }
+        }
+    }
+
+    // an expression in rpn, with the columns it reads as `Expr::Column`.
+    // comparisons bind loosest, then `+` and `-`, then `*` and `/`
+    fn expression(&mut self) -> Result<Vec<Expr>, SqlError> {
+        let mut expr = Vec::new();
+        self.comparison(&mut expr, 0)?;
+
+        if self.peek_keyword("and") || self.peek_keyword("or") {
+            return Err(SqlError::Unsupported("AND and OR, a condition is a single comparison".to_string()));
+        }
+
+        Ok(expr)
+    }
+
+    // `depth` counts the NOT and parentheses the comparison is inside of
+    fn comparison(&mut self, expr: &mut Vec<Expr>, depth: usize) -> Result<(), SqlError> {
+        if depth > MAX_DEPTH {
+            return Err(SqlError::Unsupported(format!("expressions nested more than {} levels deep", MAX_DEPTH)));
+        }
+
+        if self.eat_keyword("not") {
+            self.comparison(expr, depth + 1)?;
+            expr.push(Expr::Not);
+            return Ok(());
+        }
+
+        self.additive(expr, depth)?;
+
+        let operator = match self.peek() {
+            Some(Token::Symbol("=")) => Expr::Eq,
+            Some(Token::Symbol("<>")) | Some(Token::Symbol("!=")) => Expr::Neq,
+            Some(Token::Symbol("<")) => Expr::Lt,
+            Some(Token::Symbol(">")) => Expr::Gt,
+            Some(Token::Symbol("<=")) => Expr::LtEq,
+            Some(Token::Symbol(">=")) => Expr::GtEq,
+            _ => return Ok(()),
+        };
+        self.position += 1;
+
+        self.additive(expr, depth)?;
+        expr.push(operator);
+        Ok(())
+    }
+
+    fn additive(&mut self, expr: &mut Vec<Expr>, depth: usize) -> Result<(), SqlError> {
+        self.multiplicative(expr, depth)?;
+
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Symbol("+")) => Expr::Add,
+                Some(Token::Symbol("-")) => Expr::Sub,
+                _ => return Ok(()),
+            };
+            self.position += 1;
+
+            self.multiplicative(expr, depth)?;
+            expr.push(operator);
+        }
+    }
+
+    fn multiplicative(&mut self, expr: &mut Vec<Expr>, depth: usize) -> Result<(), SqlError> {
+        self.operand(expr, depth)?;
+
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Symbol("*")) => Expr::Mul,
+                Some(Token::Symbol("/")) => Expr::Div,
+                _ => return Ok(()),
+            };
+            self.position += 1;
+
+            self.operand(expr, depth)?;
+            expr.push(operator);
+        }
+    }
+
+    fn operand(&mut self, expr: &mut Vec<Expr>, depth: usize) -> Result<(), SqlError> {
+        if self.eat_symbol("(") {
+            self.comparison(expr, depth + 1)?;
+            return self.expect_symbol(")");
+        }
+
+        if let Some(Token::Word(word)) = self.peek() {
+            if word.starts_with('c') && word[1..].parse::<usize>().is_ok() {
+                expr.push(Expr::Column(self.column()?));
+                return Ok(());
+            }
+        }
+
+        expr.push(Expr::Value(self.literal()?));
+        Ok(())
+    }
+}
+
+fn number_value(number: &str, negative: bool) -> Result<Value, SqlError> {
+    let number = match negative {
+        true => format!("-{}", number),
+        false => number.to_string(),
+    };
+
+    let value = match number.contains('.') {
+        true => number.parse().ok().map(Value::Float),
+        false => number.parse().ok().map(Value::Int),
+    };
+
+    value.ok_or_else(|| SqlError::Syntax(format!("{} is not a number that fits a column", number)))
+}
+
+// a query needs a condition, every row matches one that is always true
+fn every_row() -> (usize, Vec<Expr>) {
+    (0, vec![Expr::Value(Value::Bool(true))])
+}
+
+// an expression runs on the cell of one column. the column it reads, if any, and the expression reading that cell
+fn single_column(expr: Vec<Expr>) -> Result<(Option<usize>, Vec<Expr>), SqlError> {
+    let mut column = None;
+
+    for part in &expr {
+        if let Expr::Column(id) = part {
+            match column {
+                Some(column) if column != *id => {
+                    return Err(SqlError::Unsupported("an expression reading more than one column".to_string()));
+                }
+                _ => column = Some(*id),
+            }
+        }
+    }
+
+    let expr = expr.into_iter()
+        .map(|part| match part {
+            Expr::Column(_) => Expr::Cell,
+            part => part,
+        })
+        .collect();
+
+    Ok((column, expr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_core::ordering::Direction;
+
+    fn statement(text: &str) -> Statement {
+        let mut statements = parse(text).unwrap();
+        assert_eq!(statements.len(), 1);
+        statements.pop().unwrap()
+    }
+
+    // values have no equality, expressions are compared by their debug form
+    fn rpn(expr: &[Expr]) -> String {
+        format!("{:?}", expr)
+    }
+
+    fn where_condition(text: &str) -> (usize, String) {
+        match statement(&format!("SELECT * FROM t0 WHERE {}", text)).query {
+            Query::Select { columns, condition, .. } => (columns, rpn(&condition)),
+            query => panic!("expected a select, found {:?}", query),
+        }
+    }
+
+    fn unsupported(text: &str) {
+        assert!(matches!(parse(text), Err(SqlError::Unsupported(_))), "{} was not unsupported: {:?}", text, parse(text));
+    }
+
+    #[test]
+    fn translates_select() {
+        let select = statement("select c1, c0 from t2 where c1 >= 'a' order by c0 desc nulls first, c1 limit 5 offset 2");
+        assert_eq!(select.columns, Some(vec![1, 0]));
+
+        let Query::Select { table, columns, condition, order_by, limit, offset } = select.query else {
+            panic!("expected a select, found {:?}", select.query);
+        };
+        assert_eq!((table, columns, limit, offset), (2, 1, Some(5), 2));
+        assert_eq!(rpn(&condition), rpn(&[Expr::Cell, Expr::Value(Value::String("a".to_string())), Expr::GtEq]));
+        assert_eq!(order_by.len(), 2);
+        assert_eq!((order_by[0].column, order_by[0].direction, order_by[0].nulls), (0, Direction::Descending, NullsOrder::First));
+        assert_eq!((order_by[1].column, order_by[1].direction), (1, Direction::Ascending));
+
+        let select = statement("SELECT * FROM t0");
+        assert_eq!(select.columns, None);
+        assert!(matches!(select.query, Query::SelectTable { table: 0 }));
+    }
+
+    #[test]
+    fn translates_insert() {
+        let Query::Insert { table, values } = statement("INSERT INTO t1 VALUES (1, -2.5, 'it''s', true)").query else {
+            panic!("expected an insert");
+        };
+        assert_eq!(table, 1);
+        assert_eq!(rpn(&values), rpn(&[
+            Expr::Value(Value::Int(1)),
+            Expr::Value(Value::Float(-2.5)),
+            Expr::Value(Value::String("it's".to_string())),
+            Expr::Value(Value::Bool(true)),
+        ]));
+
+        let Query::InsertBatch { table, batch: Batch::Rows(rows) } = statement("INSERT INTO t0 VALUES (1), (2)").query else {
+            panic!("expected a batch of rows");
+        };
+        assert_eq!(table, 0);
+        assert_eq!(format!("{:?}", rows), format!("{:?}", vec![vec![Value::Int(1)], vec![Value::Int(2)]]));
+    }
+
+    #[test]
+    fn translates_update() {
+        let update = statement("UPDATE t0 SET c1 = c1 * 2, c2 = 'x' WHERE c0 = 3 RETURNING c1");
+        assert_eq!(update.columns, Some(vec![1]));
+
+        let Query::Update { table, condition_column, targets, condition, returning } = update.query else {
+            panic!("expected an update, found {:?}", update.query);
+        };
+        assert_eq!((table, condition_column, returning), (0, 0, Returning::New));
+        assert_eq!(rpn(&condition), rpn(&[Expr::Cell, Expr::Value(Value::Int(3)), Expr::Eq]));
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0].0, 1);
+        assert_eq!(rpn(&targets[0].1), rpn(&[Expr::Cell, Expr::Value(Value::Int(2)), Expr::Mul]));
+        assert_eq!(targets[1].0, 2);
+        assert_eq!(rpn(&targets[1].1), rpn(&[Expr::Value(Value::String("x".to_string()))]));
+
+        assert!(matches!(statement("UPDATE t3 SET c0 = 1").query, Query::UpdateAll { table: 3, returning: Returning::None, .. }));
+    }
+
+    #[test]
+    fn translates_delete() {
+        let delete = statement("DELETE FROM t1 WHERE c2 < 0 RETURNING *");
+        assert_eq!(delete.columns, None);
+
+        let Query::Delete { table, column, condition, returning } = delete.query else {
+            panic!("expected a delete, found {:?}", delete.query);
+        };
+        assert_eq!((table, column, returning), (1, 2, Returning::Old));
+        assert_eq!(rpn(&condition), rpn(&[Expr::Cell, Expr::Value(Value::Int(0)), Expr::Lt]));
+
+        // without a condition every row matches
+        let Query::Delete { condition, returning, .. } = statement("DELETE FROM t0").query else {
+            panic!("expected a delete");
+        };
+        assert_eq!(returning, Returning::None);
+        assert_eq!(rpn(&condition), rpn(&[Expr::Value(Value::Bool(true))]));
+    }
+
+    #[test]
+    fn translates_create_and_drop_table() {
+        let Query::AddTable { columns } = statement("CREATE TABLE people (c0 INTEGER NOT NULL, c1 varchar(20), c2 REAL, c3 BOOLEAN)").query else {
+            panic!("expected an add table");
+        };
+        assert_eq!(columns, vec![Types::Int, Types::String, Types::Float, Types::Bool]);
+
+        assert!(matches!(statement("DROP TABLE t4").query, Query::DropTable { id: 4 }));
+    }
+
+    #[test]
+    fn translates_transactions() {
+        let statements = parse("BEGIN; SAVEPOINT a; ROLLBACK TO SAVEPOINT a; RELEASE a; COMMIT; ROLLBACK").unwrap();
+        let queries: Vec<_> = statements.iter().map(|statement| statement.query.name()).collect();
+        assert_eq!(queries, vec!["Begin", "Savepoint", "RollbackTo", "Release", "Commit", "Rollback"]);
+    }
+
+    #[test]
+    fn follows_operator_precedence() {
+        let (column, condition) = where_condition("c0 + 2 * 3 > 4");
+        assert_eq!(column, 0);
+        assert_eq!(condition, rpn(&[
+            Expr::Cell,
+            Expr::Value(Value::Int(2)),
+            Expr::Value(Value::Int(3)),
+            Expr::Mul,
+            Expr::Add,
+            Expr::Value(Value::Int(4)),
+            Expr::Gt,
+        ]));
+
+        // parentheses group first, operators of the same level from the left
+        assert_eq!(where_condition("(c1 + 2) * 3 = 9"), (1, rpn(&[
+            Expr::Cell,
+            Expr::Value(Value::Int(2)),
+            Expr::Add,
+            Expr::Value(Value::Int(3)),
+            Expr::Mul,
+            Expr::Value(Value::Int(9)),
+            Expr::Eq,
+        ])));
+        assert_eq!(where_condition("c0 - 1 - 2 <> 0").1, rpn(&[
+            Expr::Cell,
+            Expr::Value(Value::Int(1)),
+            Expr::Sub,
+            Expr::Value(Value::Int(2)),
+            Expr::Sub,
+            Expr::Value(Value::Int(0)),
+            Expr::Neq,
+        ]));
+        assert_eq!(where_condition("NOT c0 / 2 < 1").1, rpn(&[
+            Expr::Cell,
+            Expr::Value(Value::Int(2)),
+            Expr::Div,
+            Expr::Value(Value::Int(1)),
+            Expr::Lt,
+            Expr::Not,
+        ]));
+    }
+
+    #[test]
+    fn rejects_unsupported_sql() {
+        unsupported("SELECT * FROM t0 WHERE c0 = 1 AND c0 < 5");
+        unsupported("SELECT * FROM t0 WHERE c0 = 1 OR c0 = 2");
+        unsupported("SELECT * FROM t0 WHERE c0 = c1");
+        unsupported("UPDATE t0 SET c0 = c1");
+        unsupported("INSERT INTO t0 (c0) VALUES (1)");
+        unsupported("INSERT INTO t0 VALUES (NULL)");
+        unsupported("CREATE TABLE x (c0 BIGINT)");
+        unsupported("CREATE TABLE x (c0 INTEGER PRIMARY KEY)");
+        unsupported("CREATE INDEX i ON t0 (c0)");
+        unsupported("DROP TABLE IF EXISTS t0");
+        unsupported("ALTER TABLE t0 ADD COLUMN c1 INTEGER");
+        unsupported("DELETE FROM t0 USING t1");
+
+        assert_eq!(parse("SELECT FROM t0").err(), Some(SqlError::Syntax("expected a column such as c0, found from".to_string())));
+        assert_eq!(parse("SELECT * FROM t0 WHERE c0 = 'a").err(), Some(SqlError::Syntax("unterminated string".to_string())));
+        assert_eq!(parse("SELECT * FROM people").err(), Some(SqlError::Syntax("expected a table such as t0, found people".to_string())));
+        assert_eq!(parse("CREATE TABLE x (c1 INTEGER)").err(), Some(SqlError::Syntax("expected c0, columns are named after their position, found c1".to_string())));
+        assert!(matches!(parse("SELECT * FROM t0 # 1"), Err(SqlError::Syntax(_))));
+    }
+
+    #[test]
+    fn rejects_deeply_nested_expressions() {
+        let nots = "NOT ".repeat(5000);
+        assert!(matches!(parse(&format!("SELECT * FROM t0 WHERE {}1 = 1", nots)), Err(SqlError::Unsupported(_))));
+
+        let parentheses = format!("{}1{}", "(".repeat(5000), ")".repeat(5000));
+        assert!(matches!(parse(&format!("SELECT * FROM t0 WHERE c0 = {}", parentheses)), Err(SqlError::Unsupported(_))));
+
+        // up to the limit is fine
+        let nots = "NOT ".repeat(MAX_DEPTH);
+        assert!(parse(&format!("SELECT * FROM t0 WHERE {}c0 = 1", nots)).is_ok());
+        let parentheses = format!("{}c0{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(parse(&format!("SELECT * FROM t0 WHERE {} = 1", parentheses)).is_ok());
+    }
+}
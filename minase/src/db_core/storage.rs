@@ -0,0 +1,236 @@
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex as AsyncMutex, MutexGuard};
+use crate::db_core::database::Table;
+
+
+// the committed state of the database at one point in time.
+// tables are shared between snapshots and only copied when a session writes to them,
+// a version of a table is freed as soon as the last snapshot that holds it is dropped
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+    pub(crate) tables: Vec<Arc<Table>>,
+    // bumped by every commit that adds or drops a table, since that changes the table ids
+    pub(crate) schema_version: u64,
+}
+
+// writes `file_name` in `data_dir`. the previous file is only replaced once the new one is complete,
+// so a crash part way leaves the last good version in place
+pub(crate) fn write_atomically(data_dir: &Path, file_name: &str, bytes: &[u8], fsync: bool) -> Result<(), std::io::Error> {
+    std::fs::create_dir_all(data_dir)?;
+
+    let path = data_dir.join(file_name);
+    let partial = data_dir.join(format!("{}.partial", file_name));
+
+    let mut file = std::fs::File::create(&partial)?;
+    file.write_all(bytes)?;
+    if fsync {
+        file.sync_all()?;
+    }
+    drop(file);
+
+    std::fs::rename(&partial, &path)?;
+    if fsync {
+        std::fs::File::open(data_dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+// the file in the data directory holding the last saved snapshot
+const SNAPSHOT_FILE: &str = "snapshot.mpk";
+
+impl Snapshot {
+    pub fn tables(&self) -> &[Arc<Table>] {
+        &self.tables
+    }
+
+    // whether both snapshots hold the very same versions of the same tables
+    pub fn same_as(&self, other: &Snapshot) -> bool {
+        self.tables.len() == other.tables.len()
+            && self.tables.iter().zip(&other.tables).all(|(table, other)| Arc::ptr_eq(table, other))
+    }
+
+    // writes the tables to the snapshot file in `data_dir`
+    pub fn save(&self, data_dir: &Path, fsync: bool) -> Result<(), std::io::Error> {
+        let mut buffer = vec![];
+        let tables: Vec<&Table> = self.tables.iter().map(|table| table.as_ref()).collect();
+        tables.serialize(&mut rmp_serde::Serializer::new(&mut buffer))
+            .map_err(std::io::Error::other)?;
+
+        write_atomically(data_dir, SNAPSHOT_FILE, &buffer, fsync)
+    }
+}
+
+// the tables shared by every session of a server
+#[derive(Debug)]
+pub struct Store {
+    committed: Mutex<Snapshot>,
+    // held for the whole of a statement that writes outside of a transaction and for the
+    // validation of a commit, so writers are serialized while readers never wait on it
+    writer: AsyncMutex<()>,
+    // the oid the next table added gets
+    next_oid: AtomicU64,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Store::new()
+    }
+}
+
+impl Store {
+    pub fn new() -> Self {
+        Store {
+            committed: Mutex::new(Snapshot::default()),
+            writer: AsyncMutex::new(()),
+            next_oid: AtomicU64::new(1),
+        }
+    }
+
+    // a store holding the tables saved in `data_dir`, or no tables when nothing was saved there yet
+    pub fn load(data_dir: &Path) -> Result<Self, std::io::Error> {
+        let buffer = match std::fs::read(data_dir.join(SNAPSHOT_FILE)) {
+            Ok(buffer) => buffer,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Store::new()),
+            Err(err) => return Err(err),
+        };
+
+        let mut tables = Vec::<Table>::deserialize(&mut rmp_serde::Deserializer::new(&buffer[..]))
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+
+        // tables saved before they had oids get theirs now
+        let mut next_oid = tables.iter().map(|table| table.oid).max().unwrap_or(0) + 1;
+        for table in tables.iter_mut().filter(|table| table.oid == 0) {
+            table.oid = next_oid;
+            next_oid += 1;
+        }
+
+        Ok(Store {
+            committed: Mutex::new(Snapshot {
+                tables: tables.into_iter().map(Arc::new).collect(),
+                schema_version: 0,
+            }),
+            writer: AsyncMutex::new(()),
+            next_oid: AtomicU64::new(next_oid),
+        })
+    }
+
+    pub(crate) fn allocate_oid(&self) -> u64 {
+        self.next_oid.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.committed.lock().unwrap().clone()
+    }
+
+    pub async fn lock_writer(&self) -> MutexGuard<'_, ()> {
+        self.writer.lock().await
+    }
+
+    // replaces the committed tables, the caller has to hold the writer lock
+    pub(crate) fn publish(&self, tables: Vec<Arc<Table>>, schema_changed: bool) {
+        let mut committed = self.committed.lock().unwrap();
+
+        committed.tables = tables;
+        if schema_changed {
+            committed.schema_version += 1;
+        }
+    }
+
+    // applies the changes a transaction made on top of `base`, the caller has to hold the writer lock.
+    // the first transaction to commit a change to a table wins, a later one that changed the same
+    // table from the same base fails and leaves the committed tables untouched
+    pub(crate) fn commit(&self, base: &Snapshot, tables: Vec<Arc<Table>>, schema_changed: bool) -> bool {
+        let mut committed = self.committed.lock().unwrap();
+
+        let unchanged_since = |id: usize| Arc::ptr_eq(&committed.tables[id], &base.tables[id]);
+        let written = (0..tables.len().min(base.tables.len()))
+            .filter(|id| !Arc::ptr_eq(&tables[*id], &base.tables[*id]))
+            .collect::<Vec<usize>>();
+
+        if schema_changed {
+            // table ids of the transaction are only valid if nothing was committed since it began
+            if committed.schema_version != base.schema_version || !(0..base.tables.len()).all(unchanged_since) {
+                return false;
+            }
+
+            committed.tables = tables;
+            committed.schema_version += 1;
+            return true;
+        }
+
+        if written.is_empty() {
+            return true;
+        }
+
+        if committed.schema_version != base.schema_version || !written.iter().all(|id| unchanged_since(*id)) {
+            return false;
+        }
+
+        for id in written {
+            committed.tables[id] = tables[id].clone();
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_core::values::{Column, Types};
+
+    fn table(oid: u64) -> Arc<Table> {
+        let mut table = Table::new(vec![Column::empty(&Types::Int)], vec![Types::Int]);
+        table.oid = oid;
+        Arc::new(table)
+    }
+
+    // `base` with the table at `id` replaced by a new version, as a transaction writing to it leaves it
+    fn written(base: &Snapshot, id: usize) -> Vec<Arc<Table>> {
+        let mut tables = base.tables.clone();
+        tables[id] = Arc::new(tables[id].as_ref().clone());
+        tables
+    }
+
+    #[test]
+    fn commit_rejects_a_conflicting_write() {
+        let store = Store::new();
+        store.publish(vec![table(1), table(2)], true);
+        let base = store.snapshot();
+
+        let first = written(&base, 0);
+        assert!(store.commit(&base, first.clone(), false));
+        assert!(Arc::ptr_eq(&store.snapshot().tables[0], &first[0]));
+
+        // a second transaction from the same base wrote the same table
+        let second = written(&base, 0);
+        assert!(!store.commit(&base, second, false));
+        assert!(Arc::ptr_eq(&store.snapshot().tables[0], &first[0]));
+
+        // a write to another table does not conflict, and keeps the first commit
+        let other = written(&base, 1);
+        assert!(store.commit(&base, other.clone(), false));
+        let committed = store.snapshot();
+        assert!(Arc::ptr_eq(&committed.tables[0], &first[0]));
+        assert!(Arc::ptr_eq(&committed.tables[1], &other[1]));
+    }
+
+    #[test]
+    fn commit_rejects_a_schema_change_after_a_write() {
+        let store = Store::new();
+        store.publish(vec![table(1)], true);
+        let base = store.snapshot();
+
+        assert!(store.commit(&base, written(&base, 0), false));
+
+        let mut added = base.tables.clone();
+        added.push(table(2));
+        assert!(!store.commit(&base, added, true));
+        assert_eq!(store.snapshot().tables.len(), 1);
+    }
+}
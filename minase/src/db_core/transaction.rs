@@ -0,0 +1,148 @@
+use std::sync::Arc;
+use crate::db_core::database::Table;
+use crate::db_core::storage::Snapshot;
+
+
+// an open transaction of a session. the session works on its own versions of the tables taken from
+// `base`, which is checked against the committed tables and published on commit. `base` and the
+// savepoints hold on to table versions rather than copies, a write copies only the chunks it changes
+#[derive(Debug)]
+pub struct Transaction {
+    pub(crate) base: Snapshot,
+    savepoints: Vec<(String, Vec<Arc<Table>>, bool)>,
+}
+
+impl Transaction {
+    pub fn begin(base: Snapshot) -> Self {
+        Transaction {
+            base,
+            savepoints: Vec::new(),
+        }
+    }
+
+    pub fn savepoint(&mut self, name: String, tables: &[Arc<Table>], schema_changed: bool) {
+        self.savepoints.push((name, tables.to_vec(), schema_changed));
+    }
+
+    // the tables as they were when the most recent savepoint called `name` was taken.
+    // later savepoints are discarded, the savepoint itself stays so it can be rolled back to again
+    pub fn rollback_to(&mut self, name: &str) -> Option<(Vec<Arc<Table>>, bool)> {
+        let position = self.savepoints.iter().rposition(|(savepoint, ..)| savepoint == name)?;
+        self.savepoints.truncate(position + 1);
+
+        let (_, tables, schema_changed) = &self.savepoints[position];
+        Some((tables.clone(), *schema_changed))
+    }
+
+    // forgets the most recent savepoint called `name` and every savepoint taken after it
+    pub fn release(&mut self, name: &str) -> bool {
+        match self.savepoints.iter().rposition(|(savepoint, ..)| savepoint == name) {
+            Some(position) => {
+                self.savepoints.truncate(position);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use logger::Logger;
+    use crate::db_core::database::{Database, Returning};
+    use crate::db_core::query_error::QueryError;
+    use crate::db_core::storage::Store;
+    use crate::db_core::values::{Column, Expr, Types, Value};
+
+    // the int column of table 0 as the session sees it
+    async fn rows(db: &mut Database) -> Vec<i32> {
+        match &db.select_table(0).await.unwrap().columns[0] {
+            Column::Int(values) => values.to_vec(),
+            column => panic!("expected an int column, found {:?}", column),
+        }
+    }
+
+    async fn database() -> (Database, Arc<Store>) {
+        let store = Arc::new(Store::new());
+        let mut db = Database::with_store(Logger::noop(), store.clone());
+        db.add_table(vec![Types::Int, Types::String]).await.unwrap();
+        db.insert(0, vec![Value::Int(1), Value::String("a".to_string())]).await.unwrap();
+
+        (db, store)
+    }
+
+    async fn insert(db: &mut Database, value: i32) {
+        db.insert(0, vec![Value::Int(value), Value::String("b".to_string())]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_statement_keeps_earlier_statements() {
+        let (mut db, store) = database().await;
+
+        db.begin().await.unwrap();
+        insert(&mut db, 2).await;
+
+        // the first target is set on every row before the second fails on its type
+        let failed = db.update_all(0, vec![
+            (0, vec![Expr::Cell, Expr::Value(Value::Int(10)), Expr::Add]),
+            (1, vec![Expr::Value(Value::Int(0))]),
+        ], Returning::None).await;
+        assert!(matches!(failed, Err(QueryError::TypeMismatch)));
+        assert_eq!(rows(&mut db).await, vec![1, 2]);
+
+        insert(&mut db, 3).await;
+        db.commit().await.unwrap();
+
+        assert_eq!(store.snapshot().tables()[0].row_count(), 3);
+        assert_eq!(rows(&mut db).await, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn rollback_to_restores_the_savepoint() {
+        let (mut db, store) = database().await;
+
+        db.begin().await.unwrap();
+        insert(&mut db, 2).await;
+        db.savepoint("a".to_string()).await.unwrap();
+
+        insert(&mut db, 3).await;
+        db.update_all(0, vec![(0, vec![Expr::Value(Value::Int(0))])], Returning::None).await.unwrap();
+        db.add_table(vec![Types::Bool]).await.unwrap();
+        assert_eq!(rows(&mut db).await, vec![0, 0, 0]);
+
+        db.rollback_to("a".to_string()).await.unwrap();
+        assert_eq!(rows(&mut db).await, vec![1, 2]);
+        assert!(matches!(db.select_table(1).await, Err(QueryError::TableNotFound)));
+
+        // the savepoint stays, so it can be rolled back to again
+        insert(&mut db, 4).await;
+        db.rollback_to("a".to_string()).await.unwrap();
+        assert_eq!(rows(&mut db).await, vec![1, 2]);
+
+        db.commit().await.unwrap();
+        assert_eq!(store.snapshot().tables().len(), 1);
+        assert_eq!(rows(&mut db).await, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn release_keeps_the_changes() {
+        let (mut db, store) = database().await;
+
+        db.begin().await.unwrap();
+        db.savepoint("a".to_string()).await.unwrap();
+        insert(&mut db, 2).await;
+        db.savepoint("b".to_string()).await.unwrap();
+        insert(&mut db, 3).await;
+
+        db.release("a".to_string()).await.unwrap();
+        assert_eq!(rows(&mut db).await, vec![1, 2, 3]);
+
+        // savepoints taken after the released one are gone as well
+        assert!(matches!(db.rollback_to("a".to_string()).await, Err(QueryError::SavepointNotFound)));
+        assert!(matches!(db.rollback_to("b".to_string()).await, Err(QueryError::SavepointNotFound)));
+
+        db.commit().await.unwrap();
+        assert_eq!(store.snapshot().tables()[0].row_count(), 3);
+    }
+}
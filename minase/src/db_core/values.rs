@@ -1,4 +1,5 @@
 use serde_derive::{Deserialize, Serialize};
+use crate::db_core::chunks::Chunks;
 use crate::db_core::query_error::QueryError;
 
 macro_rules! evaluate {
@@ -61,6 +62,17 @@ macro_rules! evaluate {
                             format!("{} on table {}, column {}: invalid query", stringify!($operation), $table, $column)
                         ).await;
                     }
+                    QueryError::NoTransaction | QueryError::TransactionInProgress | QueryError::SavepointNotFound | QueryError::WriteConflict
+                        | QueryError::Cancelled | QueryError::Timeout | QueryError::NotAuthenticated
+                        | QueryError::AuthenticationFailed | QueryError::PermissionDenied
+                        | QueryError::UserExists | QueryError::UserNotFound
+                        | QueryError::StatementExists | QueryError::StatementNotFound | QueryError::Overflow
+                        | QueryError::InvalidCsv(_) | QueryError::FileError(_) | QueryError::InvalidArrow(_) => {
+                        $logger.error(
+                            "Unexpected Error".to_string(),
+                            format!("{} on table {}, column {}: {}", stringify!($operation), $table, $column, err)
+                        ).await;
+                    }
                 }
 
                 return Err(err);
@@ -85,13 +97,64 @@ pub enum Expr {
     LtEq,
     Eq,
     Neq,
-    Not
+    Not,
+    // a column of the row being evaluated, only valid where a whole row is available such as a join predicate
+    Column(usize),
+    // a parameter of a prepared statement, replaced by the value bound to it before the query runs
+    Param(usize),
+}
+
+impl Expr {
+    // the value of an expression that is nothing but a value, such as a value to insert
+    pub fn into_value(self) -> Result<Value, QueryError> {
+        match self {
+            Expr::Value(value) => Ok(value),
+            _ => Err(QueryError::InvalidQuery),
+        }
+    }
 }
 
 pub struct ExprEvaluator {}
 
 impl ExprEvaluator {
     pub fn evaluate(query: Vec<Expr>, value: Value) -> Result<Value, QueryError>{
+        let query: Vec<Expr> = query.into_iter().map(
+            |e| {
+                match e {
+                    Expr::Cell => {
+                        Expr::Value(value.clone())
+                    }
+                    expr=> { expr }
+                }
+            }
+        ).collect();
+
+        Self::execute(query)
+    }
+
+    // evaluates an expression over a whole row, `column` returns the value of a column of that row
+    pub fn evaluate_columns<F>(query: &[Expr], column: F) -> Result<Value, QueryError>
+    where
+        F: Fn(usize) -> Option<Value>
+    {
+        let mut resolved = Vec::with_capacity(query.len());
+
+        for part in query {
+            match part {
+                Expr::Column(id) => {
+                    match column(*id) {
+                        Some(value) => resolved.push(Expr::Value(value)),
+                        None => return Err(QueryError::ColumnNotFound),
+                    }
+                }
+                expr => resolved.push(expr.clone()),
+            }
+        }
+
+        Self::execute(resolved)
+    }
+
+    fn execute(query: Vec<Expr>) -> Result<Value, QueryError> {
         let mut stack = Vec::new();
 
         macro_rules! operation {
@@ -172,17 +235,6 @@ impl ExprEvaluator {
             };
         }
 
-        let query: Vec<Expr> = query.into_iter().map(
-            |e| {
-                match e {
-                    Expr::Cell => {
-                        Expr::Value(value.clone())
-                    }
-                    expr=> { expr }
-                }
-            }
-        ).collect();
-
         for part in query {
             match part {
                 Expr::Value(val) => {
@@ -191,6 +243,12 @@ impl ExprEvaluator {
                 Expr::Cell => {
                     return Err(QueryError::CellValueNotSet);
                 }
+                Expr::Column(..) => {
+                    return Err(QueryError::ColumnNotFound);
+                }
+                Expr::Param(..) => {
+                    return Err(QueryError::InvalidQuery);
+                }
                 Expr::Add => {
                     if stack.len() < 2 {
                         return Err(QueryError::StackUnderflow);
@@ -282,7 +340,7 @@ impl ExprEvaluator {
                     operation!(4, left, right, !=);
                 }
                 Expr::Not => {
-                    if stack.len() < 1 {
+                    if stack.is_empty() {
                         return Err(QueryError::StackUnderflow);
                     }
 
@@ -315,6 +373,18 @@ pub enum Types {
     Bool,
 }
 
+impl Types {
+    // the value stored in a cell that is null
+    pub fn default_value(&self) -> Value {
+        match self {
+            Types::Int => Value::Int(0),
+            Types::Float => Value::Float(0.0),
+            Types::String => Value::String(String::new()),
+            Types::Bool => Value::Bool(false),
+        }
+    }
+}
+
 pub trait ToTypes {
     fn to_types(&self) -> Types;
 }
@@ -393,12 +463,126 @@ impl ToTypes for Value {
     }
 }
 
+// cloning a column shares its values, see `Chunks`
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Column {
-    Int(Vec<i32>),
-    Float(Vec<f32>),
-    String(Vec<String>),
-    Bool(Vec<bool>),
+    Int(Chunks<i32>),
+    Float(Chunks<f32>),
+    String(Chunks<String>),
+    Bool(Chunks<bool>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CellKey {
+    Int(i32),
+    Float(u32),
+    String(String),
+    Bool(bool),
+}
+
+impl Column {
+    pub fn empty(types: &Types) -> Column {
+        match types {
+            Types::Int => Column::Int(Chunks::new()),
+            Types::Float => Column::Float(Chunks::new()),
+            Types::String => Column::String(Chunks::new()),
+            Types::Bool => Column::Bool(Chunks::new()),
+        }
+    }
+
+    pub fn push(&mut self, value: Value) -> Result<(), QueryError> {
+        match (self, value) {
+            (Column::Int(values), Value::Int(val)) => values.push(val),
+            (Column::Float(values), Value::Float(val)) => values.push(val),
+            (Column::String(values), Value::String(val)) => values.push(val),
+            (Column::Bool(values), Value::Bool(val)) => values.push(val),
+            _ => return Err(QueryError::TypeMismatch),
+        }
+
+        Ok(())
+    }
+
+    // moves the values of `other` to the end of the column, both have to hold the same type
+    pub fn append(&mut self, other: Column) -> Result<(), QueryError> {
+        match (self, other) {
+            (Column::Int(values), Column::Int(other)) => values.append(other),
+            (Column::Float(values), Column::Float(other)) => values.append(other),
+            (Column::String(values), Column::String(other)) => values.append(other),
+            (Column::Bool(values), Column::Bool(other)) => values.append(other),
+            _ => return Err(QueryError::TypeMismatch),
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Column::Int(values) => values.len(),
+            Column::Float(values) => values.len(),
+            Column::String(values) => values.len(),
+            Column::Bool(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, row: usize) -> Option<Value> {
+        match self {
+            Column::Int(values) => values.get(row).map(|val| Value::Int(*val)),
+            Column::Float(values) => values.get(row).map(|val| Value::Float(*val)),
+            Column::String(values) => values.get(row).map(|val| Value::String(val.clone())),
+            Column::Bool(values) => values.get(row).map(|val| Value::Bool(*val)),
+        }
+    }
+
+    // a hashable form of the cell at `row`, used to group and join rows by value
+    pub fn key(&self, row: usize) -> CellKey {
+        match self {
+            Column::Int(values) => CellKey::Int(values[row]),
+            Column::Float(values) => {
+                // 0.0 and -0.0 compare equal, so they have to land in the same group
+                let val = if values[row] == 0.0 { 0.0 } else { values[row] };
+                CellKey::Float(val.to_bits())
+            }
+            Column::String(values) => CellKey::String(values[row].clone()),
+            Column::Bool(values) => CellKey::Bool(values[row]),
+        }
+    }
+
+    // removes `rows`, given in ascending order
+    pub fn remove_rows(&mut self, rows: &[usize]) {
+        match self {
+            Column::Int(values) => values.remove_rows(rows),
+            Column::Float(values) => values.remove_rows(rows),
+            Column::String(values) => values.remove_rows(rows),
+            Column::Bool(values) => values.remove_rows(rows),
+        }
+    }
+
+    // roughly how much memory the column holds, counting the strings it owns but not allocator overhead
+    pub fn memory_bytes(&self) -> usize {
+        match self {
+            Column::Int(values) => values.capacity() * std::mem::size_of::<i32>(),
+            Column::Float(values) => values.capacity() * std::mem::size_of::<f32>(),
+            Column::String(values) => {
+                values.capacity() * std::mem::size_of::<String>()
+                    + values.iter().map(|val| val.capacity()).sum::<usize>()
+            }
+            Column::Bool(values) => values.capacity() * std::mem::size_of::<bool>(),
+        }
+    }
+
+    // builds a new column out of the given rows, in the order they are given
+    pub fn take(&self, row_ids: &[usize]) -> Column {
+        match self {
+            Column::Int(values) => Column::Int(row_ids.iter().map(|row| values[*row]).collect()),
+            Column::Float(values) => Column::Float(row_ids.iter().map(|row| values[*row]).collect()),
+            Column::String(values) => Column::String(row_ids.iter().map(|row| values[*row].clone()).collect()),
+            Column::Bool(values) => Column::Bool(row_ids.iter().map(|row| values[*row]).collect()),
+        }
+    }
 }
 
 impl ToTypes for Column {
@@ -0,0 +1,108 @@
+use std::sync::RwLock;
+use minase::db_core::auth::{Catalog, Privilege, ScramKeys};
+use minase::db_core::database::Database;
+use minase::db_core::query::Query;
+use minase::db_core::query_error::QueryError;
+use crate::config::Config;
+
+// the catalog of users shared by every session, saved next to the tables when the server persists its data
+#[derive(Debug)]
+pub struct Users {
+    catalog: RwLock<Catalog>,
+    config: Config,
+}
+
+impl Users {
+    // loads the saved users, creating the admin user when authentication is on and there are none yet
+    pub fn open(config: &Config, admin_password: Option<&str>) -> Result<Users, Box<dyn std::error::Error>> {
+        let mut catalog = match config.durability.persist {
+            true => Catalog::load(&config.data_dir)?,
+            false => Catalog::default(),
+        };
+
+        if config.auth.enabled && catalog.is_empty() {
+            let password = admin_password
+                .ok_or("authentication is on but there are no users, set an admin password to create the first one")?;
+
+            catalog.create_user(&config.auth.admin_user, password)?;
+            catalog.grant(&config.auth.admin_user, Privilege::Admin, None)?;
+
+            if config.durability.persist {
+                catalog.save(&config.data_dir, config.durability.fsync)?;
+            }
+        }
+
+        Ok(Users {
+            catalog: RwLock::new(catalog),
+            config: config.clone(),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.auth.enabled
+    }
+
+    // whether the session logged in as `user` may run `query`, with the tables it may only run on because
+    // of their own grants. the query has to find them where they were checked. with authentication off
+    // every query is allowed
+    pub fn authorize(&self, user: Option<&str>, query: &Query, db: &mut Database) -> Result<Vec<(usize, u64)>, QueryError> {
+        if !self.config.auth.enabled {
+            return Ok(Vec::new());
+        }
+
+        // cancelling is guarded by the key, the connection sending it does not log in
+        if let Query::Login { .. } | Query::Exit | Query::Cancel { .. } = query {
+            return Ok(Vec::new());
+        }
+
+        let user = user.ok_or(QueryError::NotAuthenticated)?;
+
+        if let Query::SetPassword { name, .. } = query {
+            if name == user {
+                return Ok(Vec::new());
+            }
+        }
+
+        let catalog = self.catalog.read().unwrap();
+        let mut authorized = Vec::new();
+
+        for (privilege, table) in query.required_privileges() {
+            // a grant on every table covers whichever table ends up at the position
+            if catalog.authorize(user, privilege, None).is_ok() {
+                continue;
+            }
+
+            // a table that does not exist has no grants of its own
+            let table = table.ok_or(QueryError::PermissionDenied)?;
+            let oid = db.table_oid(table).ok_or(QueryError::PermissionDenied)?;
+
+            catalog.authorize(user, privilege, Some(oid))?;
+            authorized.push((table, oid));
+        }
+
+        Ok(authorized)
+    }
+
+    pub fn login(&self, name: &str, password: &str) -> Result<(), QueryError> {
+        // hashing the password takes a while, other tasks move to another worker meanwhile
+        tokio::task::block_in_place(|| self.catalog.read().unwrap().authenticate(name, password))
+    }
+
+    pub fn scram_keys(&self, name: &str) -> ScramKeys {
+        self.catalog.read().unwrap().scram_keys(name)
+    }
+
+    // applies `change` to the catalog and saves it when the server persists its data.
+    // the change is kept even when saving fails, the error is for the caller to log
+    pub fn change(&self, change: impl FnOnce(&mut Catalog) -> Result<(), QueryError>) -> Result<Result<(), std::io::Error>, QueryError> {
+        tokio::task::block_in_place(|| {
+            let mut catalog = self.catalog.write().unwrap();
+            change(&mut catalog)?;
+
+            Ok(match self.config.durability.persist {
+                true => catalog.save(&self.config.data_dir, self.config.durability.fsync),
+                false => Ok(()),
+            })
+        })
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use minase::db_core::interrupt::Canceller;
+use minase::db_core::query::CancelKey;
+
+// the sessions whose queries can be cancelled from another connection
+#[derive(Debug, Default)]
+pub struct Cancellers {
+    sessions: Mutex<HashMap<u64, (u64, Canceller)>>,
+}
+
+// keeps a session cancellable until it is dropped
+#[derive(Debug)]
+pub struct Registration {
+    cancellers: Arc<Cancellers>,
+    key: CancelKey,
+}
+
+impl Cancellers {
+    pub fn register(self: &Arc<Self>, session: u64, canceller: Canceller) -> Registration {
+        let key = CancelKey {
+            session,
+            secret: rand::random(),
+        };
+        self.sessions.lock().unwrap().insert(session, (key.secret, canceller));
+
+        Registration {
+            cancellers: self.clone(),
+            key,
+        }
+    }
+
+    // whether a session with this key was found. a wrong secret is treated like an unknown session
+    pub fn cancel(&self, key: CancelKey) -> bool {
+        match self.sessions.lock().unwrap().get(&key.session) {
+            Some((secret, canceller)) if *secret == key.secret => {
+                canceller.cancel();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Registration {
+    pub fn key(&self) -> CancelKey {
+        self.key
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.cancellers.sessions.lock().unwrap().remove(&self.key.session);
+    }
+}
@@ -0,0 +1,306 @@
+use std::path::{Component, PathBuf};
+use clap::Parser;
+use serde_derive::{Deserialize, Serialize};
+use logger::Level;
+
+// command line options. every option can also be given through the environment variable next
+// to it, a flag wins over the variable and both win over the config file. a switch such as
+// `--persist` turns its setting on when given alone and takes `true` or `false` as well
+#[derive(Debug, Parser)]
+#[command(name = "server", version, about = "The minase database server")]
+pub struct Args {
+    #[arg(short, long, env = "MINASE_CONFIG", help = "Toml file to read the configuration from, options not in it keep their defaults")]
+    pub config: Option<PathBuf>,
+
+    #[arg(long, env = "MINASE_LISTEN", help = "Address to accept client connections on, empty to only listen on the unix socket")]
+    pub listen: Option<String>,
+
+    #[arg(long, env = "MINASE_UNIX_SOCKET", help = "Path of a unix socket to accept client connections on as well")]
+    pub unix_socket: Option<PathBuf>,
+
+    #[arg(long, env = "MINASE_UNIX_SOCKET_MODE", help = "Permissions of the unix socket in octal, deciding which users may connect")]
+    pub unix_socket_mode: Option<String>,
+
+    #[arg(long, env = "MINASE_HTTP_LISTEN", help = "Address of the http gateway taking queries as json, off unless set")]
+    pub http_listen: Option<String>,
+
+    #[arg(long, env = "MINASE_POSTGRES_LISTEN", help = "Address speaking the postgres protocol to take sql, off unless set")]
+    pub postgres_listen: Option<String>,
+
+    #[arg(long, env = "MINASE_METRICS_LISTEN", help = "Address of the http endpoint serving `/metrics`, empty to turn it off")]
+    pub metrics_listen: Option<String>,
+
+    #[arg(long, env = "MINASE_DATA_DIR", help = "Directory holding the saved tables")]
+    pub data_dir: Option<PathBuf>,
+
+    #[arg(long, env = "MINASE_FILE_DIR", help = "Directory under the data directory that imports and exports may read and write files in, off unless set")]
+    pub file_dir: Option<PathBuf>,
+
+    #[arg(long, env = "MINASE_MAX_CONNECTIONS", help = "Connections served at once, further clients wait until one closes")]
+    pub max_connections: Option<usize>,
+
+    #[arg(long, env = "MINASE_MAX_FRAME_BYTES", help = "Largest query a client may send, in bytes")]
+    pub max_frame_bytes: Option<u32>,
+
+    #[arg(long, env = "MINASE_QUERY_TIMEOUT_MILLIS", help = "Milliseconds a query may run before it fails with a timeout, 0 lets queries run as long as they take")]
+    pub query_timeout_millis: Option<u64>,
+
+    #[arg(long, env = "MINASE_SHUTDOWN_TIMEOUT", help = "Seconds to wait on shutdown for sessions to finish their query before aborting them")]
+    pub shutdown_timeout: Option<u64>,
+
+    #[arg(long, env = "MINASE_LOG_PATH", help = "File the server log is written to")]
+    pub log_path: Option<PathBuf>,
+
+    #[arg(long, env = "MINASE_LOG_LEVEL", help = "Minimum level of the records logged: debug, info, warn or error")]
+    pub log_level: Option<Level>,
+
+    #[arg(long, env = "MINASE_SLOW_QUERY_LOG_PATH", help = "File slow queries are written to")]
+    pub slow_query_log_path: Option<PathBuf>,
+
+    #[arg(long, env = "MINASE_SLOW_QUERY_MILLIS", help = "Queries taking at least this many milliseconds are logged as slow")]
+    pub slow_query_millis: Option<u64>,
+
+    #[arg(long, env = "MINASE_PERSIST", num_args = 0..=1, default_missing_value = "true", help = "Save the tables to the data directory and load them back on start")]
+    pub persist: Option<bool>,
+
+    #[arg(long, env = "MINASE_SNAPSHOT_INTERVAL", help = "Seconds between two saves of the tables, 0 turns periodic saves off")]
+    pub snapshot_interval: Option<u64>,
+
+    #[arg(long, env = "MINASE_FSYNC", num_args = 0..=1, default_missing_value = "true", help = "Fsync every save before it replaces the previous one")]
+    pub fsync: Option<bool>,
+
+    #[arg(long, env = "MINASE_AUTH", num_args = 0..=1, default_missing_value = "true", help = "Require clients to log in, and check every query against the privileges of the user")]
+    pub auth: Option<bool>,
+
+    // only read while no user exists yet, and never part of the printed configuration
+    #[arg(long, env = "MINASE_ADMIN_PASSWORD", hide_env_values = true, help = "Password of the admin user created when authentication is on and there are no users yet")]
+    pub admin_password: Option<String>,
+
+    #[arg(long, env = "MINASE_ADMIN_USER", help = "Name of the admin user created when authentication is on and there are no users yet")]
+    pub admin_user: Option<String>,
+
+    #[arg(long, env = "MINASE_TLS", num_args = 0..=1, default_missing_value = "true", help = "Accept client connections over tls only")]
+    pub tls: Option<bool>,
+
+    #[arg(long, env = "MINASE_TLS_CERT", help = "Pem file with the certificate chain of the server")]
+    pub tls_cert: Option<PathBuf>,
+
+    #[arg(long, env = "MINASE_TLS_KEY", help = "Pem file with the private key of the server")]
+    pub tls_key: Option<PathBuf>,
+
+    #[arg(long, env = "MINASE_TLS_CLIENT_CA", help = "Pem file with the certificates client certificates have to be signed by, requiring clients to present one")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    #[arg(long, help = "Print the effective configuration as toml and exit")]
+    pub print_config: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: String,
+    pub unix_socket: Option<PathBuf>,
+    pub unix_socket_mode: String,
+    pub http_listen: Option<String>,
+    pub postgres_listen: Option<String>,
+    // on unless set to an empty address, which is also how it is written out when off
+    #[serde(serialize_with = "empty_when_off")]
+    pub metrics_listen: Option<String>,
+    pub data_dir: PathBuf,
+    // relative to data_dir, files on the server can not be imported or exported without it
+    pub file_dir: Option<PathBuf>,
+    pub max_connections: usize,
+    pub max_frame_bytes: u32,
+    pub query_timeout_millis: u64,
+    pub shutdown_timeout: u64,
+    pub log: LogConfig,
+    pub durability: DurabilityConfig,
+    pub auth: AuthConfig,
+    pub tls: TlsConfig,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub path: PathBuf,
+    pub level: Level,
+    pub slow_query_path: PathBuf,
+    pub slow_query_millis: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DurabilityConfig {
+    pub persist: bool,
+    pub snapshot_interval: u64,
+    pub fsync: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub enabled: bool,
+    pub admin_user: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    // mutual tls, clients without a certificate signed by this ca are turned away
+    pub client_ca_path: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: "127.0.0.1:8080".to_string(),
+            unix_socket: None,
+            unix_socket_mode: "660".to_string(),
+            http_listen: None,
+            postgres_listen: None,
+            metrics_listen: Some("127.0.0.1:8081".to_string()),
+            data_dir: PathBuf::from("data"),
+            file_dir: None,
+            max_connections: 1024,
+            max_frame_bytes: 64 * 1024 * 1024,
+            query_timeout_millis: 0,
+            shutdown_timeout: 30,
+            log: LogConfig::default(),
+            durability: DurabilityConfig::default(),
+            auth: AuthConfig::default(),
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            path: PathBuf::from("minase.log"),
+            level: Level::Debug,
+            slow_query_path: PathBuf::from("minase-slow.log"),
+            slow_query_millis: 100,
+        }
+    }
+}
+
+impl Default for DurabilityConfig {
+    fn default() -> Self {
+        DurabilityConfig {
+            persist: false,
+            snapshot_interval: 60,
+            fsync: true,
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            enabled: false,
+            admin_user: "admin".to_string(),
+        }
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            enabled: false,
+            cert_path: PathBuf::from("minase.crt"),
+            key_path: PathBuf::from("minase.key"),
+            client_ca_path: None,
+        }
+    }
+}
+
+impl Config {
+    // the defaults, overridden by the config file and then by the options
+    pub fn load(args: &Args) -> Result<Config, Box<dyn std::error::Error>> {
+        let mut config = match &args.config {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|err| format!("failed to read config file {}: {}", path.display(), err))?;
+
+                toml::from_str(&text)
+                    .map_err(|err| format!("invalid config file {}: {}", path.display(), err))?
+            }
+            None => Config::default(),
+        };
+
+        macro_rules! apply {
+            ($option:ident => $($field:ident).+) => {
+                if let Some(value) = &args.$option {
+                    config.$($field).+ = value.clone();
+                }
+            };
+        }
+
+        apply!(listen => listen);
+        apply!(unix_socket_mode => unix_socket_mode);
+        apply!(data_dir => data_dir);
+        apply!(max_connections => max_connections);
+        apply!(max_frame_bytes => max_frame_bytes);
+        apply!(query_timeout_millis => query_timeout_millis);
+        apply!(shutdown_timeout => shutdown_timeout);
+        apply!(log_path => log.path);
+        apply!(log_level => log.level);
+        apply!(slow_query_log_path => log.slow_query_path);
+        apply!(slow_query_millis => log.slow_query_millis);
+        apply!(persist => durability.persist);
+        apply!(snapshot_interval => durability.snapshot_interval);
+        apply!(fsync => durability.fsync);
+        apply!(auth => auth.enabled);
+        apply!(admin_user => auth.admin_user);
+        apply!(tls => tls.enabled);
+        apply!(tls_cert => tls.cert_path);
+        apply!(tls_key => tls.key_path);
+
+        if let Some(path) = &args.unix_socket {
+            config.unix_socket = Some(path.clone());
+        }
+        if let Some(dir) = &args.file_dir {
+            config.file_dir = Some(dir.clone());
+        }
+        if let Some(addr) = &args.http_listen {
+            config.http_listen = Some(addr.clone());
+        }
+        if let Some(addr) = &args.postgres_listen {
+            config.postgres_listen = Some(addr.clone());
+        }
+        if let Some(addr) = &args.metrics_listen {
+            config.metrics_listen = Some(addr.clone());
+        }
+        if config.metrics_listen.as_deref() == Some("") {
+            config.metrics_listen = None;
+        }
+        if let Some(path) = &args.tls_client_ca {
+            config.tls.client_ca_path = Some(path.clone());
+        }
+
+        if config.listen.is_empty() && config.unix_socket.is_none() {
+            return Err("listen can only be empty when a unix socket is set".into());
+        }
+        if u32::from_str_radix(&config.unix_socket_mode, 8).map_or(true, |mode| mode > 0o777) {
+            return Err(format!("unix_socket_mode {} is not an octal file mode", config.unix_socket_mode).into());
+        }
+        // data_dir itself holds the saved tables and users, which exports must not overwrite
+        let under_data_dir = |dir: &PathBuf| dir.components().next().is_some()
+            && dir.components().all(|component| matches!(component, Component::Normal(_)));
+        if config.file_dir.as_ref().is_some_and(|dir| !under_data_dir(dir)) {
+            return Err("file_dir has to be a relative path of a directory under data_dir, without `..`".into());
+        }
+        if config.max_connections == 0 {
+            return Err("max_connections has to be at least 1".into());
+        }
+
+        Ok(config)
+    }
+}
+
+fn empty_when_off<S: serde::Serializer>(addr: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(addr.as_deref().unwrap_or_default())
+}
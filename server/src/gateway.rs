@@ -0,0 +1,363 @@
+use std::time::Duration;
+use base64::Engine;
+use serde_json::{json, Value as Json};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::watch;
+use minase::db_core::database::Table;
+use minase::db_core::query::Query;
+use minase::db_core::query_error::QueryError;
+use minase::db_core::sql::{self, SqlError, Statement};
+use minase::db_core::values::Value;
+use crate::Stream;
+use crate::session::{Reply, Session};
+
+// a request head larger than this is refused before its body is read
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+// time a client gets to send its whole request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Request {
+    method: String,
+    path: String,
+    // names are lowercase
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header == name).map(|(_, value)| value.as_str())
+    }
+}
+
+// answers a single request on the connection, `POST /query` with a query or an array of queries as json, or sql
+// statements with the `application/sql` content type. the queries of an array, like the statements, run in order
+// on one session, so they can form a transaction, and stop at the first error. on shutdown a request still being
+// read is refused, and the queries of a request stop once the one in flight has finished
+pub async fn serve(
+    mut socket: Box<dyn Stream>,
+    mut session: Session,
+    mut shutdown_requested: watch::Receiver<bool>,
+    max_body_bytes: usize
+) -> Result<(), std::io::Error> {
+    let read = tokio::select! {
+        read = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut socket, max_body_bytes)) => read,
+        _ = shutdown_requested.changed() => return shutting_down(&mut socket, &mut session).await,
+    };
+
+    let request = match read {
+        Ok(Ok(Ok(request))) => request,
+        Ok(Ok(Err(status))) => return write_response(&mut socket, status, &[], &failure("InvalidRequest", status)).await,
+        Ok(Err(err)) => return Err(err),
+        Err(_) => return write_response(&mut socket, "408 Request Timeout", &[], &failure("RequestTimeout", "request not received in time")).await,
+    };
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/query") => {}
+        (_, "/query") => {
+            return write_response(&mut socket, "405 Method Not Allowed", &[("Allow", "POST")], &failure("MethodNotAllowed", "only POST is allowed")).await;
+        }
+        _ => return write_response(&mut socket, "404 Not Found", &[], &failure("NotFound", "no such path")).await,
+    }
+
+    let credentials = request.header("authorization").and_then(basic_credentials);
+    match credentials {
+        Some((user, password)) => {
+            if let Err(err) = session.login(user, &password).await {
+                return write_error(&mut socket, &err).await;
+            }
+        }
+        None if session.requires_login() => return write_error(&mut socket, &QueryError::NotAuthenticated).await,
+        None => {}
+    }
+
+    let content_type = request.header("content-type").and_then(|value| value.split(';').next()).unwrap_or_default();
+    let (statements, batch) = match content_type.trim().eq_ignore_ascii_case("application/sql") {
+        true => match sql_statements(&request.body) {
+            Ok(statements) => {
+                let batch = statements.len() != 1;
+                (statements, batch)
+            }
+            Err(err) => {
+                let error = match err {
+                    SqlError::Syntax(_) => "SyntaxError",
+                    SqlError::Unsupported(_) => "Unsupported",
+                };
+                return write_response(&mut socket, "400 Bad Request", &[], &failure(error, &err.to_string())).await;
+            }
+        },
+        false => {
+            let (queries, batch) = match serde_json::from_slice::<Json>(&request.body) {
+                Ok(Json::Array(queries)) => (queries.into_iter().map(serde_json::from_value).collect::<Result<Vec<Query>, _>>(), true),
+                Ok(query) => (serde_json::from_value(query).map(|query| vec![query]), false),
+                Err(err) => (Err(err), false),
+            };
+
+            match queries {
+                Ok(queries) => (queries.into_iter().map(|query| Statement { query, columns: None }).collect(), batch),
+                Err(err) => {
+                    return write_response(&mut socket, "400 Bad Request", &[], &failure("InvalidJson", &err.to_string())).await;
+                }
+            }
+        }
+    };
+
+    let mut status = "200 OK";
+    let mut results = Vec::with_capacity(statements.len());
+
+    for Statement { query, columns } in statements {
+        if *shutdown_requested.borrow() {
+            status = "503 Service Unavailable";
+            results.push(failure("ShuttingDown", "server is shutting down"));
+            break;
+        }
+
+        let reply = project(session.execute(query).await, columns.as_deref());
+
+        if let Some(err) = reply.error() {
+            status = error_status(err);
+            results.push(error_json(err));
+            break;
+        }
+        results.push(reply_json(reply));
+    }
+
+    // a transaction the queries left open is rolled back with the session
+    session.db.logger_flush().await;
+
+    let body = match batch {
+        true => Json::Array(results),
+        false => results.pop().unwrap_or(Json::Null),
+    };
+    write_response(&mut socket, status, &[], &body).await
+}
+
+// the request line, the headers and a body of `content-length` bytes, or the status refusing the request
+async fn read_request(socket: &mut Box<dyn Stream>, max_body_bytes: usize) -> Result<Result<Request, &'static str>, std::io::Error> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 4096];
+
+    let head_end = loop {
+        if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position;
+        }
+        if buffer.len() > MAX_HEAD_BYTES {
+            return Ok(Err("431 Request Header Fields Too Large"));
+        }
+
+        let read = socket.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let (Some(method), Some(path)) = (request_line.next(), request_line.next()) else {
+        return Ok(Err("400 Bad Request"));
+    };
+
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let length = match headers.iter().find(|(name, _)| name == "content-length") {
+        Some((_, length)) => match length.parse::<usize>() {
+            Ok(length) => length,
+            Err(_) => return Ok(Err("400 Bad Request")),
+        },
+        None => 0,
+    };
+    if length > max_body_bytes {
+        return Ok(Err("413 Payload Too Large"));
+    }
+
+    let mut body = buffer.split_off(head_end + 4);
+    if body.len() < length {
+        let read = body.len();
+        body.resize(length, 0);
+        socket.read_exact(&mut body[read..]).await?;
+    }
+    body.truncate(length);
+
+    Ok(Ok(Request {
+        method: method.to_string(),
+        // the query string plays no part
+        path: path.split('?').next().unwrap_or_default().to_string(),
+        headers,
+        body,
+    }))
+}
+
+fn sql_statements(body: &[u8]) -> Result<Vec<Statement>, SqlError> {
+    let text = std::str::from_utf8(body).map_err(|_| SqlError::Syntax("the body is not utf-8".to_string()))?;
+    sql::parse(text)
+}
+
+// the columns a sql statement selected, of the table a query sent back
+fn project(reply: Reply, columns: Option<&[usize]>) -> Reply {
+    let Some(columns) = columns else {
+        return reply;
+    };
+
+    match reply {
+        Reply::Table(res) => Reply::Table(res.and_then(|table| table.project(columns))),
+        Reply::Mutation(res) => Reply::Mutation(res.and_then(|mut mutation| {
+            mutation.returning = mutation.returning.map(|table| table.project(columns)).transpose()?;
+            Ok(mutation)
+        })),
+        reply => reply,
+    }
+}
+
+// the user and password of an `Authorization: Basic` header
+fn basic_credentials(header: &str) -> Option<(String, String)> {
+    let encoded = header.strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+    let (user, password) = std::str::from_utf8(&decoded).ok()?.split_once(':')?;
+
+    Some((user.to_string(), password.to_string()))
+}
+
+fn error_status(err: &QueryError) -> &'static str {
+    match err {
+        QueryError::TableNotFound
+        | QueryError::ColumnNotFound
+        | QueryError::SavepointNotFound
+        | QueryError::UserNotFound
+        | QueryError::StatementNotFound => "404 Not Found",
+        QueryError::NoTransaction
+        | QueryError::TransactionInProgress
+        | QueryError::WriteConflict
+        | QueryError::UserExists
+        | QueryError::StatementExists
+        | QueryError::Cancelled => "409 Conflict",
+        QueryError::NotAuthenticated | QueryError::AuthenticationFailed => "401 Unauthorized",
+        QueryError::PermissionDenied => "403 Forbidden",
+        QueryError::Timeout => "504 Gateway Timeout",
+        QueryError::TypeMismatch
+        | QueryError::OperatorMismatch
+        | QueryError::NoOperation
+        | QueryError::CellValueNotSet
+        | QueryError::SizeMismatch
+        | QueryError::StackUnderflow
+        | QueryError::InvalidQuery
+        | QueryError::Overflow
+        | QueryError::InvalidCsv(_)
+        | QueryError::InvalidArrow(_) => "400 Bad Request",
+        QueryError::FileError(_) => "500 Internal Server Error",
+    }
+}
+
+async fn shutting_down(socket: &mut Box<dyn Stream>, session: &mut Session) -> Result<(), std::io::Error> {
+    session.db.logger.info("Session Closing".to_string(), "Server is shutting down".to_string()).await;
+    session.db.logger_flush().await;
+
+    write_response(socket, "503 Service Unavailable", &[], &failure("ShuttingDown", "server is shutting down")).await
+}
+
+fn failure(error: &str, message: &str) -> Json {
+    json!({ "error": error, "message": message })
+}
+
+fn error_json(err: &QueryError) -> Json {
+    let mut json = failure(err.name(), &err.to_string());
+    if let QueryError::InvalidCsv(errors) = err {
+        json["rows"] = errors.iter().map(|error| json!({ "line": error.line, "message": error.message })).collect();
+    }
+
+    json
+}
+
+fn value_json(value: Value) -> Json {
+    match value {
+        Value::Int(value) => json!(value),
+        // nan and infinity have no json form
+        Value::Float(value) => serde_json::Number::from_f64(value as f64).map_or(Json::Null, Json::Number),
+        Value::String(value) => json!(value),
+        Value::Bool(value) => json!(value),
+    }
+}
+
+// the column types and the rows of a table, a null cell as `null`
+fn table_json(table: &Table) -> Json {
+    let rows: Vec<Json> = (0..table.row_count())
+        .map(|row| {
+            Json::Array(table.columns().iter().enumerate().map(|(column_id, column)| {
+                match table.is_null(column_id, row) {
+                    true => Json::Null,
+                    false => column.get(row).map_or(Json::Null, value_json),
+                }
+            }).collect())
+        })
+        .collect();
+
+    json!({
+        "types": table.column_types().iter().map(|types| format!("{:?}", types)).collect::<Vec<_>>(),
+        "rows": rows,
+    })
+}
+
+fn reply_json(reply: Reply) -> Json {
+    match reply {
+        Reply::Created(Ok(table)) => json!({ "table": table }),
+        Reply::Table(Ok(table)) => table_json(&table),
+        Reply::Mutation(Ok(mutation)) => json!({
+            "matched": mutation.matched,
+            "modified": mutation.modified,
+            "returning": mutation.returning.as_ref().map(table_json),
+        }),
+        Reply::CancelKey(Ok(key)) => json!({ "session": key.session, "secret": key.secret }),
+        Reply::Import(Ok(import)) => json!({
+            "table": import.table,
+            "rows": import.rows,
+            "errors": import.errors.iter().map(|error| json!({ "line": error.line, "message": error.message })).collect::<Vec<_>>(),
+        }),
+        Reply::Export(Ok(export)) => json!({ "rows": export.rows, "data": export.data }),
+        Reply::ArrowImport(Ok(import)) => json!({ "table": import.table, "rows": import.rows }),
+        // the file is binary, so it is sent in base64
+        Reply::ArrowExport(Ok(export)) => json!({
+            "rows": export.rows,
+            "data": export.data.map(|data| base64::engine::general_purpose::STANDARD.encode(data)),
+        }),
+        Reply::None | Reply::Status(Ok(())) => json!({}),
+        Reply::Created(Err(err))
+        | Reply::Table(Err(err))
+        | Reply::Mutation(Err(err))
+        | Reply::Status(Err(err))
+        | Reply::CancelKey(Err(err))
+        | Reply::Import(Err(err))
+        | Reply::Export(Err(err))
+        | Reply::ArrowImport(Err(err))
+        | Reply::ArrowExport(Err(err)) => error_json(&err),
+    }
+}
+
+async fn write_error(socket: &mut Box<dyn Stream>, err: &QueryError) -> Result<(), std::io::Error> {
+    let headers: &[(&str, &str)] = match err {
+        QueryError::NotAuthenticated | QueryError::AuthenticationFailed => &[("WWW-Authenticate", "Basic realm=\"minase\"")],
+        _ => &[],
+    };
+
+    write_response(socket, error_status(err), headers, &error_json(err)).await
+}
+
+async fn write_response(socket: &mut Box<dyn Stream>, status: &str, headers: &[(&str, &str)], body: &Json) -> Result<(), std::io::Error> {
+    let body = body.to_string();
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status,
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(body.as_bytes()).await?;
+    socket.shutdown().await
+}
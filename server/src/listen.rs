@@ -0,0 +1,157 @@
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use crate::config::Config;
+
+// a client as accepted, before the tls handshake on tcp
+pub enum Connection {
+    Tcp(TcpStream),
+    // a client of the http gateway
+    Http(TcpStream),
+    // a client speaking the postgres protocol, which negotiates tls itself
+    Postgres(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+// the tcp listener, the unix socket, the http gateway and the postgres listener, each of which can be turned off
+pub struct Listeners {
+    tcp: Option<TcpListener>,
+    http: Option<TcpListener>,
+    postgres: Option<TcpListener>,
+    #[cfg(unix)]
+    unix: Option<(UnixListener, PathBuf)>,
+}
+
+impl Listeners {
+    pub async fn bind(config: &Config) -> Result<Listeners, Box<dyn std::error::Error>> {
+        let tcp = match config.listen.is_empty() {
+            true => None,
+            false => Some(TcpListener::bind(&config.listen).await?),
+        };
+
+        let http = match &config.http_listen {
+            Some(addr) => Some(TcpListener::bind(addr).await?),
+            None => None,
+        };
+
+        let postgres = match &config.postgres_listen {
+            Some(addr) => Some(TcpListener::bind(addr).await?),
+            None => None,
+        };
+
+        #[cfg(unix)]
+        let unix = match &config.unix_socket {
+            Some(path) => {
+                let mode = u32::from_str_radix(&config.unix_socket_mode, 8)?;
+                Some((bind_unix(path, mode)?, path.clone()))
+            }
+            None => None,
+        };
+
+        #[cfg(not(unix))]
+        if config.unix_socket.is_some() {
+            return Err("unix sockets are not supported on this platform".into());
+        }
+
+        Ok(Listeners {
+            tcp,
+            http,
+            postgres,
+            #[cfg(unix)]
+            unix,
+        })
+    }
+
+    // the next client on any listener, with the address it is logged under
+    pub async fn accept(&self) -> Result<(Connection, String), std::io::Error> {
+        #[cfg(unix)]
+        tokio::select! {
+            accepted = accept_tcp(self.tcp.as_ref(), Connection::Tcp) => accepted,
+            accepted = accept_tcp(self.http.as_ref(), Connection::Http) => accepted,
+            accepted = accept_tcp(self.postgres.as_ref(), Connection::Postgres) => accepted,
+            accepted = accept_unix(self.unix.as_ref()) => accepted,
+        }
+
+        #[cfg(not(unix))]
+        tokio::select! {
+            accepted = accept_tcp(self.tcp.as_ref(), Connection::Tcp) => accepted,
+            accepted = accept_tcp(self.http.as_ref(), Connection::Http) => accepted,
+            accepted = accept_tcp(self.postgres.as_ref(), Connection::Postgres) => accepted,
+        }
+    }
+
+    // stops accepting, removing the socket file so no client connects to a server that is gone
+    pub fn close(self) {
+        #[cfg(unix)]
+        if let Some((listener, path)) = self.unix {
+            drop(listener);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+async fn accept_tcp(listener: Option<&TcpListener>, connection: fn(TcpStream) -> Connection) -> Result<(Connection, String), std::io::Error> {
+    let Some(listener) = listener else {
+        return std::future::pending().await;
+    };
+
+    let (socket, peer): (TcpStream, SocketAddr) = listener.accept().await?;
+    Ok((connection(socket), peer.to_string()))
+}
+
+#[cfg(unix)]
+async fn accept_unix(listener: Option<&(UnixListener, PathBuf)>) -> Result<(Connection, String), std::io::Error> {
+    let Some((listener, path)) = listener else {
+        return std::future::pending().await;
+    };
+
+    let (socket, _) = listener.accept().await?;
+    // clients on a unix socket have no address of their own, the user they run as tells them apart
+    let peer = match socket.peer_cred() {
+        Ok(cred) => format!("unix:{} uid={}", path.display(), cred.uid()),
+        Err(_) => format!("unix:{}", path.display()),
+    };
+
+    Ok((Connection::Unix(socket), peer))
+}
+
+// the socket file is left behind when the server is killed, a file nobody answers on is replaced.
+// the socket is bound in a directory only this user can enter, given its mode there and then moved into
+// place, so no client can connect before the mode applies
+#[cfg(unix)]
+fn bind_unix(path: &Path, mode: u32) -> Result<UnixListener, Box<dyn std::error::Error>> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    if path.exists() && std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(format!("another server is listening on {}", path.display()).into());
+    }
+
+    let file_name = path.file_name().ok_or_else(|| format!("unix socket {} has no file name", path.display()))?;
+    let mut private_name = std::ffi::OsString::from(".");
+    private_name.push(file_name);
+    private_name.push(format!(".{}.tmp", std::process::id()));
+    let private_dir = path.with_file_name(private_name);
+    let private_path = private_dir.join(file_name);
+
+    // left over from a server that was killed while binding
+    if private_dir.exists() {
+        std::fs::remove_dir_all(&private_dir)?;
+    }
+    std::fs::DirBuilder::new().mode(0o700).create(&private_dir)
+        .map_err(|err| format!("failed to create {}: {}", private_dir.display(), err))?;
+
+    let bound = UnixListener::bind(&private_path)
+        .map_err(|err| format!("failed to bind unix socket {}: {}", path.display(), err).into())
+        .and_then(|listener| {
+            std::fs::set_permissions(&private_path, std::fs::Permissions::from_mode(mode))?;
+            std::fs::rename(&private_path, path)?;
+            Ok(listener)
+        });
+    let _ = std::fs::remove_dir_all(&private_dir);
+
+    bound
+}
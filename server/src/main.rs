@@ -4,9 +4,40 @@ extern crate rmp_serde as rmp;
 use rmp::{Deserializer, Serializer};
 use serde::{Deserialize, Serialize};
 use tokio::net::TcpListener;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use minase::db_core::database::{Database};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::sync::Arc;
+use std::process::ExitCode;
+use std::time::Duration;
+use clap::Parser;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, watch};
+use tokio::task::JoinSet;
+use minase::db_core::storage::Store;
+use logger::{Level, Logger, LoggerConfig};
 use minase::db_core::query::Query;
+use minase::db_core::query_error::QueryError;
+use auth::Users;
+use config::{Args, Config};
+use listen::{Connection, Listeners};
+use metrics::Metrics;
+use session::{Reply, Session, Shared};
+
+mod auth;
+mod cancel;
+mod config;
+mod gateway;
+mod listen;
+mod metrics;
+mod postgres;
+mod session;
+mod tls;
+
+// a client that has not finished the tls handshake by then is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// a client connection, plain or encrypted
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
 
 // let mut buffer = vec![];
 // result.serialize(&mut rmp::Serializer::new(&mut buffer))?;
@@ -14,189 +45,433 @@ use minase::db_core::query::Query;
 //
 // let res = Table::deserialize(&mut Deserializer::new(&buffer[..]))?;
 
+// sends a reply back to the client as a length prefixed message, counting it in the metrics.
+// returns from the session when the reply cannot be serialized or the client is gone
+macro_rules! respond {
+    ($socket:expr, $metrics:expr, $logger:expr, $reply:expr) => {
+        let mut buffer = vec![];
+        let sent = match $reply.serialize(&mut Serializer::new(&mut buffer)) {
+            Ok(()) => {
+                let size = (buffer.len() as u32).to_be_bytes();
+                let res = match $socket.write_all(&size[..]).await {
+                    Ok(()) => $socket.write_all(&buffer[..]).await,
+                    Err(err) => Err(err),
+                };
+                res.map(|()| $metrics.sent(size.len() + buffer.len()))
+                    .map_err(|err| ("Connection Error", format!("failed to send reply: {}", err)))
+            }
+            Err(err) => Err(("Serialization Error", format!("failed to serialize reply: {}", err))),
+        };
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let listener = TcpListener::bind("127.0.0.1:8080").await?;
-
-
-    // logger.info("Database Exiting".to_string(), "Execution has ended".to_string()).await;
-    // logger.flush_buffer().await;
+        if let Err((title, description)) = sent {
+            $logger.error(title.to_string(), description).await;
+            $logger.flush_buffer().await;
+            return Err(());
+        }
+    };
+}
 
-    loop {
-        let mut logger = logger::Logger::new().await;
+#[tokio::main]
+async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
 
-        let (mut socket, _) = listener.accept().await?;
+    if args.print_config {
+        print!("{}", toml::to_string_pretty(&config)?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let tls = match config.tls.enabled {
+        true => match tls::acceptor(&config.tls) {
+            Ok(acceptor) => Some(acceptor),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(2);
+            }
+        },
+        false => None,
+    };
 
-        let res = tokio::spawn(async move {
-            let mut size_buffer = [0; 4];
-            let mut buffer;
+    let listeners = Listeners::bind(&config).await?;
 
-            logger.info("Database Starting".to_string(), "Execution has started".to_string()).await;
 
-            let mut db = Database::new(
-                &mut logger
-            );
+    // logger.info("Database Exiting".to_string(), "Execution has ended".to_string()).await;
+    // logger.flush_buffer().await;
 
-            // flush stdout
-            // std::io::stdout().flush();
+    let mut base_logger = Logger::open(LoggerConfig {
+        path: config.log.path.clone(),
+        level: config.log.level,
+        target: "server".to_string(),
+        ..LoggerConfig::default()
+    }).await?;
+    base_logger.reopen_on_sighup()?;
+
+    let base_slow_logger = Logger::open(LoggerConfig {
+        path: config.log.slow_query_path.clone(),
+        target: "slow_query".to_string(),
+        ..LoggerConfig::default()
+    }).await?;
+    base_slow_logger.reopen_on_sighup()?;
+
+    let store = match config.durability.persist {
+        true => Arc::new(Store::load(&config.data_dir)?),
+        false => Arc::new(Store::new()),
+    };
+    let users = match Users::open(&config, args.admin_password.as_deref()) {
+        Ok(users) => users,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
 
-            loop {
-                // db.logger_flush().await;
-                // Read data from the socket
-                socket.read(&mut size_buffer).await.unwrap();
+    // flipped once on shutdown, sessions and the snapshot task stop when they see it
+    let (shutdown, shutdown_requested) = watch::channel(false);
+    let mut signals = ShutdownSignals::new()?;
 
-                // println!("{:?}", err);
+    let saver = match config.durability.persist && config.durability.snapshot_interval > 0 {
+        true => Some(tokio::spawn(save_periodically(store.clone(), config.clone(), base_logger.clone(), shutdown_requested.clone()))),
+        false => None,
+    };
 
-                let size = u32::from_be_bytes(size_buffer);
-                buffer = vec![0; size as usize];
+    let metrics = Arc::new(Metrics::default());
+    if let Some(addr) = &config.metrics_listen {
+        tokio::spawn(metrics::serve(TcpListener::bind(addr).await?, metrics.clone(), store.clone()));
+    }
+
+    let query_timeout = (config.query_timeout_millis > 0).then(|| Duration::from_millis(config.query_timeout_millis));
+    // created on start and resolved once, so the paths of imports and exports are checked against where it really is
+    let file_dir = match &config.file_dir {
+        Some(dir) => {
+            let dir = config.data_dir.join(dir);
+            std::fs::create_dir_all(&dir)
+                .and_then(|()| dir.canonicalize())
+                .map(Some)
+                .map_err(|err| format!("failed to create file_dir {}: {}", dir.display(), err))?
+        }
+        None => None,
+    };
 
-                let n = socket.read(&mut buffer).await.unwrap();
+    let shared = Arc::new(Shared::new(
+        store.clone(),
+        users,
+        metrics,
+        base_logger.clone(),
+        base_slow_logger.clone(),
+        config.log.slow_query_millis,
+        query_timeout,
+        file_dir
+    ));
+
+    base_logger.log(
+        Level::Info,
+        "Server Starting".to_string(),
+        "Accepting connections".to_string(),
+        vec![
+            ("listen".to_string(), config.listen.clone()),
+            ("unix_socket".to_string(), config.unix_socket.as_ref().map(|path| path.display().to_string()).unwrap_or_default()),
+            ("http_listen".to_string(), config.http_listen.clone().unwrap_or_default()),
+            ("postgres_listen".to_string(), config.postgres_listen.clone().unwrap_or_default()),
+            ("metrics_listen".to_string(), config.metrics_listen.clone().unwrap_or_default()),
+            ("tables".to_string(), store.snapshot().tables().len().to_string()),
+            ("auth".to_string(), config.auth.enabled.to_string()),
+            ("tls".to_string(), config.tls.enabled.to_string()),
+        ]
+    ).await;
+
+    let max_frame_bytes = config.max_frame_bytes;
+    let connections = Arc::new(Semaphore::new(config.max_connections));
+    let mut sessions = JoinSet::new();
+
+    let signal = loop {
+        let (permit, connection, peer) = tokio::select! {
+            signal = signals.recv() => break signal,
+            Some(_) = sessions.join_next() => continue,
+            accepted = accept(&listeners, &connections) => accepted?,
+        };
+        let shared = shared.clone();
+        let open_connection = shared.metrics.connection();
+        let tls = tls.clone();
+        let shutdown_requested = shutdown_requested.clone();
+
+        // every connection is a session of its own on the shared tables
+        sessions.spawn(async move {
+            let _permit = permit;
+            let _connection = open_connection;
+            let mut session = Session::new(&shared);
+            let http = matches!(connection, Connection::Http(_));
+
+            // postgres clients ask for tls once connected, the protocol handles it
+            if let Connection::Postgres(socket) = connection {
+                session.db.logger.log(
+                    Level::Info,
+                    "Session Starting".to_string(),
+                    "Connection has been accepted".to_string(),
+                    vec![("peer".to_string(), peer)]
+                ).await;
+
+                return postgres::serve(socket, tls, session, shutdown_requested, max_frame_bytes).await.map_err(|_| ());
+            }
 
-                if n == 0 {
-                    db.logger.error("Connection Error".to_string(), "Connection has been closed".to_string()).await;
-                    db.logger_flush().await;
-                    return Err(());
+            // tls is only spoken on tcp, file permissions guard the unix socket
+            let socket: Box<dyn Stream> = match (connection, tls) {
+                (Connection::Tcp(socket), Some(acceptor)) | (Connection::Http(socket), Some(acceptor)) => {
+                    let handshake = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+                        Ok(Ok(socket)) => Ok(socket),
+                        Ok(Err(err)) => Err(err.to_string()),
+                        Err(_) => Err(format!("no handshake within {}s", HANDSHAKE_TIMEOUT.as_secs())),
+                    };
+
+                    match handshake {
+                        Ok(socket) => Box::new(socket),
+                        Err(description) => {
+                            session.db.logger.log(
+                                Level::Warn,
+                                "TLS Handshake Failed".to_string(),
+                                description,
+                                vec![("peer".to_string(), peer)]
+                            ).await;
+                            session.db.logger_flush().await;
+                            return Err(());
+                        }
+                    }
                 }
+                (Connection::Tcp(socket), None) | (Connection::Http(socket), None) => Box::new(socket),
+                (Connection::Postgres(_), _) => unreachable!("served before the handshake"),
+                #[cfg(unix)]
+                (Connection::Unix(socket), _) => Box::new(socket),
+            };
+
+            session.db.logger.log(
+                Level::Info,
+                "Session Starting".to_string(),
+                "Connection has been accepted".to_string(),
+                vec![("peer".to_string(), peer)]
+            ).await;
+
+            match http {
+                true => gateway::serve(socket, session, shutdown_requested, max_frame_bytes as usize).await.map_err(|_| ()),
+                false => serve_protocol(socket, session, &shared.metrics, shutdown_requested, max_frame_bytes).await,
+            }
+        });
+    };
 
+    listeners.close();
+    let _ = shutdown.send(true);
 
-                let query = Query::deserialize(
-                    &mut Deserializer::new(
-                        &buffer[..]
-                    )
-                ).unwrap();
-
-                db.logger_info("Query Received".to_string(), format!("{:?}", query)).await;
+    base_logger.info(
+        "Server Shutting Down".to_string(),
+        format!("{} received, waiting up to {}s for {} sessions to finish", signal, config.shutdown_timeout, sessions.len())
+    ).await;
 
+    let mut status = ExitCode::SUCCESS;
 
-                match query {
-                    Query::Select { table, columns, condition } => {
-                        let mut buffer = vec![];
+    let drained = tokio::select! {
+        drained = tokio::time::timeout(Duration::from_secs(config.shutdown_timeout), async {
+            while sessions.join_next().await.is_some() {}
+        }) => drained.is_ok(),
+        signal = signals.recv() => {
+            base_logger.warn("Shutdown Forced".to_string(), format!("{} received while waiting for sessions", signal)).await;
+            false
+        }
+    };
 
-                        let table = db.select(table, columns, condition).await;
-                        table.serialize(
-                            &mut Serializer::new(
-                                &mut buffer
-                            )
-                        ).unwrap();
+    if !drained {
+        base_logger.warn("Sessions Aborted".to_string(), format!("{} sessions did not finish in time and were aborted", sessions.len())).await;
+        sessions.shutdown().await;
+        status = ExitCode::FAILURE;
+    }
+
+    if let Some(saver) = saver {
+        let _ = saver.await;
+    }
+
+    if config.durability.persist && !save(&store, &config, &mut base_logger).await {
+        status = ExitCode::FAILURE;
+    }
+
+    base_logger.info("Server Stopped".to_string(), "Shutdown has completed".to_string()).await;
+
+    if let Err(err) = base_slow_logger.shutdown().await {
+        eprintln!("failed to flush slow query log: {}", err);
+    }
+    if let Err(err) = base_logger.shutdown().await {
+        eprintln!("failed to flush log: {}", err);
+        status = ExitCode::FAILURE;
+    }
+
+    Ok(status)
+}
+
+// answers length prefixed msgpack queries until the client exits or the server shuts down
+async fn serve_protocol(
+    mut socket: Box<dyn Stream>,
+    mut session: Session,
+    metrics: &Metrics,
+    mut shutdown_requested: watch::Receiver<bool>,
+    max_frame_bytes: u32
+) -> Result<(), ()> {
+    let mut size_buffer = [0; 4];
+    let mut buffer = vec![];
+
+    // flush stdout
+    // std::io::stdout().flush();
 
-                        let size = (buffer.len() as u32).to_be_bytes();
-                        socket.write_all(&size[..]).await.unwrap();
-                        socket.write_all(&buffer[..]).await.unwrap();
-                    }
-                    Query::SelectTable { table } => {
-                        let mut buffer = vec![];
-
-                        let table = db.select_table(table).await;
-                        table.serialize(
-                            &mut Serializer::new(
-                                &mut buffer
-                            )
-                        ).unwrap();
-
-                        let size = (buffer.len() as u32).to_be_bytes();
-                        socket.write_all(&size[..]).await.unwrap();
-                        socket.write_all(&buffer[..]).await.unwrap();
-                    }
-                    Query::Insert { table, values } => {
-                        let mut buffer = vec![];
-
-                        let res = db.insert(table, values).await;
+    loop {
+        // db.logger_flush().await;
+        // Read data from the socket
+        // a session only stops for a shutdown between queries, so the query in flight finishes
+        let mut closed = tokio::select! {
+            read = socket.read_exact(&mut size_buffer) => read.is_err(),
+            _ = shutdown_requested.changed() => {
+                session.db.logger.info("Session Closing".to_string(), "Server is shutting down".to_string()).await;
+                session.db.logger_flush().await;
+                return Ok(());
+            }
+        };
 
-                        res.serialize(
-                            &mut Serializer::new(
-                                &mut buffer
-                            )
-                        ).unwrap();
+        // println!("{:?}", err);
 
-                        let size = (buffer.len() as u32).to_be_bytes();
-                        socket.write_all(&size[..]).await.unwrap();
-                        socket.write_all(&buffer[..]).await.unwrap();
-                    }
-                    Query::Update { table, condition_column, targets, condition } => {
-                        let mut buffer = vec![];
+        if !closed {
+            let size = u32::from_be_bytes(size_buffer);
 
-                        let res = db.update(table, condition_column, targets, condition).await;
+            // the rest of the stream cannot be read past a frame that is not read, so the connection is dropped
+            if size > max_frame_bytes {
+                session.db.logger.error(
+                    "Frame Too Large".to_string(),
+                    format!("query of {} bytes is larger than the limit of {} bytes", size, max_frame_bytes)
+                ).await;
+                session.db.logger_flush().await;
+                return Err(());
+            }
 
-                        res.serialize(
-                            &mut Serializer::new(
-                                &mut buffer
-                            )
-                        ).unwrap();
+            buffer.resize(size as usize, 0);
 
-                        let size = (buffer.len() as u32).to_be_bytes();
-                        socket.write_all(&size[..]).await.unwrap();
-                        socket.write_all(&buffer[..]).await.unwrap();
-                    }
-                    Query::UpdateAll { table, targets } => {
-                        let mut buffer = vec![];
+            closed = socket.read_exact(&mut buffer).await.is_err();
+            metrics.received(size_buffer.len() + buffer.len());
+        }
 
-                        let res = db.update_all(table, targets).await;
+        if closed {
+            session.db.logger.error("Connection Error".to_string(), "Connection has been closed".to_string()).await;
+            session.db.logger_flush().await;
+            return Err(());
+        }
 
-                        res.serialize(
-                            &mut Serializer::new(
-                                &mut buffer
-                            )
-                        ).unwrap();
 
-                        let size = (buffer.len() as u32).to_be_bytes();
-                        socket.write_all(&size[..]).await.unwrap();
-                        socket.write_all(&buffer[..]).await.unwrap();
-                    }
-                    Query::Delete { table, column, condition } => {
-                        let mut buffer = vec![];
+        // the frame was read whole, but what it holds is not a query. whether the client waits for a reply
+        // is not known, so it is sent an error and the connection is closed
+        let query = match Query::deserialize(&mut Deserializer::new(&buffer[..])) {
+            Ok(query) => query,
+            Err(err) => {
+                session.db.logger.error("Invalid Query".to_string(), format!("failed to deserialize query: {}", err)).await;
+                respond!(socket, metrics, session.db.logger, Reply::Status(Err(QueryError::InvalidQuery)));
+                session.db.logger_flush().await;
+                return Err(());
+            }
+        };
 
-                        let res = db.delete(table, column, condition).await;
+        let exit = matches!(query, Query::Exit);
+        // the client does not wait on exit, so nothing is sent for it
+        let has_reply = query.has_reply();
+        let reply = session.execute(query).await;
 
-                        res.serialize(
-                            &mut Serializer::new(
-                                &mut buffer
-                            )
-                        ).unwrap();
+        if exit {
+            session.db.logger.info("Session Exiting".to_string(), "Session has ended".to_string()).await;
+            session.db.logger.flush_buffer().await;
+            return Ok(());
+        }
 
-                        let size = (buffer.len() as u32).to_be_bytes();
-                        socket.write_all(&size[..]).await.unwrap();
-                        socket.write_all(&buffer[..]).await.unwrap();
-                    }
-                    Query::AddTable { columns } => {
-                        db.add_table(columns).await;
-                    }
-                    Query::DropTable { id } => {
-                        let mut buffer = vec![];
+        if has_reply {
+            respond!(socket, metrics, session.db.logger, reply);
+        }
+    }
+}
+
+// waits for a free connection slot, then for the next client
+async fn accept(listeners: &Listeners, connections: &Arc<Semaphore>) -> Result<(OwnedSemaphorePermit, Connection, String), Box<dyn std::error::Error>> {
+    let permit = connections.clone().acquire_owned().await?;
+    let (connection, peer) = listeners.accept().await?;
+
+    Ok((permit, connection, peer))
+}
+
+// SIGINT, and SIGTERM where there is one
+struct ShutdownSignals {
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+}
+
+impl ShutdownSignals {
+    fn new() -> Result<Self, std::io::Error> {
+        Ok(ShutdownSignals {
+            #[cfg(unix)]
+            terminate: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?,
+        })
+    }
+
+    // the name of the next signal received
+    async fn recv(&mut self) -> &'static str {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+            _ = self.terminate.recv() => "SIGTERM",
+        }
 
-                        let res = db.drop_table(id).await;
+        #[cfg(not(unix))]
+        {
+            let _ = tokio::signal::ctrl_c().await;
+            "SIGINT"
+        }
+    }
+}
+
+// saves the committed tables to the data directory, logging how it went
+async fn save(store: &Store, config: &Config, logger: &mut Logger) -> bool {
+    let data_dir = config.data_dir.clone();
+    let fsync = config.durability.fsync;
+    let snapshot = store.snapshot();
+
+    match tokio::task::spawn_blocking(move || snapshot.save(&data_dir, fsync)).await {
+        Ok(Ok(())) => {
+            logger.debug("Snapshot Saved".to_string(), format!("tables saved to {}", config.data_dir.display())).await;
+            true
+        }
+        Ok(Err(err)) => {
+            logger.error("Snapshot Failed".to_string(), format!("failed to save tables to {}: {}", config.data_dir.display(), err)).await;
+            false
+        }
+        Err(err) => {
+            logger.error("Snapshot Failed".to_string(), format!("snapshot task failed: {}", err)).await;
+            false
+        }
+    }
+}
 
-                        res.serialize(
-                            &mut Serializer::new(
-                                &mut buffer
-                            )
-                        ).unwrap();
+// saves the committed tables to the data directory every `snapshot_interval` seconds,
+// skipping saves while nothing was committed, until the server shuts down
+async fn save_periodically(store: Arc<Store>, config: Config, mut logger: Logger, mut shutdown_requested: watch::Receiver<bool>) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(config.durability.snapshot_interval));
+    let mut last_saved = store.snapshot();
 
-                        let size = (buffer.len() as u32).to_be_bytes();
-                        socket.write_all(&size[..]).await.unwrap();
-                        socket.write_all(&buffer[..]).await.unwrap();
-                    }
-                    Query::Exit => {
-                        db.logger.info("Database Exiting".to_string(), "Execution has ended".to_string()).await;
-                        db.logger.flush_buffer().await;
-                        return Ok(());
-                    }
-                    Query::FlushLogs => {
-                        db.logger_flush().await;
-                    }
-                }
-            }
-        });
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown_requested.changed() => return,
+        }
 
-        match res.await {
-            Ok(_) => {
-                break
-            }
-            Err(_) => {
-                break
-            }
+        let snapshot = store.snapshot();
+        if snapshot.same_as(&last_saved) {
+            continue;
         }
-    };
 
-    Ok(())
-}
\ No newline at end of file
+        if save(&store, &config, &mut logger).await {
+            last_saved = snapshot;
+        }
+    }
+}
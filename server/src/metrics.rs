@@ -0,0 +1,296 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use minase::db_core::storage::{Snapshot, Store};
+use minase::db_core::values::ToTypes;
+
+// upper bounds of the query latency buckets, in seconds
+const LATENCY_BUCKETS: [f64; 12] = [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0];
+
+// a request head larger than this is not a metrics scrape
+const MAX_REQUEST_BYTES: usize = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+struct Histogram {
+    // observations per bucket, not cumulative, the last one holds everything above the largest bound
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = LATENCY_BUCKETS.iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let mut cumulative = 0;
+
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        cumulative += self.buckets[LATENCY_BUCKETS.len()].load(Ordering::Relaxed);
+
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, cumulative);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9);
+        let _ = writeln!(out, "{}_count {}", name, self.count.load(Ordering::Relaxed));
+    }
+}
+
+// counters shared by every session of the server, rendered in the prometheus text format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    queries: Mutex<BTreeMap<&'static str, u64>>,
+    errors: Mutex<BTreeMap<&'static str, u64>>,
+    latency: Histogram,
+    connections: AtomicU64,
+    open_connections: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+// counts a connection as open until it is dropped
+#[derive(Debug)]
+pub struct OpenConnection {
+    metrics: Arc<Metrics>,
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.metrics.open_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub fn connection(self: &Arc<Self>) -> OpenConnection {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.open_connections.fetch_add(1, Ordering::Relaxed);
+
+        OpenConnection {
+            metrics: self.clone(),
+        }
+    }
+
+    pub fn query(&self, name: &'static str) {
+        *self.queries.lock().unwrap().entry(name).or_default() += 1;
+    }
+
+    pub fn error(&self, name: &'static str) {
+        *self.errors.lock().unwrap().entry(name).or_default() += 1;
+    }
+
+    pub fn latency(&self, duration: Duration) {
+        self.latency.observe(duration);
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    // the counters, followed by the size of every committed table in `snapshot`
+    pub fn render(&self, snapshot: &Snapshot) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# HELP minase_queries_total Queries received, by query type.");
+        let _ = writeln!(out, "# TYPE minase_queries_total counter");
+        for (query, count) in self.queries.lock().unwrap().iter() {
+            let _ = writeln!(out, "minase_queries_total{{query=\"{}\"}} {}", query, count);
+        }
+
+        let _ = writeln!(out, "# HELP minase_query_errors_total Queries that failed, by error.");
+        let _ = writeln!(out, "# TYPE minase_query_errors_total counter");
+        for (error, count) in self.errors.lock().unwrap().iter() {
+            let _ = writeln!(out, "minase_query_errors_total{{error=\"{}\"}} {}", error, count);
+        }
+
+        let _ = writeln!(out, "# HELP minase_query_duration_seconds Time taken to execute a query.");
+        let _ = writeln!(out, "# TYPE minase_query_duration_seconds histogram");
+        self.latency.render(&mut out, "minase_query_duration_seconds");
+
+        let _ = writeln!(out, "# HELP minase_connections_total Connections accepted.");
+        let _ = writeln!(out, "# TYPE minase_connections_total counter");
+        let _ = writeln!(out, "minase_connections_total {}", self.connections.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# HELP minase_open_connections Connections currently open.");
+        let _ = writeln!(out, "# TYPE minase_open_connections gauge");
+        let _ = writeln!(out, "minase_open_connections {}", self.open_connections.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# HELP minase_received_bytes_total Bytes of queries read from clients.");
+        let _ = writeln!(out, "# TYPE minase_received_bytes_total counter");
+        let _ = writeln!(out, "minase_received_bytes_total {}", self.bytes_received.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# HELP minase_sent_bytes_total Bytes of results written to clients.");
+        let _ = writeln!(out, "# TYPE minase_sent_bytes_total counter");
+        let _ = writeln!(out, "minase_sent_bytes_total {}", self.bytes_sent.load(Ordering::Relaxed));
+
+        let tables = snapshot.tables();
+
+        let _ = writeln!(out, "# HELP minase_tables Committed tables.");
+        let _ = writeln!(out, "# TYPE minase_tables gauge");
+        let _ = writeln!(out, "minase_tables {}", tables.len());
+
+        let _ = writeln!(out, "# HELP minase_table_rows Rows per committed table.");
+        let _ = writeln!(out, "# TYPE minase_table_rows gauge");
+        for (id, table) in tables.iter().enumerate() {
+            let _ = writeln!(out, "minase_table_rows{{table=\"{}\"}} {}", id, table.row_count());
+        }
+
+        let _ = writeln!(out, "# HELP minase_column_memory_bytes Approximate memory held per column.");
+        let _ = writeln!(out, "# TYPE minase_column_memory_bytes gauge");
+        for (id, table) in tables.iter().enumerate() {
+            for (column_id, column) in table.columns().iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    "minase_column_memory_bytes{{table=\"{}\",column=\"{}\",type=\"{:?}\"}} {}",
+                    id,
+                    column_id,
+                    column.to_types(),
+                    column.memory_bytes()
+                );
+            }
+        }
+
+        out
+    }
+}
+
+// answers `GET /metrics` with the current metrics, one request per connection
+pub async fn serve(listener: TcpListener, metrics: Arc<Metrics>, store: Arc<Store>) {
+    loop {
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(err) => {
+                eprintln!("failed to accept metrics connection: {}", err);
+                continue;
+            }
+        };
+
+        let metrics = metrics.clone();
+        let store = store.clone();
+
+        tokio::spawn(async move {
+            let _ = tokio::time::timeout(REQUEST_TIMEOUT, respond(socket, &metrics, &store)).await;
+        });
+    }
+}
+
+async fn respond(mut socket: TcpStream, metrics: &Metrics, store: &Store) -> Result<(), std::io::Error> {
+    let mut request = Vec::new();
+    let mut chunk = [0; 1024];
+
+    // only the request line matters, the headers are read and ignored
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST_BYTES {
+            return write_response(&mut socket, "431 Request Header Fields Too Large", "").await;
+        }
+
+        let read = socket.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&chunk[..read]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split_whitespace();
+
+    match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            write_response(&mut socket, "200 OK", &metrics.render(&store.snapshot())).await
+        }
+        (Some("GET"), _) => write_response(&mut socket, "404 Not Found", "").await,
+        _ => write_response(&mut socket, "405 Method Not Allowed", "").await,
+    }
+}
+
+async fn write_response(socket: &mut TcpStream, status: &str, body: &str) -> Result<(), std::io::Error> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    );
+
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(body.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use logger::Logger;
+    use minase::db_core::database::Database;
+    use minase::db_core::values::{Types, Value};
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut socket = TcpStream::connect(addr).await.unwrap();
+        socket.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn serves_counters_and_table_sizes() {
+        let store = Arc::new(Store::new());
+        let mut db = Database::with_store(Logger::noop(), store.clone());
+        db.add_table(vec![Types::Int]).await.unwrap();
+        db.insert(0, vec![Value::Int(7)]).await.unwrap();
+
+        let metrics = Arc::new(Metrics::default());
+        let _connection = metrics.connection();
+        metrics.query("select");
+        metrics.query("select");
+        metrics.error("TableNotFound");
+        metrics.received(10);
+        metrics.sent(20);
+        metrics.latency(Duration::from_millis(2));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, metrics, store));
+
+        let response = get(addr, "/metrics").await;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("\r\nContent-Type: text/plain; version=0.0.4\r\n"));
+        assert!(head.contains(&format!("\r\nContent-Length: {}\r\n", body.len())));
+
+        for line in [
+            "minase_queries_total{query=\"select\"} 2",
+            "minase_query_errors_total{error=\"TableNotFound\"} 1",
+            "minase_query_duration_seconds_bucket{le=\"0.001\"} 0",
+            "minase_query_duration_seconds_bucket{le=\"0.0025\"} 1",
+            "minase_query_duration_seconds_count 1",
+            "minase_connections_total 1",
+            "minase_open_connections 1",
+            "minase_received_bytes_total 10",
+            "minase_sent_bytes_total 20",
+            "minase_tables 1",
+            "minase_table_rows{table=\"0\"} 1",
+        ] {
+            assert!(body.lines().any(|body_line| body_line == line), "missing `{}` in\n{}", line, body);
+        }
+
+        assert!(get(addr, "/").await.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
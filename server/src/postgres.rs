@@ -0,0 +1,504 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio_rustls::TlsAcceptor;
+use minase::db_core::database::Table;
+use minase::db_core::query::Query;
+use minase::db_core::query_error::QueryError;
+use minase::db_core::sql::{self, SqlError, Statement};
+use minase::db_core::values::{Types, Value};
+use crate::{HANDSHAKE_TIMEOUT, Stream};
+use crate::session::{Reply, Session};
+
+// request codes sent in place of a protocol version in the first message
+const PROTOCOL_VERSION: i32 = 3 << 16;
+const SSL_REQUEST: i32 = 80877103;
+const GSSENC_REQUEST: i32 = 80877104;
+const CANCEL_REQUEST: i32 = 80877102;
+
+// the startup message only holds a few parameters
+const MAX_STARTUP_BYTES: usize = 10_000;
+
+// authentication request codes
+const AUTHENTICATION_OK: i32 = 0;
+const AUTHENTICATION_SASL: i32 = 10;
+const AUTHENTICATION_SASL_CONTINUE: i32 = 11;
+const AUTHENTICATION_SASL_FINAL: i32 = 12;
+
+const SCRAM_SHA_256: &str = "SCRAM-SHA-256";
+
+// an error as sent to the client, with its sqlstate code
+struct Failure {
+    code: &'static str,
+    message: String,
+}
+
+impl From<QueryError> for Failure {
+    fn from(err: QueryError) -> Self {
+        let code = match err {
+            QueryError::TableNotFound => "42P01",
+            QueryError::ColumnNotFound => "42703",
+            QueryError::TypeMismatch | QueryError::OperatorMismatch => "42804",
+            QueryError::SizeMismatch | QueryError::StackUnderflow => "42601",
+            QueryError::NoOperation | QueryError::CellValueNotSet | QueryError::InvalidQuery => "22023",
+            QueryError::NoTransaction => "25P01",
+            QueryError::TransactionInProgress => "25001",
+            QueryError::SavepointNotFound => "3B001",
+            QueryError::WriteConflict => "40001",
+            QueryError::Cancelled | QueryError::Timeout => "57014",
+            QueryError::NotAuthenticated => "28000",
+            QueryError::AuthenticationFailed => "28P01",
+            QueryError::PermissionDenied => "42501",
+            QueryError::UserExists => "42710",
+            QueryError::UserNotFound => "42704",
+            QueryError::StatementExists => "42P05",
+            QueryError::StatementNotFound => "26000",
+            QueryError::Overflow => "22003",
+            QueryError::InvalidCsv(_) | QueryError::InvalidArrow(_) => "22P04",
+            QueryError::FileError(_) => "58030",
+        };
+
+        Failure {
+            code,
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<SqlError> for Failure {
+    fn from(err: SqlError) -> Self {
+        Failure {
+            code: match err {
+                SqlError::Syntax(_) => "42601",
+                SqlError::Unsupported(_) => "0A000",
+            },
+            message: err.to_string(),
+        }
+    }
+}
+
+// appends a message: its type, then its length including the length itself
+fn message(out: &mut Vec<u8>, tag: u8, body: &[u8]) {
+    out.push(tag);
+    out.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+    out.extend_from_slice(body);
+}
+
+fn cstring(out: &mut Vec<u8>, string: &str) {
+    out.extend_from_slice(string.as_bytes());
+    out.push(0);
+}
+
+fn error_response(out: &mut Vec<u8>, severity: &str, failure: &Failure) {
+    let mut body = Vec::new();
+    for (field, value) in [(b'S', severity), (b'V', severity), (b'C', failure.code), (b'M', failure.message.as_str())] {
+        body.push(field);
+        cstring(&mut body, value);
+    }
+    body.push(0);
+
+    message(out, b'E', &body);
+}
+
+fn ready_for_query(out: &mut Vec<u8>, session: &Session) {
+    let status = match session.db.in_transaction() {
+        true => b'T',
+        false => b'I',
+    };
+    message(out, b'Z', &[status]);
+}
+
+// the oid and the size postgres gives the type, -1 for a variable size
+fn type_info(types: &Types) -> (i32, i16) {
+    match types {
+        Types::Int => (23, 4),
+        Types::Float => (700, 4),
+        Types::String => (25, -1),
+        Types::Bool => (16, 1),
+    }
+}
+
+fn value_text(value: Value) -> String {
+    match value {
+        Value::Int(value) => value.to_string(),
+        Value::Float(value) if value.is_nan() => "NaN".to_string(),
+        Value::Float(value) if value == f32::INFINITY => "Infinity".to_string(),
+        Value::Float(value) if value == f32::NEG_INFINITY => "-Infinity".to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::String(value) => value,
+        Value::Bool(value) => if value { "t" } else { "f" }.to_string(),
+    }
+}
+
+// a row description followed by every row in the text format. columns are named after the position
+// they had in the table
+fn rows(out: &mut Vec<u8>, table: &Table, statement: &Statement) {
+    let mut body = Vec::new();
+    body.extend_from_slice(&(table.column_types().len() as i16).to_be_bytes());
+
+    for (id, types) in table.column_types().iter().enumerate() {
+        let position = statement.columns.as_ref().map_or(id, |columns| columns[id]);
+        let (oid, size) = type_info(types);
+
+        cstring(&mut body, &format!("c{}", position));
+        body.extend_from_slice(&0i32.to_be_bytes());
+        body.extend_from_slice(&0i16.to_be_bytes());
+        body.extend_from_slice(&oid.to_be_bytes());
+        body.extend_from_slice(&size.to_be_bytes());
+        body.extend_from_slice(&(-1i32).to_be_bytes());
+        body.extend_from_slice(&0i16.to_be_bytes());
+    }
+    message(out, b'T', &body);
+
+    for row in 0..table.row_count() {
+        body.clear();
+        body.extend_from_slice(&(table.columns().len() as i16).to_be_bytes());
+
+        for (id, column) in table.columns().iter().enumerate() {
+            match column.get(row).filter(|_| !table.is_null(id, row)) {
+                Some(value) => {
+                    let text = value_text(value);
+                    body.extend_from_slice(&(text.len() as i32).to_be_bytes());
+                    body.extend_from_slice(text.as_bytes());
+                }
+                None => body.extend_from_slice(&(-1i32).to_be_bytes()),
+            }
+        }
+        message(out, b'D', &body);
+    }
+}
+
+fn command_complete(out: &mut Vec<u8>, tag: &str) {
+    let mut body = Vec::new();
+    cstring(&mut body, tag);
+    message(out, b'C', &body);
+}
+
+// runs a translated statement, appending its rows and command tag
+async fn run(out: &mut Vec<u8>, session: &mut Session, statement: &Statement) -> Result<(), Failure> {
+    let tag = match &statement.query {
+        Query::Insert { .. } | Query::InsertBatch { .. } => "INSERT 0",
+        Query::Update { .. } | Query::UpdateAll { .. } => "UPDATE",
+        Query::Delete { .. } => "DELETE",
+        Query::AddTable { .. } => "CREATE TABLE",
+        Query::DropTable { .. } => "DROP TABLE",
+        Query::Begin => "BEGIN",
+        Query::Commit => "COMMIT",
+        Query::Rollback | Query::RollbackTo { .. } => "ROLLBACK",
+        Query::Savepoint { .. } => "SAVEPOINT",
+        Query::Release { .. } => "RELEASE",
+        _ => "SELECT",
+    };
+
+    match session.execute(statement.query.clone()).await {
+        Reply::Created(res) => {
+            res?;
+            command_complete(out, tag);
+        }
+        Reply::Table(res) => {
+            let table = statement.project(res?)?;
+            rows(out, &table, statement);
+            command_complete(out, &format!("{} {}", tag, table.row_count()));
+        }
+        Reply::Mutation(res) => {
+            let mutation = res?;
+            // only there when the statement asked for the rows back
+            if let Some(returning) = mutation.returning {
+                rows(out, &statement.project(returning)?, statement);
+            }
+            command_complete(out, &format!("{} {}", tag, mutation.matched));
+        }
+        Reply::Status(res) => {
+            res?;
+            match &statement.query {
+                Query::Insert { .. } => command_complete(out, &format!("{} 1", tag)),
+                Query::InsertBatch { batch, .. } => command_complete(out, &format!("{} {}", tag, batch.row_count())),
+                _ => command_complete(out, tag),
+            }
+        }
+        Reply::CancelKey(res) => {
+            res?;
+            command_complete(out, tag);
+        }
+        Reply::Import(res) => {
+            res?;
+            command_complete(out, tag);
+        }
+        Reply::Export(res) => {
+            res?;
+            command_complete(out, tag);
+        }
+        Reply::ArrowImport(res) => {
+            res?;
+            command_complete(out, tag);
+        }
+        Reply::ArrowExport(res) => {
+            res?;
+            command_complete(out, tag);
+        }
+        Reply::None => command_complete(out, tag),
+    }
+
+    Ok(())
+}
+
+async fn read_message(socket: &mut Box<dyn Stream>, max_message_bytes: u32) -> Result<(u8, Vec<u8>), std::io::Error> {
+    let mut tag = [0; 1];
+    socket.read_exact(&mut tag).await?;
+    let body = read_body(socket, max_message_bytes as usize).await?;
+
+    Ok((tag[0], body))
+}
+
+// a length, including the length itself, followed by that many bytes
+async fn read_body(socket: &mut Box<dyn Stream>, max_bytes: usize) -> Result<Vec<u8>, std::io::Error> {
+    let length = socket.read_i32().await?;
+    if length < 4 || length as usize - 4 > max_bytes {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("message of {} bytes", length)));
+    }
+
+    let mut body = vec![0; length as usize - 4];
+    socket.read_exact(&mut body).await?;
+    Ok(body)
+}
+
+// the string up to the first nul byte
+fn read_cstring(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_string()
+}
+
+fn authentication(out: &mut Vec<u8>, code: i32, data: &[u8]) {
+    let mut body = code.to_be_bytes().to_vec();
+    body.extend_from_slice(data);
+    message(out, b'R', &body);
+}
+
+// the value of `name=value` among the comma separated attributes of a scram message
+fn attribute<'a>(message: &'a str, name: &str) -> Option<&'a str> {
+    message.split(',').find_map(|field| field.strip_prefix(name)?.strip_prefix('='))
+}
+
+// checks the password of `user` with a scram-sha-256 exchange, so it never crosses the connection.
+// an exchange the client breaks off or gets wrong fails with invalid data
+async fn scram(socket: &mut Box<dyn Stream>, session: &Session, user: &str) -> Result<Result<(), QueryError>, std::io::Error> {
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+    let mut out = Vec::new();
+
+    let mut mechanisms = Vec::new();
+    cstring(&mut mechanisms, SCRAM_SHA_256);
+    mechanisms.push(0);
+    authentication(&mut out, AUTHENTICATION_SASL, &mechanisms);
+    socket.write_all(&out).await?;
+
+    // the mechanism the client chose, then its first message after the message's length
+    let (tag, body) = read_message(socket, MAX_STARTUP_BYTES as u32).await?;
+    let mechanism = read_cstring(&body);
+    let client_first = match body.get(mechanism.len() + 5..) {
+        Some(client_first) if tag == b'p' && mechanism == SCRAM_SHA_256 => String::from_utf8_lossy(client_first).to_string(),
+        _ => return Err(invalid("expected a SCRAM-SHA-256 initial response")),
+    };
+
+    // the gs2 header in front says whether the client binds the exchange to the channel, which is not offered
+    let mut parts = client_first.splitn(3, ',');
+    let (binding, authzid, client_first_bare) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(binding @ ("n" | "y")), Some(authzid), Some(bare)) => (binding, authzid, bare),
+        _ => return Err(invalid("unsupported channel binding")),
+    };
+    let client_nonce = attribute(client_first_bare, "r").ok_or_else(|| invalid("the initial response has no nonce"))?;
+
+    let keys = session.scram_keys(user);
+    let nonce = format!("{}{}", client_nonce, BASE64.encode(rand::random::<[u8; 18]>()));
+    let server_first = format!("r={},s={},i={}", nonce, BASE64.encode(&keys.salt), keys.rounds);
+
+    out.clear();
+    authentication(&mut out, AUTHENTICATION_SASL_CONTINUE, server_first.as_bytes());
+    socket.write_all(&out).await?;
+
+    let (tag, body) = read_message(socket, MAX_STARTUP_BYTES as u32).await?;
+    let client_final = String::from_utf8_lossy(&body).to_string();
+    let (without_proof, proof) = match client_final.rsplit_once(",p=") {
+        Some(parts) if tag == b'p' => parts,
+        _ => return Err(invalid("expected a SCRAM-SHA-256 response")),
+    };
+
+    let header = BASE64.encode(format!("{},{},", binding, authzid));
+    if attribute(without_proof, "c") != Some(header.as_str()) || attribute(without_proof, "r") != Some(nonce.as_str()) {
+        return Err(invalid("the SCRAM-SHA-256 response does not match the exchange"));
+    }
+    let proof = BASE64.decode(proof).map_err(|_| invalid("the SCRAM-SHA-256 proof is not base64"))?;
+
+    let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+    let signature = match keys.verify(auth_message.as_bytes(), &proof) {
+        Ok(signature) => signature,
+        Err(err) => return Ok(Err(err)),
+    };
+
+    out.clear();
+    authentication(&mut out, AUTHENTICATION_SASL_FINAL, format!("v={}", BASE64.encode(signature)).as_bytes());
+    socket.write_all(&out).await?;
+
+    Ok(Ok(()))
+}
+
+async fn fatal(mut socket: Box<dyn Stream>, code: &'static str, message: &str) -> Result<(), std::io::Error> {
+    let mut out = Vec::new();
+    error_response(&mut out, "FATAL", &Failure { code, message: message.to_string() });
+    socket.write_all(&out).await?;
+    socket.shutdown().await
+}
+
+// speaks the simple query subset of the postgres protocol, version 3. tls is negotiated within the
+// protocol, and required when the server has it on
+pub async fn serve(
+    socket: TcpStream,
+    tls: Option<TlsAcceptor>,
+    mut session: Session,
+    mut shutdown_requested: watch::Receiver<bool>,
+    max_message_bytes: u32
+) -> Result<(), std::io::Error> {
+    let mut socket: Box<dyn Stream> = Box::new(socket);
+    let mut tls = tls;
+    let mut encrypted = false;
+
+    let parameters = loop {
+        let body = read_body(&mut socket, MAX_STARTUP_BYTES).await?;
+        let code = i32::from_be_bytes(body.get(..4).and_then(|code| code.try_into().ok()).unwrap_or_default());
+
+        match code {
+            SSL_REQUEST => match tls.take() {
+                Some(acceptor) => {
+                    socket.write_all(b"S").await?;
+                    socket = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+                        Ok(Ok(socket)) => Box::new(socket),
+                        Ok(Err(err)) => return Err(err),
+                        Err(_) => return Err(std::io::ErrorKind::TimedOut.into()),
+                    };
+                    encrypted = true;
+                }
+                None => socket.write_all(b"N").await?,
+            },
+            GSSENC_REQUEST => socket.write_all(b"N").await?,
+            // cancel keys are not handed out on this protocol, so there is nothing to cancel
+            CANCEL_REQUEST => return Ok(()),
+            PROTOCOL_VERSION => break body[4..].to_vec(),
+            _ => return fatal(socket, "08P01", "unsupported protocol version, only 3.0 is spoken").await,
+        }
+    };
+
+    if tls.is_some() && !encrypted {
+        return fatal(socket, "28000", "the server requires TLS").await;
+    }
+
+    // pairs of nul terminated names and values
+    let mut fields = parameters.split(|byte| *byte == 0).map(|field| String::from_utf8_lossy(field).to_string());
+    let mut user = String::new();
+    while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+        if name == "user" {
+            user = value;
+        }
+    }
+
+    let mut out = Vec::new();
+
+    if session.requires_login() {
+        let res = match scram(&mut socket, &session, &user).await {
+            Ok(res) => res,
+            Err(err) if err.kind() == std::io::ErrorKind::InvalidData => return fatal(socket, "08P01", &err.to_string()).await,
+            Err(err) => return Err(err),
+        };
+
+        if let Err(err) = session.finish_login(user.clone(), res).await {
+            let failure = Failure::from(err);
+            return fatal(socket, failure.code, &format!("password authentication failed for user \"{}\"", user)).await;
+        }
+    }
+
+    authentication(&mut out, AUTHENTICATION_OK, &[]);
+    for (name, value) in [
+        ("server_version", "14.0"),
+        ("server_encoding", "UTF8"),
+        ("client_encoding", "UTF8"),
+        ("DateStyle", "ISO, MDY"),
+        ("integer_datetimes", "on"),
+        ("standard_conforming_strings", "on"),
+    ] {
+        let mut body = Vec::new();
+        cstring(&mut body, name);
+        cstring(&mut body, value);
+        message(&mut out, b'S', &body);
+    }
+    ready_for_query(&mut out, &session);
+    socket.write_all(&out).await?;
+
+    // set after an extended protocol message was refused, until the client syncs
+    let mut refused = false;
+
+    loop {
+        out.clear();
+
+        // a session only stops for a shutdown between queries, so the query in flight finishes
+        let (tag, body) = tokio::select! {
+            message = read_message(&mut socket, max_message_bytes) => match message {
+                Ok(message) => message,
+                Err(err) => {
+                    session.db.logger.error("Connection Error".to_string(), "Connection has been closed".to_string()).await;
+                    session.db.logger_flush().await;
+                    return Err(err);
+                }
+            },
+            _ = shutdown_requested.changed() => {
+                session.db.logger.info("Session Closing".to_string(), "Server is shutting down".to_string()).await;
+                session.db.logger_flush().await;
+                return fatal(socket, "57P01", "terminating connection due to server shutdown").await;
+            }
+        };
+
+        match tag {
+            b'Q' => {
+                match sql::parse(&read_cstring(&body)) {
+                    Ok(statements) if statements.is_empty() => message(&mut out, b'I', &[]),
+                    Ok(statements) => {
+                        // like postgres, the statements after a failed one are skipped
+                        for statement in &statements {
+                            if let Err(failure) = run(&mut out, &mut session, statement).await {
+                                error_response(&mut out, "ERROR", &failure);
+                                break;
+                            }
+                        }
+                    }
+                    Err(err) => error_response(&mut out, "ERROR", &Failure::from(err)),
+                }
+                ready_for_query(&mut out, &session);
+            }
+            b'X' => {
+                session.db.logger.info("Session Exiting".to_string(), "Session has ended".to_string()).await;
+                session.db.logger_flush().await;
+                return Ok(());
+            }
+            b'S' => {
+                refused = false;
+                ready_for_query(&mut out, &session);
+            }
+            b'P' | b'B' | b'D' | b'E' | b'C' | b'H' | b'F' => {
+                if !refused {
+                    refused = true;
+                    error_response(&mut out, "ERROR", &Failure {
+                        code: "0A000",
+                        message: "the extended query protocol is not supported, send simple queries".to_string(),
+                    });
+                }
+                // a function call is not followed by a sync
+                if tag == b'F' {
+                    refused = false;
+                    ready_for_query(&mut out, &session);
+                }
+            }
+            _ => return fatal(socket, "08P01", &format!("unexpected message {:?}", tag as char)).await,
+        }
+
+        socket.write_all(&out).await?;
+    }
+}
@@ -0,0 +1,430 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use serde::{Serialize, Serializer};
+use logger::{Level, Logger};
+use minase::db_core::arrow::{ArrowExport, ArrowImport};
+use minase::db_core::auth::ScramKeys;
+use minase::db_core::csv::{CsvExport, CsvImport};
+use minase::db_core::database::{Database, MutationResult, Table};
+use minase::db_core::query::{CancelKey, Query};
+use minase::db_core::query_error::QueryError;
+use minase::db_core::storage::Store;
+use minase::db_core::values::Expr;
+use crate::auth::Users;
+use crate::cancel::{Cancellers, Registration};
+use crate::metrics::Metrics;
+
+// what every session shares, whichever listener its client came in on
+#[derive(Debug)]
+pub struct Shared {
+    pub store: Arc<Store>,
+    pub users: Users,
+    pub cancellers: Arc<Cancellers>,
+    pub metrics: Arc<Metrics>,
+    pub logger: Logger,
+    pub slow_logger: Logger,
+    pub slow_query_millis: AtomicU64,
+    pub query_timeout: Option<Duration>,
+    // canonical, imports and exports may only name files in it
+    pub file_dir: Option<PathBuf>,
+    next_session: AtomicU64,
+}
+
+// what a query sends back. the protocol the client speaks decides how it is serialized,
+// msgpack sends the result as is
+#[derive(Debug)]
+pub enum Reply {
+    // for exit, the only query the client does not wait on
+    None,
+    // the position of the table an add table created
+    Created(Result<usize, QueryError>),
+    Table(Result<Table, QueryError>),
+    Mutation(Result<MutationResult, QueryError>),
+    Status(Result<(), QueryError>),
+    CancelKey(Result<CancelKey, QueryError>),
+    Import(Result<CsvImport, QueryError>),
+    Export(Result<CsvExport, QueryError>),
+    ArrowImport(Result<ArrowImport, QueryError>),
+    ArrowExport(Result<ArrowExport, QueryError>),
+}
+
+impl Serialize for Reply {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Reply::None => serializer.serialize_unit(),
+            Reply::Created(res) => res.serialize(serializer),
+            Reply::Table(res) => res.serialize(serializer),
+            Reply::Mutation(res) => res.serialize(serializer),
+            Reply::Status(res) => res.serialize(serializer),
+            Reply::CancelKey(res) => res.serialize(serializer),
+            Reply::Import(res) => res.serialize(serializer),
+            Reply::Export(res) => res.serialize(serializer),
+            Reply::ArrowImport(res) => res.serialize(serializer),
+            Reply::ArrowExport(res) => res.serialize(serializer),
+        }
+    }
+}
+
+impl Reply {
+    pub fn error(&self) -> Option<&QueryError> {
+        match self {
+            Reply::None => None,
+            Reply::Created(res) => res.as_ref().err(),
+            Reply::Table(res) => res.as_ref().err(),
+            Reply::Mutation(res) => res.as_ref().err(),
+            Reply::Status(res) => res.as_ref().err(),
+            Reply::CancelKey(res) => res.as_ref().err(),
+            Reply::Import(res) => res.as_ref().err(),
+            Reply::Export(res) => res.as_ref().err(),
+            Reply::ArrowImport(res) => res.as_ref().err(),
+            Reply::ArrowExport(res) => res.as_ref().err(),
+        }
+    }
+}
+
+impl Shared {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        store: Arc<Store>,
+        users: Users,
+        metrics: Arc<Metrics>,
+        logger: Logger,
+        slow_logger: Logger,
+        slow_query_millis: u64,
+        query_timeout: Option<Duration>,
+        file_dir: Option<PathBuf>
+    ) -> Self {
+        Shared {
+            store,
+            users,
+            cancellers: Arc::new(Cancellers::default()),
+            metrics,
+            logger,
+            slow_logger,
+            slow_query_millis: AtomicU64::new(slow_query_millis),
+            query_timeout,
+            file_dir,
+            next_session: AtomicU64::new(0),
+        }
+    }
+}
+
+// a client's view of the database: its transaction, the user it logged in as and its cancel key
+pub struct Session {
+    pub db: Database,
+    shared: Arc<Shared>,
+    slow_logger: Logger,
+    registration: Registration,
+    // the user the session logged in as
+    user: Option<String>,
+    next_query: u64,
+}
+
+impl Session {
+    pub fn new(shared: &Arc<Shared>) -> Self {
+        let id = shared.next_session.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut db = Database::with_store(shared.logger.for_session(id), shared.store.clone());
+        db.set_file_dir(shared.file_dir.clone());
+        let registration = shared.cancellers.register(id, db.canceller());
+
+        Session {
+            db,
+            shared: shared.clone(),
+            slow_logger: shared.slow_logger.for_session(id),
+            registration,
+            user: None,
+            next_query: 0,
+        }
+    }
+
+    // whether queries fail until the session logged in
+    pub fn requires_login(&self) -> bool {
+        self.shared.users.enabled()
+    }
+
+    pub async fn login(&mut self, name: String, password: &str) -> Result<(), QueryError> {
+        let res = self.shared.users.login(&name, password);
+        self.finish_login(name, res).await
+    }
+
+    // what the session needs to check a login over scram, which the protocol the client speaks runs
+    pub fn scram_keys(&self, name: &str) -> ScramKeys {
+        self.shared.users.scram_keys(name)
+    }
+
+    // logs the session in as `name` when its password was found to be right
+    pub async fn finish_login(&mut self, name: String, res: Result<(), QueryError>) -> Result<(), QueryError> {
+        match res {
+            Ok(()) => {
+                self.db.logger.log(
+                    Level::Info,
+                    "Logged In".to_string(),
+                    "Session has been authenticated".to_string(),
+                    vec![("user".to_string(), name.clone())]
+                ).await;
+                self.user = Some(name);
+            }
+            Err(_) => {
+                self.db.logger.log(
+                    Level::Warn,
+                    "Login Failed".to_string(),
+                    "Wrong user or password".to_string(),
+                    vec![("user".to_string(), name)]
+                ).await;
+            }
+        }
+
+        res
+    }
+
+    // runs a query after checking the user may, timing it for the metrics and the slow query log
+    pub async fn execute(&mut self, mut query: Query) -> Reply {
+        let shared = self.shared.clone();
+        let metrics = &shared.metrics;
+
+        self.next_query += 1;
+        self.db.logger.set_query(Some(self.next_query));
+        self.slow_logger.set_query(Some(self.next_query));
+
+        // a query can bring its own deadline in place of the server's
+        let mut timeout = shared.query_timeout;
+        let mut bind_error = None;
+        let mut pinned = Vec::new();
+        loop {
+            match query {
+                Query::WithTimeout { millis, query: inner } => {
+                    timeout = (millis > 0).then(|| Duration::from_millis(millis));
+                    query = *inner;
+                }
+                // a prepared statement is checked and run as the query it was prepared from
+                Query::Execute { name, params } => match self.db.bind(&name, &params).await {
+                    Ok((bound, tables)) => {
+                        query = bound;
+                        pinned = tables;
+                    }
+                    Err(err) => {
+                        bind_error = Some(err);
+                        query = Query::Execute { name, params };
+                        break;
+                    }
+                },
+                _ => break,
+            }
+        }
+
+        metrics.query(query.name());
+
+        let statement = format!("{:?}", query);
+        self.db.logger_info("Query Received".to_string(), statement.clone()).await;
+
+        match shared.users.authorize(self.user.as_deref(), &query, &mut self.db) {
+            Ok(tables) => {
+                pinned.extend(tables);
+                self.db.pin_tables(pinned);
+            }
+            Err(err) => {
+                self.db.logger.log(
+                    Level::Warn,
+                    "Query Denied".to_string(),
+                    err.to_string(),
+                    vec![("user".to_string(), self.user.clone().unwrap_or_default())]
+                ).await;
+                metrics.error(err.name());
+
+                return Reply::Status(Err(err));
+            }
+        }
+
+        let started = Instant::now();
+        self.db.start_query(timeout);
+
+        let db = &mut self.db;
+        let reply = match query {
+            query @ (Query::Select { .. } | Query::SelectTable { .. } | Query::Aggregate { .. } | Query::Join { .. }) => {
+                Reply::Table(read(db, query).await)
+            }
+            Query::Insert { table, values } => {
+                // a parameter is only left when the insert was sent without being prepared
+                Reply::Status(match values.into_iter().map(Expr::into_value).collect() {
+                    Ok(values) => db.insert(table, values).await,
+                    Err(err) => Err(err),
+                })
+            }
+            Query::InsertBatch { table, batch } => {
+                Reply::Status(db.insert_batch(table, batch).await)
+            }
+            Query::Update { table, condition_column, targets, condition, returning } => {
+                Reply::Mutation(db.update(table, condition_column, targets, condition, returning).await)
+            }
+            Query::UpdateAll { table, targets, returning } => {
+                Reply::Mutation(db.update_all(table, targets, returning).await)
+            }
+            Query::Delete { table, column, condition, returning } => {
+                Reply::Mutation(db.delete(table, column, condition, returning).await)
+            }
+            Query::AddTable { columns } => {
+                Reply::Created(db.add_table(columns).await)
+            }
+            Query::DropTable { id } => {
+                Reply::Status(db.drop_table(id).await)
+            }
+            Query::Begin => {
+                Reply::Status(db.begin().await)
+            }
+            Query::Commit => {
+                Reply::Status(db.commit().await)
+            }
+            Query::Rollback => {
+                Reply::Status(db.rollback().await)
+            }
+            Query::Savepoint { name } => {
+                Reply::Status(db.savepoint(name).await)
+            }
+            Query::RollbackTo { name } => {
+                Reply::Status(db.rollback_to(name).await)
+            }
+            Query::Release { name } => {
+                Reply::Status(db.release(name).await)
+            }
+            // closing the connection is up to the listener
+            Query::Exit => {
+                Reply::None
+            }
+            Query::FlushLogs => {
+                db.logger_flush().await;
+                Reply::Status(Ok(()))
+            }
+            Query::SetLogLevel { level } => {
+                db.set_log_level(level).await;
+                Reply::Status(Ok(()))
+            }
+            Query::SetSlowQueryThreshold { millis } => {
+                shared.slow_query_millis.store(millis, Ordering::Relaxed);
+                db.logger_info(
+                    "Slow Query Threshold Set".to_string(),
+                    format!("queries taking {}ms or longer are logged as slow", millis)
+                ).await;
+                Reply::Status(Ok(()))
+            }
+            Query::WithTimeout { .. } => {
+                unreachable!("unwrapped before the query runs");
+            }
+            Query::CancelKey => {
+                Reply::CancelKey(Ok(self.registration.key()))
+            }
+            Query::Cancel { key } => {
+                let description = match shared.cancellers.cancel(key) {
+                    true => "query of the session has been cancelled",
+                    false => "no session with this key",
+                };
+                db.logger.log(
+                    Level::Info,
+                    "Cancel Requested".to_string(),
+                    description.to_string(),
+                    vec![("target_session".to_string(), key.session.to_string())]
+                ).await;
+
+                Reply::Status(Ok(()))
+            }
+            Query::Login { user, password } => {
+                Reply::Status(self.login(user, &password.0).await)
+            }
+            Query::CreateUser { name, password } => {
+                let res = shared.users.change(|catalog| catalog.create_user(&name, &password.0));
+                Reply::Status(saved(res, &mut db.logger).await)
+            }
+            Query::DropUser { name } => {
+                let res = shared.users.change(|catalog| catalog.drop_user(&name));
+                Reply::Status(saved(res, &mut db.logger).await)
+            }
+            Query::SetPassword { name, password } => {
+                let res = shared.users.change(|catalog| catalog.set_password(&name, &password.0));
+                Reply::Status(saved(res, &mut db.logger).await)
+            }
+            Query::Grant { user, privilege, table } => {
+                Reply::Status(match table.map(|table| db.table_oid(table).ok_or(QueryError::TableNotFound)).transpose() {
+                    Ok(oid) => saved(shared.users.change(|catalog| catalog.grant(&user, privilege, oid)), &mut db.logger).await,
+                    Err(err) => Err(err),
+                })
+            }
+            Query::Revoke { user, privilege, table } => {
+                Reply::Status(match table.map(|table| db.table_oid(table).ok_or(QueryError::TableNotFound)).transpose() {
+                    Ok(oid) => saved(shared.users.change(|catalog| catalog.revoke(&user, privilege, oid)), &mut db.logger).await,
+                    Err(err) => Err(err),
+                })
+            }
+            Query::Prepare { name, query } => {
+                Reply::Status(db.prepare(name, *query).await)
+            }
+            // only left when binding failed
+            Query::Execute { .. } => {
+                Reply::Status(Err(bind_error.take().expect("executed statements are bound before they run")))
+            }
+            Query::Deallocate { name } => {
+                Reply::Status(db.deallocate(&name).await)
+            }
+            Query::ImportCsv { source, table, options } => {
+                Reply::Import(db.import_csv(source, table, options).await)
+            }
+            Query::ExportCsv { query, path, options } => {
+                Reply::Export(match read(db, *query).await {
+                    Ok(table) => db.export_csv(&table, path, options).await,
+                    Err(err) => Err(err),
+                })
+            }
+            Query::ImportArrow { source, format, table } => {
+                Reply::ArrowImport(db.import_arrow(source, format, table).await)
+            }
+            Query::ExportArrow { query, path, format } => {
+                Reply::ArrowExport(match read(db, *query).await {
+                    Ok(table) => db.export_arrow(&table, path, format).await,
+                    Err(err) => Err(err),
+                })
+            }
+        };
+
+        if let Some(err) = reply.error() {
+            metrics.error(err.name());
+        }
+
+        let elapsed = started.elapsed();
+        metrics.latency(elapsed);
+        let stats = self.db.take_stats();
+        let fields = vec![
+            ("duration_ms".to_string(), format!("{:.3}", elapsed.as_secs_f64() * 1000.0)),
+            ("rows_scanned".to_string(), stats.rows_scanned.to_string()),
+            ("rows_returned".to_string(), stats.rows_returned.to_string()),
+        ];
+
+        if elapsed.as_millis() >= shared.slow_query_millis.load(Ordering::Relaxed) as u128 {
+            self.slow_logger.log(Level::Warn, "Slow Query".to_string(), statement, fields.clone()).await;
+        }
+        self.db.logger.log(Level::Debug, "Query Finished".to_string(), "Query has been executed".to_string(), fields).await;
+
+        reply
+    }
+}
+
+// the table a query reading rows sends back, an invalid query for every other kind
+async fn read(db: &mut Database, query: Query) -> Result<Table, QueryError> {
+    match query {
+        Query::Select { table, columns, condition, order_by, limit, offset } => {
+            db.select(table, columns, condition, order_by, limit, offset).await
+        }
+        Query::SelectTable { table } => db.select_table(table).await,
+        Query::Aggregate { table, group_by, aggregates, having } => db.aggregate(table, group_by, aggregates, having).await,
+        Query::Join { table, joins, columns } => db.join(table, joins, columns).await,
+        _ => Err(QueryError::InvalidQuery),
+    }
+}
+
+// the result of a change to the users as sent to the client, logging when the users could not be saved
+async fn saved(res: Result<Result<(), std::io::Error>, QueryError>, logger: &mut Logger) -> Result<(), QueryError> {
+    if let Ok(Err(err)) = &res {
+        logger.error("Users Not Saved".to_string(), format!("failed to save users: {}", err)).await;
+    }
+
+    res.map(|_| ())
+}
@@ -0,0 +1,43 @@
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use crate::config::TlsConfig;
+
+fn certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, Box<dyn std::error::Error>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| format!("failed to read certificates from {}: {}", path.display(), err))?;
+
+    if certs.is_empty() {
+        return Err(format!("no certificate in {}", path.display()).into());
+    }
+
+    Ok(certs)
+}
+
+// the acceptor wrapping every client connection, read once on start so bad files stop the server right away
+pub fn acceptor(config: &TlsConfig) -> Result<TlsAcceptor, Box<dyn std::error::Error>> {
+    let key = PrivateKeyDer::from_pem_file(&config.key_path)
+        .map_err(|err| format!("failed to read private key from {}: {}", config.key_path.display(), err))?;
+
+    let builder = match &config.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in certs(path)? {
+                roots.add(cert).map_err(|err| format!("invalid client ca {}: {}", path.display(), err))?;
+            }
+
+            ServerConfig::builder().with_client_cert_verifier(WebPkiClientVerifier::builder(Arc::new(roots)).build()?)
+        }
+        None => ServerConfig::builder().with_no_client_auth(),
+    };
+
+    let server_config = builder.with_single_cert(certs(&config.cert_path)?, key)
+        .map_err(|err| format!("invalid certificate or key: {}", err))?;
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
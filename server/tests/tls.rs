@@ -0,0 +1,146 @@
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::Duration;
+use minase::db_core::query::Query;
+use minase::db_core::query_error::QueryError;
+use minase_driver::{Minase, TlsConfig};
+use rcgen::{BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, Issuer, KeyPair, KeyUsagePurpose};
+
+// a certificate authority that signs the server's and the clients' certificates
+struct Authority {
+    issuer: Issuer<'static, KeyPair>,
+    cert: PathBuf,
+}
+
+impl Authority {
+    fn new(dir: &Path, name: &str) -> Authority {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::DigitalSignature];
+
+        let key = KeyPair::generate().unwrap();
+        let cert = dir.join(format!("{}.crt", name));
+        std::fs::write(&cert, params.self_signed(&key).unwrap().pem()).unwrap();
+
+        Authority { issuer: Issuer::new(params, key), cert }
+    }
+
+    // writes a certificate for `name` and its key, returning their paths
+    fn sign(&self, dir: &Path, name: &str, purpose: ExtendedKeyUsagePurpose) -> (PathBuf, PathBuf) {
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        params.extended_key_usages = vec![purpose];
+
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &self.issuer).unwrap();
+
+        let paths = (dir.join(format!("{}.crt", name)), dir.join(format!("{}.key", name)));
+        std::fs::write(&paths.0, cert.pem()).unwrap();
+        std::fs::write(&paths.1, key.serialize_pem()).unwrap();
+
+        paths
+    }
+}
+
+// a server process, killed when the test is done with it
+struct Server {
+    child: Child,
+    addr: String,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Server {
+    async fn start(dir: &Path, cert: &Path, key: &Path, client_ca: Option<&Path>) -> Server {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let addr = format!("127.0.0.1:{}", port);
+
+        let mut command = Command::new(env!("CARGO_BIN_EXE_server"));
+        command
+            .arg("--listen").arg(&addr)
+            .arg("--metrics-listen").arg("127.0.0.1:0")
+            .arg("--data-dir").arg(dir.join("data"))
+            .arg("--log-path").arg(dir.join("minase.log"))
+            .arg("--slow-query-log-path").arg(dir.join("minase-slow.log"))
+            .arg("--tls")
+            .arg("--tls-cert").arg(cert)
+            .arg("--tls-key").arg(key);
+
+        if let Some(client_ca) = client_ca {
+            command.arg("--tls-client-ca").arg(client_ca);
+        }
+
+        let server = Server { child: command.spawn().unwrap(), addr };
+
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(&server.addr).await.is_ok() {
+                return server;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        panic!("server did not start listening on {}", server.addr);
+    }
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn client_config(ca: &Path, client: Option<&(PathBuf, PathBuf)>) -> TlsConfig {
+    TlsConfig {
+        ca_cert: Some(ca.to_path_buf()),
+        client_cert: client.map(|(cert, _)| cert.clone()),
+        client_key: client.map(|(_, key)| key.clone()),
+        server_name: Some("localhost".to_string()),
+    }
+}
+
+// connects, sends a query and reads its result, the error of whichever step failed otherwise
+async fn round_trip(addr: &str, config: &TlsConfig) -> Result<(), String> {
+    let mut minase = Minase::connect_tls(addr, config).await.map_err(|err| err.to_string())?;
+
+    minase.query(Query::SelectTable { table: 0 }).await.map_err(|err| err.to_string())?;
+    match minase.receive_table().await {
+        Err(QueryError::TableNotFound) => Ok(()),
+        Err(err) => Err(err.to_string()),
+        Ok(_) => Err("a table was found on an empty server".to_string()),
+    }
+}
+
+#[tokio::test]
+async fn tls_round_trip_and_untrusted_ca() {
+    let dir = scratch_dir("tls");
+    let ca = Authority::new(&dir, "ca");
+    let other_ca = Authority::new(&dir, "other-ca");
+    let (cert, key) = ca.sign(&dir, "server", ExtendedKeyUsagePurpose::ServerAuth);
+
+    let server = Server::start(&dir, &cert, &key, None).await;
+
+    round_trip(&server.addr, &client_config(&ca.cert, None)).await.unwrap();
+    assert!(round_trip(&server.addr, &client_config(&other_ca.cert, None)).await.is_err());
+}
+
+#[tokio::test]
+async fn mutual_tls_requires_a_client_certificate() {
+    let dir = scratch_dir("mtls");
+    let ca = Authority::new(&dir, "ca");
+    let client_ca = Authority::new(&dir, "client-ca");
+    let (cert, key) = ca.sign(&dir, "server", ExtendedKeyUsagePurpose::ServerAuth);
+    let client = client_ca.sign(&dir, "client", ExtendedKeyUsagePurpose::ClientAuth);
+    let stranger = ca.sign(&dir, "stranger", ExtendedKeyUsagePurpose::ClientAuth);
+
+    let server = Server::start(&dir, &cert, &key, Some(&client_ca.cert)).await;
+
+    round_trip(&server.addr, &client_config(&ca.cert, Some(&client))).await.unwrap();
+    assert!(round_trip(&server.addr, &client_config(&ca.cert, None)).await.is_err());
+    assert!(round_trip(&server.addr, &client_config(&ca.cert, Some(&stranger))).await.is_err());
+}